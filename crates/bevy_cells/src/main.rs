@@ -6,8 +6,8 @@ fn main() {
     // Bevy plugins
     app.add_plugins(DefaultPlugins);
 
-    // Add our plugin
-    app.add_plugins(cell_engine::CellEnginePlugin);
+    // Add our plugin, with the rules loaded from the assets folder so they can be edited live
+    app.add_plugins(cell_engine::CellEnginePlugin::default().with_rule_set("rules/default.rules"));
 
     app.run();
 }
@@ -0,0 +1,70 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use cell_particle::rule::{parse_rules, ParseError};
+
+use crate::CellRule;
+
+/// Bevy [`Asset`] for a set of rules, loaded from a `.rules` file.
+/// See [`cell_particle::rule::parse_rules`] for the format.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct RuleSet {
+    /// The rules of the set, before expanding their symmetries
+    pub rules: Vec<CellRule>,
+}
+
+/// Bevy [`AssetLoader`] for [`RuleSet`]s
+#[derive(Default)]
+pub struct RuleSetLoader;
+
+/// Error type for loading a [`RuleSet`]
+#[derive(Debug)]
+pub enum RuleSetLoaderError {
+    /// The file could not be read
+    Io(std::io::Error),
+    /// The file is not valid UTF-8
+    Utf8(std::str::Utf8Error),
+    /// The file is not a valid rule set
+    Parse(ParseError),
+}
+
+impl std::fmt::Display for RuleSetLoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleSetLoaderError::Io(error) => write!(f, "Could not read rule set: {}", error),
+            RuleSetLoaderError::Utf8(error) => write!(f, "Rule set is not UTF-8: {}", error),
+            RuleSetLoaderError::Parse(error) => write!(f, "Invalid rule set at {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RuleSetLoaderError {}
+
+impl AssetLoader for RuleSetLoader {
+    type Asset = RuleSet;
+    type Settings = ();
+    type Error = RuleSetLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(RuleSetLoaderError::Io)?;
+        let source = std::str::from_utf8(&bytes).map_err(RuleSetLoaderError::Utf8)?;
+        let rules = parse_rules(source).map_err(RuleSetLoaderError::Parse)?;
+
+        Ok(RuleSet {
+            rules: rules.into_iter().map(CellRule::from).collect(),
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["rules"]
+    }
+}
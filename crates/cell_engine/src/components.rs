@@ -1,303 +1,234 @@
-use std::collections::HashSet;
+use std::borrow::Borrow;
+use std::io::{BufRead, Read, Seek, Write};
 
 use bevy::prelude::*;
-use bevy_catppuccin::*;
 use cell_particle::{
-    grid::Grid,
-    particle::{self, Particle, ParticleKind},
-    rule::{Occupancy, Rule},
+    grid::{BoundaryMode, Dimensions},
+    particle::{MaterialRegistry, ParticleKind},
+    rule::RuleDefinition,
 };
-use rand::{
-    distr::{weighted::WeightedIndex, Distribution},
-    seq::SliceRandom,
-    Rng,
+use cell_simulation::{
+    export_png, import_png, read_snapshot, write_snapshot, ImageError, Palette, ParticleCell,
+    Simulation, SimulationRule, SnapshotError, StepReport, UnknownColors, ATMOSPHERIC_PRESSURE,
 };
-use strum::IntoEnumIterator;
+
+use crate::{History, RuleSet};
 
 /// Bevy [`Component`] for a cellular automaton rule
-#[derive(Component, Debug, Clone)]
-pub struct CellRule {
-    /// The rule to apply
-    pub rule: Rule<Occupancy<ParticleKind>>,
-    /// The priority of the rule, if not set, the rule doesn't care about the order of application, and will be randomly shuffled
-    pub priority: Option<usize>,
-}
+#[derive(Component, Debug, Clone, Deref, DerefMut)]
+pub struct CellRule(pub SimulationRule);
 
-/// Wrapper cell for [`Particle`], which optionally contains a [`Particle`], and can tell you its color
-#[derive(Debug, Clone)]
-pub struct ParticleCell {
-    pub content: Option<Particle>,
+impl From<SimulationRule> for CellRule {
+    fn from(rule: SimulationRule) -> Self {
+        CellRule(rule)
+    }
 }
 
-impl ParticleCell {
-    pub fn color(&self, flavor: &Flavor) -> Color {
-        match &self.content {
-            Some(particle) => match particle.kind {
-                ParticleKind::Sand => flavor.yellow,
-                ParticleKind::Water => flavor.blue,
-                ParticleKind::Stone => flavor.surface2,
-            },
-            None => Color::NONE,
-        }
+impl From<RuleDefinition> for CellRule {
+    fn from(definition: RuleDefinition) -> Self {
+        CellRule(definition.into())
     }
 }
 
-impl Default for ParticleCell {
-    fn default() -> Self {
-        ParticleCell { content: None }
+impl CellRule {
+    /// Expands the rule's symmetry into one [`CellRule`] per variant, all with the same priority
+    pub fn variants(&self) -> impl Iterator<Item = CellRule> + '_ {
+        self.0.variants().map(CellRule)
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct ActiveCells {
-    pub cells: HashSet<(usize, usize)>,
-    pub to_check_next_frame: HashSet<(usize, usize)>,
-    pub affected_this_frame: HashSet<(usize, usize)>,
-}
+/// Bevy marker [`Component`] for [`CellRule`]s spawned from the [`crate::ActiveRuleSet`] or a
+/// [`WorldRuleSet`]
+#[derive(Component, Debug, Clone)]
+pub struct FromRuleSet;
 
-impl ActiveCells {
-    pub fn new() -> Self {
-        Self {
-            cells: HashSet::new(),
-            to_check_next_frame: HashSet::new(),
-            affected_this_frame: HashSet::new(),
-        }
-    }
+/// Bevy [`Component`] tying a [`CellRule`] to the [`CellWorld`] entity it applies to.
+/// A world with rules tied to it, or with a [`WorldRuleSet`], is stepped by those alone, even if
+/// there are none, other worlds are stepped by the rules that aren't tied to any world.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliesTo(pub Entity);
 
-    pub fn mark_active(&mut self, x: usize, y: usize) {
-        self.cells.insert((x, y));
-    }
+/// Bevy [`Component`] for a [`CellWorld`] with its own [`RuleSet`]. Its rules are spawned
+/// [`AppliesTo`] the world, and replaced whenever the asset is (re)loaded.
+#[derive(Component, Debug, Clone)]
+pub struct WorldRuleSet(pub Handle<RuleSet>);
 
-    pub fn mark_for_next_frame(&mut self, x: usize, y: usize) {
-        self.to_check_next_frame.insert((x, y));
-    }
+/// Extension trait for [`ParticleCell`], to tell you its color
+pub trait CellColor {
+    /// The colour of the cell at `(x, y)`, the colour of its material varied a little per cell
+    fn color(&self, materials: &MaterialRegistry, x: usize, y: usize) -> Color;
 
-    pub fn mark_affected(&mut self, x: usize, y: usize) {
-        self.affected_this_frame.insert((x, y));
-    }
+    /// The colour of the pressure on the particle in the cell, grey at the pressure of the air,
+    /// turning blue below it and red above it up to [`PRESSURE_VIEW_RANGE`] away
+    fn pressure_color(&self) -> Color;
+}
 
-    pub fn update(&mut self) {
-        std::mem::swap(&mut self.cells, &mut self.to_check_next_frame);
-        self.to_check_next_frame.clear();
-        self.affected_this_frame.clear();
+/// How far in kilo Pascals from the pressure of the air [`CellColor::pressure_color`] reaches its
+/// deepest blue or red
+pub const PRESSURE_VIEW_RANGE: f32 = 20.0;
+
+impl CellColor for ParticleCell {
+    fn color(&self, materials: &MaterialRegistry, x: usize, y: usize) -> Color {
+        match self
+            .content
+            .as_ref()
+            .and_then(|particle| materials.get(particle.kind))
+        {
+            Some(material) => {
+                let [red, green, blue] = material.color_at(x, y);
+                Color::srgb_u8(red, green, blue)
+            }
+            None => Color::NONE,
+        }
     }
-}
 
-impl Default for ActiveCells {
-    fn default() -> Self {
-        Self::new()
+    fn pressure_color(&self) -> Color {
+        let Some(particle) = &self.content else {
+            return Color::NONE;
+        };
+        let gauge = ((particle.state.pressure - ATMOSPHERIC_PRESSURE) / PRESSURE_VIEW_RANGE)
+            .clamp(-1.0, 1.0);
+        let neutral = Color::srgb_u8(0x6c, 0x70, 0x86);
+        if gauge < 0.0 {
+            neutral.mix(&Color::srgb_u8(0x89, 0xb4, 0xfa), -gauge)
+        } else {
+            neutral.mix(&Color::srgb_u8(0xf3, 0x8b, 0xa8), gauge)
+        }
     }
 }
 
-/// Bevy [`Component`] for the world, which is a [`Grid`] of [`Cell`]s
-#[derive(Component, Debug, Clone)]
-#[require(Transform)]
+/// Bevy [`Component`] for the world, a [`Simulation`] with a physical size.
+/// Derefs to the [`Simulation`], so its grid and active cells can be used directly.
+#[derive(Component, Debug, Clone, Deref, DerefMut)]
+#[require(Transform, History)]
 pub struct CellWorld {
     /// Physical resolution of the world in pixels per cell. Each cell is a square.
     pub resolution: u32,
-    /// The data of the world itself, grid of cells
-    pub grid: Grid<ParticleCell>,
-    /// The cells that are active in the current frame
-    pub active_cells: ActiveCells,
+    /// The simulation of the world itself
+    #[deref]
+    pub simulation: Simulation,
 }
 
 impl CellWorld {
+    /// Creates an empty world with a random seed, see [`Simulation::seed`] to reproduce it
     pub fn new(width: usize, height: usize) -> Self {
-        let grid = Grid::new(vec![vec![ParticleCell::default(); width]; height]).unwrap();
         CellWorld {
             resolution: 10,
-            grid,
-            active_cells: ActiveCells::new(),
+            simulation: Simulation::new(width, height),
         }
     }
 
+    /// The size of the world in world units, before its [`Transform`] is applied
+    pub fn size(&self) -> Vec2 {
+        let Dimensions { width, height } = self.grid.dimensions();
+        Vec2::new(width as f32, height as f32) * self.resolution as f32
+    }
+
+    /// Whether the cell lies inside the grid
+    pub fn contains(&self, (x, y): (isize, isize)) -> bool {
+        let Dimensions { width, height } = self.grid.dimensions();
+        (0..width as isize).contains(&x) && (0..height as isize).contains(&y)
+    }
+
+    /// The cell under a position in world space, which may lie outside the grid.
+    /// `transform` is the [`GlobalTransform`] of the world's entity, the grid is centred on it.
+    pub fn cell_at(&self, transform: &GlobalTransform, position: Vec2) -> (isize, isize) {
+        let Dimensions { width, height } = self.grid.dimensions();
+        let local = transform
+            .affine()
+            .inverse()
+            .transform_point3(position.extend(0.0))
+            .truncate();
+        let mut grid_position = local / self.resolution as f32 * Vec2::new(1.0, -1.0);
+        grid_position.x += width as f32 / 2.0;
+        grid_position.y += height as f32 / 2.0;
+        (
+            grid_position.x.floor() as isize,
+            grid_position.y.floor() as isize,
+        )
+    }
+
+    /// The centre of a cell in world space, the inverse of [`CellWorld::cell_at`]
+    pub fn cell_center(&self, transform: &GlobalTransform, x: isize, y: isize) -> Vec2 {
+        let Dimensions { width, height } = self.grid.dimensions();
+        let local = Vec2::new(
+            x as f32 - width as f32 / 2.0 + 0.5,
+            -(y as f32 - height as f32 / 2.0 + 0.5),
+        ) * self.resolution as f32;
+        transform.transform_point(local.extend(0.0)).truncate()
+    }
+
     pub fn with_resolution(mut self, resolution: u32) -> Self {
         self.resolution = resolution;
         self
     }
 
+    /// See [`Simulation::with_seed`]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.simulation = self.simulation.with_seed(seed);
+        self
+    }
+
+    /// See [`Simulation::with_boundary`]
+    pub fn with_boundary(mut self, boundary: BoundaryMode<ParticleKind>) -> Self {
+        self.simulation = self.simulation.with_boundary(boundary);
+        self
+    }
+
+    /// See [`Simulation::with_fill`]
     pub fn with_fill(mut self, particle_kind: ParticleKind) -> Self {
-        for cell in self.grid.iter_mut() {
-            cell.content = Some(particle::Particle::new(particle_kind.clone()));
-        }
+        self.simulation = self.simulation.with_fill(particle_kind);
         self
     }
 
+    /// See [`Simulation::with_random_particles`]
     pub fn with_random_particles(mut self) -> Self {
-        for cell in self.grid.iter_mut() {
-            let particle_kinds = ParticleKind::iter().collect::<Vec<_>>();
-            let random_index = rand::rng().random_range(0..particle_kinds.len());
-            let particle_kind = particle_kinds[random_index].clone();
-            cell.content = Some(particle::Particle::new(particle_kind));
-        }
+        self.simulation = self.simulation.with_random_particles();
         self
     }
 
-    pub fn update(&mut self, rules: &Vec<CellRule>) {
-        let mut new_grid = self.grid.clone();
-        let cells_to_check: Vec<_> = self.active_cells.cells.iter().cloned().collect();
-        let mut next_active_cells = std::mem::take(&mut self.active_cells);
-
-        // Separate rules into prioritized and unprioritized
-        let (prioritized, unprioritized): (Vec<_>, Vec<_>) =
-            rules.iter().partition(|rule| rule.priority.is_some());
-
-        // Sort prioritized rules by priority
-        let mut ordered_rules: Vec<_> = prioritized.clone();
-        ordered_rules.sort_by_key(|rule| rule.priority.unwrap());
-
-        // Group and shuffle rules with same priority
-        let mut prioritised_rules = Vec::with_capacity(rules.len());
-        let mut current_group = Vec::new();
-        let mut current_priority = None;
-
-        for rule in ordered_rules {
-            match current_priority {
-                None => {
-                    current_priority = Some(rule.priority.unwrap());
-                    current_group.push(rule);
-                }
-                Some(prev) if prev != rule.priority.unwrap() => {
-                    if !current_group.is_empty() {
-                        current_group.shuffle(&mut rand::rng());
-                        prioritised_rules.extend(current_group.drain(..));
-                    }
-                    current_priority = Some(rule.priority.unwrap());
-                    current_group.push(rule);
-                }
-                _ => current_group.push(rule),
-            }
-        }
-        // Handle the last group
-        if !current_group.is_empty() {
-            current_group.shuffle(&mut rand::rng());
-            prioritised_rules.extend(current_group);
-        }
+    /// Writes a snapshot of the world, see [`cell_simulation::write_snapshot`] for the format
+    pub fn save(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        write_snapshot(writer, &self.simulation, self.resolution)
+    }
 
-        // Randomly insert unprioritized rules
-        for rule in unprioritized {
-            let insert_pos = rand::rng().random_range(0..=prioritised_rules.len());
-            prioritised_rules.insert(insert_pos, rule);
-        }
+    /// Reads a world from a snapshot written by [`CellWorld::save`]
+    pub fn load(reader: &mut impl Read) -> Result<Self, SnapshotError> {
+        let (simulation, resolution) = read_snapshot(reader)?;
+        Ok(CellWorld {
+            resolution,
+            simulation,
+        })
+    }
 
-        // Process rules and track which cells were affected
-        'cell_loop: for &(x, y) in &cells_to_check {
-            // Skip if this cell has already been affected by a rule this frame
-            if next_active_cells.affected_this_frame.contains(&(x, y)) {
-                continue;
-            }
+    /// Writes the grid of the world as a PNG image, see [`cell_simulation::export_png`]
+    pub fn export_png(&self, writer: impl Write, palette: &Palette) -> Result<(), ImageError> {
+        export_png(writer, &self.grid, palette)
+    }
 
-            for rule in prioritised_rules
-                .iter()
-                .map(|r| r.rule.clone())
-                .collect::<Vec<_>>()
-            {
-                let rule_dims = rule.dimensions();
-
-                // Center the rule window on the particle
-                let rule_x = x.saturating_sub(rule_dims.width / 2);
-                let rule_y = y.saturating_sub(rule_dims.height / 2);
-
-                if rule_x + rule_dims.width > self.grid.dimensions().width
-                    || rule_y + rule_dims.height > self.grid.dimensions().height
-                {
-                    continue;
-                }
-
-                if let Ok(window) =
-                    self.grid
-                        .get_subgrid(rule_x, rule_y, rule_dims.width, rule_dims.height)
-                {
-                    let particle_kind_window: Grid<Occupancy<ParticleKind>> = Grid::new(
-                        window
-                            .cells
-                            .iter()
-                            .map(|row| {
-                                row.iter()
-                                    .map(|cell| {
-                                        match cell.content.as_ref().map(|p| p.kind.clone()) {
-                                            Some(kind) => Occupancy::OccupiedBy(kind),
-                                            None => Occupancy::Vacant,
-                                        }
-                                    })
-                                    .collect()
-                            })
-                            .collect(),
-                    )
-                    .unwrap();
-
-                    if rule.matches(&particle_kind_window) {
-                        let chosen_output = self.choose_rule_output(&rule, &window);
-                        new_grid.set_subgrid(rule_x, rule_y, chosen_output).unwrap();
-
-                        // Mark all cells in the rule window as affected
-                        for dy in 0..rule_dims.height {
-                            for dx in 0..rule_dims.width {
-                                next_active_cells.mark_affected(rule_x + dx, rule_y + dy);
-                            }
-                        }
-
-                        // Mark cells for next frame's active set
-                        for dy in
-                            y.saturating_sub(1)..=(y + 1).min(self.grid.dimensions().height - 1)
-                        {
-                            for dx in
-                                x.saturating_sub(1)..=(x + 1).min(self.grid.dimensions().width - 1)
-                            {
-                                next_active_cells.mark_for_next_frame(dx, dy);
-
-                                if dy + 1 < self.grid.dimensions().height {
-                                    next_active_cells.mark_for_next_frame(dx, dy + 1);
-                                }
-                            }
-                        }
-
-                        continue 'cell_loop; // Skip remaining rules for this cell
-                    }
-                }
-            }
-        }
+    /// Replaces the grid of the world with a PNG image, see [`cell_simulation::import_png`].
+    /// The seed and boundary of the world are kept, every cell of the new grid starts out active.
+    pub fn import_png(
+        &mut self,
+        reader: impl BufRead + Seek,
+        palette: &Palette,
+        unknown_colors: UnknownColors,
+    ) -> Result<(), ImageError> {
+        let grid = import_png(reader, palette, unknown_colors)?;
+        self.simulation = Simulation::from_grid(grid)
+            .with_seed(self.seed())
+            .with_boundary(self.boundary);
+        Ok(())
+    }
 
-        self.grid = new_grid;
-        self.active_cells = next_active_cells;
-        self.active_cells.update();
+    /// Steps the simulation of the world once with the given rules, owned or borrowed
+    pub fn update<R: Borrow<SimulationRule>>(&mut self, rules: &[R]) -> StepReport {
+        self.simulation.step(rules)
     }
 
-    fn choose_rule_output(
-        &self,
-        rule: &Rule<Occupancy<ParticleKind>>,
-        current_grid_window: &Grid<ParticleCell>,
-    ) -> Grid<ParticleCell> {
-        let weighted_index =
-            WeightedIndex::new(rule.output.iter().map(|o| o.probability.value())).unwrap();
-        let chosen_output = rule.output[weighted_index.sample(&mut rand::rng())].clone();
-
-        // Convert to ParticleCell grid
-        Grid::new(
-            chosen_output
-                .grid
-                .cells
-                .iter()
-                .enumerate()
-                .map(|(y, row)| {
-                    row.iter()
-                        .enumerate()
-                        .map(|(x, cell)| ParticleCell {
-                            content: match cell {
-                                Occupancy::OccupiedBy(kind) => {
-                                    Some(particle::Particle::new(kind.clone()))
-                                }
-                                Occupancy::Unknown | Occupancy::OccupiedByAny => {
-                                    current_grid_window.get(x, y).unwrap().content.clone()
-                                }
-                                _ => None,
-                            },
-                        })
-                        .collect()
-                })
-                .collect(),
-        )
-        .unwrap()
+    /// Steps the simulation of the world once with the given rules, on all threads
+    pub fn update_parallel<R: Borrow<SimulationRule>>(&mut self, rules: &[R]) -> StepReport {
+        self.simulation.step_parallel(rules)
     }
 }
 
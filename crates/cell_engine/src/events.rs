@@ -1,4 +1,43 @@
+use std::path::PathBuf;
+
 use bevy::prelude::*;
 
 #[derive(Event)]
 pub struct ToggleDebugMenu;
+
+/// Bevy [`Event`] to save the [`crate::CellWorld`] as a snapshot to the given path
+#[derive(Event, Debug, Clone)]
+pub struct SaveWorld(pub PathBuf);
+
+/// Bevy [`Event`] to replace the [`crate::CellWorld`] with the snapshot at the given path
+#[derive(Event, Debug, Clone)]
+pub struct LoadWorld(pub PathBuf);
+
+/// Bevy [`Event`] to export the [`crate::CellWorld`] as a PNG image to the given path, using the
+/// [`crate::ImagePalette`]
+#[derive(Event, Debug, Clone)]
+pub struct ExportImage(pub PathBuf);
+
+/// Bevy [`Event`] to replace the grid of the [`crate::CellWorld`] with the PNG image at the given
+/// path, using the [`crate::ImagePalette`]
+#[derive(Event, Debug, Clone)]
+pub struct ImportImage(pub PathBuf);
+
+/// Bevy [`Event`] to control the simulation, see [`crate::SimulationControl`]
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub enum SimulationCommand {
+    /// Pause the simulation
+    Pause,
+    /// Resume the simulation
+    Resume,
+    /// Pause the simulation if it is running, otherwise resume it
+    TogglePause,
+    /// Advance the paused simulation by this many ticks
+    Step(usize),
+    /// Set the number of fixed updates per second
+    SetTickRate(f64),
+    /// Set the number of ticks simulated per fixed update
+    SetTicksPerUpdate(usize),
+    /// Step the worlds on all threads, see [`cell_simulation::Simulation::step_parallel`]
+    SetParallel(bool),
+}
@@ -0,0 +1,321 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy::prelude::*;
+use cell_simulation::{ParticleCell, SnapshotError};
+
+use crate::CellWorld;
+
+/// A change to a single cell of the world
+#[derive(Debug, Clone)]
+pub struct CellEdit {
+    pub before: ParticleCell,
+    pub after: ParticleCell,
+}
+
+/// All the cells changed by one brush stroke, by position.
+/// Painting the same cell twice keeps what it was before the first time.
+#[derive(Debug, Clone, Default)]
+pub struct Stroke {
+    pub edits: BTreeMap<(usize, usize), CellEdit>,
+}
+
+impl Stroke {
+    /// Records a change to the cell at `(x, y)`
+    pub fn record(&mut self, x: usize, y: usize, before: ParticleCell, after: ParticleCell) {
+        self.edits
+            .entry((x, y))
+            .and_modify(|edit| edit.after = after.clone())
+            .or_insert(CellEdit { before, after });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Writes either the cells before or after the stroke into the world
+    fn apply(&self, cell_world: &mut CellWorld, undo: bool) {
+        for (&(x, y), edit) in &self.edits {
+            let cell = if undo { &edit.before } else { &edit.after };
+            // The world may have been replaced by a smaller one since, skip what doesn't fit
+            let _ = cell_world.set_cell(x, y, cell.clone());
+        }
+    }
+}
+
+/// Bevy [`Component`] for undoing and redoing edits of a world, and rewinding its simulation to
+/// earlier checkpoints. Every [`CellWorld`] gets one, spawn the world with one to configure it.
+#[derive(Component, Debug, Clone)]
+pub struct History {
+    /// Strokes that can be undone, the most recent last
+    undo: Vec<Stroke>,
+    /// Strokes that were undone and can be redone, the most recently undone last
+    redo: Vec<Stroke>,
+    /// The stroke being painted right now
+    current: Stroke,
+    /// Snapshots of the world, the oldest first, see [`CellWorld::save`]
+    checkpoints: VecDeque<Vec<u8>>,
+    /// The size of all checkpoints together in bytes
+    checkpoint_bytes: usize,
+    /// Ticks since the last checkpoint was taken
+    ticks_since_checkpoint: usize,
+    /// The maximum number of strokes that can be undone
+    pub max_strokes: usize,
+    /// Number of ticks between checkpoints
+    pub checkpoint_interval: usize,
+    /// Maximum size of all checkpoints together in bytes, the oldest are dropped first
+    pub checkpoint_budget: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            current: Stroke::default(),
+            checkpoints: VecDeque::new(),
+            checkpoint_bytes: 0,
+            ticks_since_checkpoint: 0,
+            max_strokes: 100,
+            checkpoint_interval: 100,
+            checkpoint_budget: 16 * 1024 * 1024,
+        }
+    }
+
+    pub fn with_max_strokes(mut self, max_strokes: usize) -> Self {
+        self.max_strokes = max_strokes;
+        self
+    }
+
+    /// Takes a checkpoint every `interval` ticks, keeping as many as fit in `budget` bytes
+    pub fn with_checkpoints(mut self, interval: usize, budget: usize) -> Self {
+        self.checkpoint_interval = interval;
+        self.checkpoint_budget = budget;
+        self
+    }
+
+    /// Records a change to a cell as part of the current stroke
+    pub fn record(&mut self, x: usize, y: usize, before: ParticleCell, after: ParticleCell) {
+        self.current.record(x, y, before, after);
+    }
+
+    /// Finishes the current stroke, making it the next one to undo
+    pub fn end_stroke(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        self.undo.push(std::mem::take(&mut self.current));
+        self.redo.clear();
+        if self.undo.len() > self.max_strokes {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Undoes the most recent stroke, returns whether there was one
+    pub fn undo(&mut self, cell_world: &mut CellWorld) -> bool {
+        self.end_stroke();
+        let Some(stroke) = self.undo.pop() else {
+            return false;
+        };
+        stroke.apply(cell_world, true);
+        self.redo.push(stroke);
+        true
+    }
+
+    /// Redoes the most recently undone stroke, returns whether there was one
+    pub fn redo(&mut self, cell_world: &mut CellWorld) -> bool {
+        let Some(stroke) = self.redo.pop() else {
+            return false;
+        };
+        stroke.apply(cell_world, false);
+        self.undo.push(stroke);
+        true
+    }
+
+    /// Forgets every stroke, to be called whenever the world is replaced, as the strokes would
+    /// be undone or redone onto a world they were never painted on
+    pub fn clear_strokes(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.current = Stroke::default();
+    }
+
+    /// Counts a tick of the simulation, taking a checkpoint every
+    /// [`History::checkpoint_interval`] ticks
+    pub fn tick(&mut self, cell_world: &CellWorld) {
+        self.ticks_since_checkpoint += 1;
+        if self.ticks_since_checkpoint >= self.checkpoint_interval {
+            self.checkpoint(cell_world);
+        }
+    }
+
+    /// Takes a checkpoint of the world, dropping the oldest ones that no longer fit the budget
+    pub fn checkpoint(&mut self, cell_world: &CellWorld) {
+        self.ticks_since_checkpoint = 0;
+        let mut bytes = Vec::new();
+        cell_world
+            .save(&mut bytes)
+            .expect("Writing to memory doesn't fail");
+        self.checkpoint_bytes += bytes.len();
+        self.checkpoints.push_back(bytes);
+
+        while self.checkpoint_bytes > self.checkpoint_budget {
+            let Some(oldest) = self.checkpoints.pop_front() else {
+                break;
+            };
+            self.checkpoint_bytes -= oldest.len();
+        }
+    }
+
+    /// The number of checkpoints that can be rewound to
+    pub fn checkpoints(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Rewinds the world to the most recent checkpoint, and drops it, so rewinding again goes
+    /// further back. Returns whether there was a checkpoint. All strokes are forgotten.
+    pub fn rewind(&mut self, cell_world: &mut CellWorld) -> Result<bool, SnapshotError> {
+        let Some(bytes) = self.checkpoints.pop_back() else {
+            return Ok(false);
+        };
+        self.checkpoint_bytes -= bytes.len();
+        self.ticks_since_checkpoint = 0;
+        *cell_world = CellWorld::load(&mut bytes.as_slice())?;
+        self.clear_strokes();
+        Ok(true)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cell_particle::particle::{Particle, ParticleKind};
+
+    use super::*;
+
+    fn cell(kind: ParticleKind) -> ParticleCell {
+        ParticleCell {
+            content: Some(Particle::new(kind)),
+        }
+    }
+
+    /// Paints the cell at `(x, y)` as part of the current stroke of the history
+    fn paint(cell_world: &mut CellWorld, history: &mut History, x: usize, y: usize) {
+        let before = cell_world.grid.get(x, y).unwrap().clone();
+        let after = cell(ParticleKind::SAND);
+        cell_world.set_cell(x, y, after.clone()).unwrap();
+        history.record(x, y, before, after);
+    }
+
+    fn is_painted(cell_world: &CellWorld, x: usize, y: usize) -> bool {
+        cell_world.grid.get(x, y).unwrap().content.is_some()
+    }
+
+    #[test]
+    fn test_stroke_keeps_the_first_before_and_the_last_after() {
+        let mut stroke = Stroke::default();
+        stroke.record(1, 2, ParticleCell::default(), cell(ParticleKind::SAND));
+        stroke.record(1, 2, cell(ParticleKind::SAND), cell(ParticleKind::WATER));
+        stroke.record(0, 0, ParticleCell::default(), cell(ParticleKind::STONE));
+
+        assert_eq!(stroke.edits.len(), 2);
+        let edit = &stroke.edits[&(1, 2)];
+        assert_eq!(edit.before, ParticleCell::default());
+        assert_eq!(
+            edit.after.occupancy(),
+            cell(ParticleKind::WATER).occupancy()
+        );
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut cell_world = CellWorld::new(4, 4);
+        let mut history = History::new();
+        paint(&mut cell_world, &mut history, 0, 0);
+        paint(&mut cell_world, &mut history, 1, 0);
+        history.end_stroke();
+        paint(&mut cell_world, &mut history, 2, 0);
+
+        // Undoing finishes the stroke being painted first
+        assert!(history.undo(&mut cell_world));
+        assert!(!is_painted(&cell_world, 2, 0));
+        assert!(is_painted(&cell_world, 0, 0));
+        assert!(history.undo(&mut cell_world));
+        assert!(!is_painted(&cell_world, 0, 0) && !is_painted(&cell_world, 1, 0));
+        assert!(!history.undo(&mut cell_world));
+
+        assert!(history.redo(&mut cell_world));
+        assert!(is_painted(&cell_world, 0, 0) && is_painted(&cell_world, 1, 0));
+        assert!(!is_painted(&cell_world, 2, 0));
+
+        // A new stroke can't be followed by what was undone before it
+        paint(&mut cell_world, &mut history, 3, 3);
+        history.end_stroke();
+        assert!(!history.redo(&mut cell_world));
+    }
+
+    #[test]
+    fn test_max_strokes_drops_the_oldest() {
+        let mut cell_world = CellWorld::new(4, 1);
+        let mut history = History::new().with_max_strokes(2);
+        for x in 0..3 {
+            paint(&mut cell_world, &mut history, x, 0);
+            history.end_stroke();
+        }
+
+        assert!(history.undo(&mut cell_world));
+        assert!(history.undo(&mut cell_world));
+        assert!(!history.undo(&mut cell_world));
+        assert!(is_painted(&cell_world, 0, 0));
+        assert!(!is_painted(&cell_world, 1, 0) && !is_painted(&cell_world, 2, 0));
+    }
+
+    #[test]
+    fn test_checkpoint_budget_drops_the_oldest() {
+        let kind_at_origin =
+            |cell_world: &CellWorld| cell_world.grid.get(0, 0).unwrap().occupancy();
+        let kinds = [ParticleKind::SAND, ParticleKind::WATER, ParticleKind::STONE];
+        // Worlds that only differ in the kind of one particle take up the same space
+        let mut cell_world = CellWorld::new(4, 4);
+        cell_world.set_cell(0, 0, cell(kinds[0])).unwrap();
+        let mut snapshot = Vec::new();
+        cell_world.save(&mut snapshot).unwrap();
+        let mut history = History::new().with_checkpoints(2, snapshot.len() * 2);
+
+        for kind in kinds {
+            cell_world.set_cell(0, 0, cell(kind)).unwrap();
+            history.tick(&cell_world);
+            history.tick(&cell_world);
+        }
+        assert_eq!(history.checkpoints(), 2);
+
+        assert!(history.rewind(&mut cell_world).unwrap());
+        assert_eq!(kind_at_origin(&cell_world), cell(kinds[2]).occupancy());
+        assert!(history.rewind(&mut cell_world).unwrap());
+        assert_eq!(kind_at_origin(&cell_world), cell(kinds[1]).occupancy());
+        // The first checkpoint didn't fit
+        assert!(!history.rewind(&mut cell_world).unwrap());
+    }
+
+    #[test]
+    fn test_rewind_forgets_strokes() {
+        let mut cell_world = CellWorld::new(4, 4);
+        let mut history = History::new();
+        history.checkpoint(&cell_world);
+        paint(&mut cell_world, &mut history, 0, 0);
+        history.end_stroke();
+        paint(&mut cell_world, &mut history, 1, 0);
+        history.end_stroke();
+        assert!(history.undo(&mut cell_world));
+        paint(&mut cell_world, &mut history, 2, 0);
+
+        assert!(history.rewind(&mut cell_world).unwrap());
+        assert!(!history.undo(&mut cell_world));
+        assert!(!history.redo(&mut cell_world));
+        assert!((0..3).all(|x| !is_painted(&cell_world, x, 0)));
+    }
+}
@@ -1,11 +1,17 @@
+mod assets;
 mod components;
 mod events;
+mod history;
 mod plugins;
 mod resources;
+mod states;
 mod systems;
 
+pub use assets::*;
 pub use components::*;
 pub use events::*;
+pub use history::*;
 pub use plugins::*;
 pub use resources::*;
+pub use states::*;
 pub use systems::*;
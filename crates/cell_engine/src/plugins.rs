@@ -2,12 +2,29 @@ use bevy::prelude::*;
 use bevy_catppuccin::{CatppuccinTheme, Flavor};
 use bevy_pointer_to_world::PointerToWorldPlugin;
 
-use crate::{systems::*, Tool};
+use crate::{
+    systems::*, ExportImage, FocusedWorld, ImagePalette, ImportImage, LoadWorld, PaintBrush,
+    PaintStroke, RuleSet, RuleSetLoader, RuleSetPath, SaveWorld, SimulationCommand,
+    SimulationControl, SimulationState, SnapshotPath, Tool, ToolShape, ViewMode,
+};
 
 #[cfg(feature = "debug")]
 use crate::{DebugMenuState, Stats, ToggleDebugMenu};
 
-pub struct CellEnginePlugin;
+#[derive(Default)]
+pub struct CellEnginePlugin {
+    /// Path of a [`RuleSet`] asset to load the rules from. The rules are reloaded whenever the
+    /// asset changes. If not set, the built-in [`DEFAULT_RULES`] are used.
+    pub rule_set: Option<String>,
+}
+
+impl CellEnginePlugin {
+    /// Load the rules from the [`RuleSet`] asset at `path`, instead of the built-in rules
+    pub fn with_rule_set(mut self, path: impl Into<String>) -> Self {
+        self.rule_set = Some(path.into());
+        self
+    }
+}
 
 impl Plugin for CellEnginePlugin {
     fn build(&self, app: &mut App) {
@@ -18,25 +35,86 @@ impl Plugin for CellEnginePlugin {
         app.insert_resource(theme);
         app.insert_resource(ClearColor(theme.flavor.base));
 
-        // Set up fixed update
-        app.insert_resource(Time::<Fixed>::from_hz(100.0));
+        // Set up fixed update, at the tick rate of the simulation control if one was inserted
+        app.init_resource::<SimulationControl>();
+        let tick_rate = app.world().resource::<SimulationControl>().tick_rate;
+        app.insert_resource(Time::<Fixed>::from_hz(tick_rate));
+        app.init_state::<SimulationState>();
+        app.add_event::<SimulationCommand>();
+        app.add_systems(Update, (simulation_keys, apply_simulation_commands).chain());
 
         app.init_resource::<Tool>();
+        app.init_resource::<ToolShape>();
+        app.init_resource::<PaintBrush>();
+        app.init_resource::<PaintStroke>();
 
         // Insert plugins
         app.add_plugins(PointerToWorldPlugin);
 
+        // Set up the rules, either from a rule set asset or the built-in ones
+        app.init_asset::<RuleSet>();
+        app.init_asset_loader::<RuleSetLoader>();
+        match &self.rule_set {
+            Some(path) => {
+                app.insert_resource(RuleSetPath(path.clone()));
+                app.add_systems(Startup, load_rule_set);
+            }
+            None => {
+                app.add_systems(Startup, setup_rules);
+            }
+        }
+        // Worlds can bring their own rule set, whether or not there's a shared one
+        app.add_systems(Update, apply_rule_set);
+
         // Set up the systems
         app.add_systems(
             Startup,
+            ((setup_environment, setup_view).chain(), setup_tool_text),
+        );
+        app.add_systems(FixedUpdate, grid_update);
+        app.init_resource::<ViewMode>();
+        app.add_systems(
+            Update,
             (
-                (setup_environment, setup_view).chain(),
-                setup_rules,
-                setup_tool_text,
+                (view_keys, view_update).chain(),
+                (tool_switch, brush_input, draw_tool_preview),
+                (camera_pan, camera_zoom, camera_fit),
+                update_tool_text,
             ),
         );
-        app.add_systems(FixedUpdate, (grid_update, mouse_input));
-        app.add_systems(Update, (view_update, tool_switch, update_tool_text));
+
+        // Painting runs every frame, so no press or release of the mouse is missed. Painting, undo
+        // and the snapshot keys act on the world under the pointer.
+        app.init_resource::<FocusedWorld>();
+        app.add_systems(
+            Update,
+            (
+                focus_world,
+                (
+                    (mouse_input, end_stroke).chain(),
+                    history_keys.before(setup_view),
+                ),
+            )
+                .chain(),
+        );
+
+        // Saving and loading snapshots and images of the world, a loaded world gets a new view
+        app.add_event::<SaveWorld>();
+        app.add_event::<LoadWorld>();
+        app.add_event::<ExportImage>();
+        app.add_event::<ImportImage>();
+        app.init_resource::<SnapshotPath>();
+        app.init_resource::<ImagePalette>();
+        app.add_systems(
+            Update,
+            (
+                snapshot_keys,
+                (save_world, export_image),
+                (load_world, import_image),
+                setup_view,
+            )
+                .chain(),
+        );
 
         #[cfg(feature = "debug")]
         {
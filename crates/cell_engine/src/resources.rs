@@ -1,5 +1,11 @@
+use std::path::PathBuf;
+
 use bevy::prelude::*;
-use cell_particle::particle::ParticleKind;
+use cell_particle::particle::{Particle, ParticleKind};
+
+use cell_simulation::{Brush, Palette, ParticleCell, UnknownColors, ABSOLUTE_ZERO};
+
+use crate::{RuleSet, SimulationState};
 
 /// Bevy [`Resource`] to keep track of the stats of the world
 #[derive(Resource, Debug, Clone)]
@@ -48,24 +54,300 @@ impl DebugMenuState {
 /// Bevy [`Resource`] to keep track of which tool is currently selected
 #[derive(Resource, Debug, Clone)]
 pub enum Tool {
-    /// The tool to select the content of a cell
-    Despawn,
-    /// The tool to spawn a particle
-    Spawn(ParticleKind),
+    /// The tool to erase the content of a cell, of any kind or only of the chosen kinds
+    Despawn(EraseFilter),
+    /// The tool to spawn a particle, in the given [`PaintMode`]
+    Spawn(ParticleKind, PaintMode),
+    /// The tool to warm particles up by the given degrees Celsius every time it's applied, or cool
+    /// them down if negative. Held in place it acts as a source or sink of heat.
+    Heat(f32),
 }
 
 impl Default for Tool {
     fn default() -> Self {
-        Self::Spawn(ParticleKind::Sand)
+        Self::Spawn(ParticleKind::SAND, PaintMode::default())
+    }
+}
+
+impl Tool {
+    /// What the tool turns a cell into, or [`None`] if the tool leaves the cell alone
+    pub fn apply(&self, cell: &ParticleCell) -> Option<ParticleCell> {
+        let kind = cell.content.as_ref().map(|particle| particle.kind);
+        match self {
+            Tool::Despawn(filter) => match (filter, kind) {
+                (_, None) => None,
+                (EraseFilter::All, Some(_)) => Some(ParticleCell { content: None }),
+                (EraseFilter::Only(kinds), Some(kind)) => kinds
+                    .contains(&kind)
+                    .then_some(ParticleCell { content: None }),
+            },
+            Tool::Spawn(particle_kind, mode) => {
+                let paints = match mode {
+                    PaintMode::Overwrite => true,
+                    PaintMode::OnlyEmpty => kind.is_none(),
+                    PaintMode::Replace(replaced) => kind == Some(*replaced),
+                };
+                paints.then(|| ParticleCell {
+                    content: Some(Particle::new(*particle_kind)),
+                })
+            }
+            Tool::Heat(degrees) => {
+                let mut particle = cell.content.clone()?;
+                particle.state.temperature =
+                    (particle.state.temperature + degrees).max(ABSOLUTE_ZERO);
+                Some(ParticleCell {
+                    content: Some(particle),
+                })
+            }
+        }
     }
 }
 
 impl std::fmt::Display for Tool {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-        // match self {
-        //     Tool::Despawn => write!(f, "Despawn"),
-        //     Tool::Spawn(kind) => write!(f, "Spawn {}", kind),
-        // }
+        match self {
+            Tool::Despawn(EraseFilter::All) => write!(f, "Erase"),
+            Tool::Despawn(EraseFilter::Only(kinds)) => {
+                let kinds: Vec<_> = kinds.iter().map(|kind| kind.to_string()).collect();
+                write!(f, "Erase {}", kinds.join(", "))
+            }
+            Tool::Spawn(kind, PaintMode::Overwrite) => write!(f, "Spawn {}", kind),
+            Tool::Spawn(kind, PaintMode::OnlyEmpty) => write!(f, "Spawn {} (only empty)", kind),
+            Tool::Spawn(kind, PaintMode::Replace(replaced)) => {
+                write!(f, "Spawn {} (replace {})", kind, replaced)
+            }
+            Tool::Heat(degrees) if *degrees < 0.0 => write!(f, "Cool ({}C)", degrees),
+            Tool::Heat(degrees) => write!(f, "Heat (+{}C)", degrees),
+        }
+    }
+}
+
+/// How [`Tool::Spawn`] treats the cells it paints over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaintMode {
+    /// Paint over every cell, whatever it contains
+    #[default]
+    Overwrite,
+    /// Paint only into empty cells
+    OnlyEmpty,
+    /// Paint only over particles of the given kind
+    Replace(ParticleKind),
+}
+
+/// Which particles [`Tool::Despawn`] erases
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum EraseFilter {
+    /// Erase particles of every kind
+    #[default]
+    All,
+    /// Erase only particles of the given kinds
+    Only(Vec<ParticleKind>),
+}
+
+impl EraseFilter {
+    /// Adds the kind to the erased kinds, or removes it if it's already there. Removing the last
+    /// kind erases [`EraseFilter::All`] again.
+    pub fn toggle(&mut self, kind: ParticleKind) {
+        match self {
+            EraseFilter::All => *self = EraseFilter::Only(vec![kind]),
+            EraseFilter::Only(kinds) => {
+                if let Some(index) = kinds.iter().position(|&erased| erased == kind) {
+                    kinds.remove(index);
+                } else {
+                    kinds.push(kind);
+                }
+                if kinds.is_empty() {
+                    *self = EraseFilter::All;
+                }
+            }
+        }
+    }
+}
+
+/// Bevy [`Resource`] holding the path of the [`RuleSet`] asset the world's rules come from
+#[derive(Resource, Debug, Clone)]
+pub struct RuleSetPath(pub String);
+
+/// Bevy [`Resource`] holding the [`RuleSet`] the world's rules come from. Whenever the asset is
+/// (re)loaded, its rules replace the ones spawned from it before.
+#[derive(Resource, Debug, Clone)]
+pub struct ActiveRuleSet(pub Handle<RuleSet>);
+
+/// Bevy [`Resource`] for the world the keyboard and mouse act on, the one under the pointer.
+/// It doesn't change in the middle of a brush stroke.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct FocusedWorld(pub Option<Entity>);
+
+/// Bevy [`Resource`] holding the path snapshots of the world are saved to and loaded from with
+/// the keyboard
+#[derive(Resource, Debug, Clone)]
+pub struct SnapshotPath(pub PathBuf);
+
+impl Default for SnapshotPath {
+    fn default() -> Self {
+        SnapshotPath(PathBuf::from("world.snapshot"))
+    }
+}
+
+/// Bevy [`Resource`] for the palette worlds are imported from and exported to images with
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ImagePalette {
+    pub palette: Palette,
+    /// What to do with colours of imported images that aren't in the palette
+    pub unknown_colors: UnknownColors,
+}
+
+/// Bevy [`Resource`] controlling how fast the simulation runs, change it by sending
+/// [`crate::SimulationCommand`]s. Insert it before adding the [`crate::CellEnginePlugin`] to
+/// start with other settings.
+#[derive(Resource, Debug, Clone)]
+pub struct SimulationControl {
+    /// Number of fixed updates per second
+    pub tick_rate: f64,
+    /// Number of ticks simulated per fixed update
+    pub ticks_per_update: usize,
+    /// Ticks left to simulate while paused
+    pub pending_steps: usize,
+    /// Whether the worlds are stepped on all threads, see
+    /// [`cell_simulation::Simulation::step_parallel`]
+    pub parallel: bool,
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        Self {
+            tick_rate: 100.0,
+            ticks_per_update: 1,
+            pending_steps: 0,
+            parallel: false,
+        }
+    }
+}
+
+impl SimulationControl {
+    /// The number of ticks to simulate in this fixed update, taking them from the pending steps
+    /// while paused
+    pub fn ticks_this_update(&mut self, state: SimulationState) -> usize {
+        match state {
+            SimulationState::Running => self.ticks_per_update,
+            SimulationState::Paused => {
+                let ticks = self.pending_steps.min(self.ticks_per_update);
+                self.pending_steps -= ticks;
+                ticks
+            }
+        }
+    }
+}
+
+/// Bevy [`Resource`] for the brush the [`Tool`] paints with
+#[derive(Resource, Debug, Clone, Default, Deref, DerefMut)]
+pub struct PaintBrush(pub Brush);
+
+/// Bevy [`Resource`] for the shape the [`Tool`] is applied in
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolShape {
+    /// Paint with the [`PaintBrush`] wherever the mouse goes
+    #[default]
+    Freehand,
+    /// Paint a line with the [`PaintBrush`] from where the mouse is pressed to where it's released
+    Line,
+    /// Fill the rectangle between where the mouse is pressed and where it's released
+    Rectangle,
+    /// Fill the area of connected cells of the same kind under the mouse
+    FloodFill,
+}
+
+/// Bevy [`Resource`] to keep track of the brush stroke being painted
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PaintStroke {
+    /// The cell the mouse was pressed on, for [`ToolShape::Line`] and [`ToolShape::Rectangle`]
+    pub start: Option<(isize, isize)>,
+    /// The cell the mouse was on last frame, to join up fast [`ToolShape::Freehand`] strokes
+    pub last: Option<(isize, isize)>,
+}
+
+/// Bevy [`Resource`] for what the world view shows of every cell
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViewMode {
+    /// The colour of the material in the cell
+    #[default]
+    Materials,
+    /// The pressure on the particle in the cell, blue below the air's and red above it
+    Pressure,
+}
+
+impl ViewMode {
+    pub fn toggle(&mut self) {
+        *self = match self {
+            Self::Materials => Self::Pressure,
+            Self::Pressure => Self::Materials,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAND: ParticleKind = ParticleKind::SAND;
+    const WATER: ParticleKind = ParticleKind::WATER;
+
+    fn cell(kind: Option<ParticleKind>) -> ParticleCell {
+        ParticleCell {
+            content: kind.map(Particle::new),
+        }
+    }
+
+    /// What the tool turns an empty cell, a sand cell and a water cell into, by kind. The outer
+    /// [`None`] means the cell is left alone.
+    fn apply_to_each(tool: Tool) -> [Option<Option<ParticleKind>>; 3] {
+        [None, Some(SAND), Some(WATER)].map(|kind| {
+            tool.apply(&cell(kind))
+                .map(|cell| cell.content.map(|particle| particle.kind))
+        })
+    }
+
+    #[test]
+    fn test_spawn_overwrite_paints_every_cell() {
+        assert_eq!(
+            apply_to_each(Tool::Spawn(SAND, PaintMode::Overwrite)),
+            [Some(Some(SAND)), Some(Some(SAND)), Some(Some(SAND))]
+        );
+    }
+
+    #[test]
+    fn test_spawn_only_empty_leaves_particles_alone() {
+        assert_eq!(
+            apply_to_each(Tool::Spawn(SAND, PaintMode::OnlyEmpty)),
+            [Some(Some(SAND)), None, None]
+        );
+    }
+
+    #[test]
+    fn test_spawn_replace_paints_only_over_the_replaced_kind() {
+        assert_eq!(
+            apply_to_each(Tool::Spawn(SAND, PaintMode::Replace(WATER))),
+            [None, None, Some(Some(SAND))]
+        );
+        assert_eq!(
+            apply_to_each(Tool::Spawn(SAND, PaintMode::Replace(SAND))),
+            [None, Some(Some(SAND)), None]
+        );
+    }
+
+    #[test]
+    fn test_erase_only_erases_the_chosen_kinds() {
+        assert_eq!(
+            apply_to_each(Tool::Despawn(EraseFilter::Only(vec![WATER]))),
+            [None, None, Some(None)]
+        );
+        assert_eq!(
+            apply_to_each(Tool::Despawn(EraseFilter::Only(vec![SAND, WATER]))),
+            [None, Some(None), Some(None)]
+        );
+        assert_eq!(
+            apply_to_each(Tool::Despawn(EraseFilter::All)),
+            [None, Some(None), Some(None)]
+        );
     }
 }
@@ -0,0 +1,11 @@
+use bevy::prelude::*;
+
+/// Bevy [`States`] for whether the simulation is running.
+/// While paused, the world only advances by the steps requested with
+/// [`crate::SimulationCommand::Step`].
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SimulationState {
+    #[default]
+    Running,
+    Paused,
+}
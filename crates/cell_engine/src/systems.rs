@@ -1,15 +1,27 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
 use bevy::asset::RenderAssetUsages;
 use bevy::image::ImageSampler;
+use bevy::input::mouse::{MouseMotion, MouseWheel};
 use bevy::prelude::*;
 use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
 use bevy_catppuccin::CatppuccinTheme;
 use bevy_pointer_to_world::{PointerToWorldCamera, PointerWorldPosition};
-use cell_particle::grid::{Dimensions, Grid};
-use cell_particle::particle::{Particle, ParticleKind};
-use cell_particle::rule::{Input, Occupancy, Output, Rule};
+use cell_particle::grid::Dimensions;
+use cell_particle::particle::materials;
+use cell_particle::rule::parse_rules;
+use cell_simulation::{flood_fill, line, rectangle, BrushShape, SimulationRule, SnapshotError};
 use percentage::Percentage;
 
-use crate::{CellRule, CellWorld, ParticleCell, Tool, ToolText, View, WorldTexture};
+use crate::{
+    ActiveRuleSet, AppliesTo, CellColor, CellRule, CellWorld, EraseFilter, ExportImage,
+    FocusedWorld, FromRuleSet, History, ImagePalette, ImportImage, LoadWorld, MainCamera,
+    PaintBrush, PaintMode, PaintStroke, RuleSet, RuleSetPath, SaveWorld, SimulationCommand,
+    SimulationControl, SimulationState, SnapshotPath, Tool, ToolShape, ToolText, View, ViewMode,
+    WorldRuleSet, WorldTexture,
+};
 #[cfg(feature = "debug")]
 use crate::{
     DebugMenu, DebugMenuState, ExistingParticleCountText, SpawnedParticleCountText, ToggleDebugMenu,
@@ -17,6 +29,8 @@ use crate::{
 
 #[cfg(feature = "debug")]
 use crate::Stats;
+#[cfg(feature = "debug")]
+use cell_simulation::DirtyRect;
 
 /// Bevy [`Startup`] system to setup the environment
 pub fn setup_environment(mut commands: Commands, theme: Res<CatppuccinTheme>) {
@@ -28,225 +42,96 @@ pub fn setup_environment(mut commands: Commands, theme: Res<CatppuccinTheme>) {
             ..default()
         },
         PointerToWorldCamera,
+        MainCamera,
     ));
 
-    // World
-    commands.spawn(CellWorld::new(126, 70));
+    // World, its seed is logged so a run can be reproduced with `CellWorld::with_seed`
+    let cell_world = CellWorld::new(126, 70);
+    info!("Cell world seed: {}", cell_world.seed());
+    commands.spawn(cell_world);
 }
 
+/// The rules the world starts out with, the same file `bevy_cells` loads as its rule set asset
+pub const DEFAULT_RULES: &str = include_str!("../../bevy_cells/assets/rules/default.rules");
+
 /// Bevy [`Startup`] system to setup the rules of the world
 pub fn setup_rules(mut commands: Commands) {
-    // Sand
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![Occupancy::OccupiedBy(ParticleKind::Sand)],
-                    vec![Occupancy::Vacant],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![Occupancy::Vacant],
-                    vec![Occupancy::OccupiedBy(ParticleKind::Sand)],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: None,
-    });
-
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Sand),
-                        Occupancy::Unknown,
-                    ],
-                    vec![Occupancy::OccupiedByAny, Occupancy::Vacant],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![Occupancy::Vacant, Occupancy::Unknown],
-                    vec![
-                        Occupancy::OccupiedByAny,
-                        Occupancy::OccupiedBy(ParticleKind::Sand),
-                    ],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: None,
-    });
-
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::Unknown,
-                        Occupancy::OccupiedBy(ParticleKind::Sand),
-                    ],
-                    vec![Occupancy::Vacant, Occupancy::OccupiedByAny],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![Occupancy::Unknown, Occupancy::Vacant],
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Sand),
-                        Occupancy::OccupiedByAny,
-                    ],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: None,
-    });
-
-    // Water
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![Occupancy::OccupiedBy(ParticleKind::Water)],
-                    vec![Occupancy::Vacant],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![Occupancy::Vacant],
-                    vec![Occupancy::OccupiedBy(ParticleKind::Water)],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: Some(0),
-    });
-
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                        Occupancy::Unknown,
-                    ],
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                        Occupancy::Vacant,
-                    ],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![Occupancy::Vacant, Occupancy::Unknown],
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                    ],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: Some(1),
-    });
-
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::Unknown,
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                    ],
-                    vec![
-                        Occupancy::Vacant,
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                    ],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![Occupancy::Unknown, Occupancy::Vacant],
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                    ],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: Some(1),
-    });
-
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                        Occupancy::Vacant,
-                    ],
-                    vec![Occupancy::OccupiedByAny, Occupancy::OccupiedByAny],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::Vacant,
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                    ],
-                    vec![Occupancy::OccupiedByAny, Occupancy::OccupiedByAny],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: Some(2),
-    });
-
-    commands.spawn(CellRule {
-        rule: Rule {
-            input: Input {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::Vacant,
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                    ],
-                    vec![Occupancy::OccupiedByAny, Occupancy::OccupiedByAny],
-                ])
-                .unwrap(),
-            },
-            output: vec![Output {
-                grid: Grid::new(vec![
-                    vec![
-                        Occupancy::OccupiedBy(ParticleKind::Water),
-                        Occupancy::Vacant,
-                    ],
-                    vec![Occupancy::OccupiedByAny, Occupancy::OccupiedByAny],
-                ])
-                .unwrap(),
-                probability: Percentage::new(1.0),
-            }],
-        },
-        priority: Some(2),
-    });
+    let rules = parse_rules(DEFAULT_RULES).expect("the default rules should be valid");
+    for rule in rules.into_iter().map(CellRule::from) {
+        commands.spawn_batch(rule.variants().collect::<Vec<_>>());
+    }
+}
+
+/// Bevy [`Startup`] system to start loading the [`RuleSet`] at [`RuleSetPath`]
+pub fn load_rule_set(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    rule_set_path: Res<RuleSetPath>,
+) {
+    let handle = asset_server.load(rule_set_path.0.clone());
+    commands.insert_resource(ActiveRuleSet(handle));
+}
+
+/// Bevy [`Update`] system to replace the rules spawned from a [`RuleSet`] whenever the asset
+/// finishes loading, which also happens when the file changes on disk. The [`ActiveRuleSet`]
+/// spawns rules shared by the worlds, a [`WorldRuleSet`] spawns rules [`AppliesTo`] its world.
+pub fn apply_rule_set(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<RuleSet>>,
+    active_rule_set: Option<Res<ActiveRuleSet>>,
+    world_rule_sets: Query<(Entity, &WorldRuleSet)>,
+    added_world_rule_sets: Query<Entity, Added<WorldRuleSet>>,
+    rule_sets: Res<Assets<RuleSet>>,
+    spawned_rules: Query<(Entity, Option<&AppliesTo>), With<FromRuleSet>>,
+) {
+    let reloaded: Vec<_> = asset_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+
+    // the rule sets to (re)spawn, by the world they apply to, `None` for the shared rules
+    let mut targets: Vec<(Option<Entity>, AssetId<RuleSet>)> = Vec::new();
+    if let Some(active_rule_set) = &active_rule_set {
+        if reloaded.contains(&active_rule_set.0.id()) {
+            targets.push((None, active_rule_set.0.id()));
+        }
+    }
+    for (world, world_rule_set) in world_rule_sets.iter() {
+        // a rule set that's already loaded sends no event for a world added later
+        if reloaded.contains(&world_rule_set.0.id()) || added_world_rule_sets.contains(world) {
+            targets.push((Some(world), world_rule_set.0.id()));
+        }
+    }
+
+    for (target, id) in targets {
+        let Some(rule_set) = rule_sets.get(id) else {
+            continue;
+        };
+
+        for (entity, applies_to) in spawned_rules.iter() {
+            if applies_to.map(|applies_to| applies_to.0) == target {
+                commands.entity(entity).despawn();
+            }
+        }
+        for rule in &rule_set.rules {
+            match target {
+                Some(world) => commands.spawn_batch(
+                    rule.variants()
+                        .map(|rule| (rule, FromRuleSet, AppliesTo(world)))
+                        .collect::<Vec<_>>(),
+                ),
+                None => commands.spawn_batch(
+                    rule.variants()
+                        .map(|rule| (rule, FromRuleSet))
+                        .collect::<Vec<_>>(),
+                ),
+            }
+        }
+        info!("Loaded {} rules from rule set", rule_set.rules.len());
+    }
 }
 
 /// Bevy [`Startup`] system to setup the visualisation of the world
@@ -257,11 +142,7 @@ pub fn setup_view(
 ) {
     for (entity, cell_world) in query.iter() {
         let Dimensions { width, height } = cell_world.grid.dimensions();
-
-        let size = Vec2::new(
-            width as f32 * cell_world.resolution as f32,
-            height as f32 * cell_world.resolution as f32,
-        );
+        let size = cell_world.size();
 
         // Create the texture
         let mut texture = Image::new_fill(
@@ -285,129 +166,849 @@ pub fn setup_view(
 
         let canvas = images.add(texture);
 
-        // Spawn a sprite bundle using the texture
-        commands.spawn((
+        // Spawn a sprite using the texture as a child of the world, so it follows its transform
+        commands.entity(entity).insert(View).with_child((
             Sprite {
                 custom_size: Some(size),
                 image: canvas,
                 ..default()
             },
-            // Transform::from_scale(Vec3::new(10.0, 0.0, 10.0)),
             WorldTexture,
         ));
-
-        commands.entity(entity).insert(View);
     }
 }
 
-/// Bevy [`FixedUpdate`] system to update the grid
-pub fn grid_update(cell_rules: Query<&CellRule>, mut grid: Query<&mut CellWorld>) {
-    let Ok(mut cell_world) = grid.get_single_mut() else {
+/// Bevy [`FixedUpdate`] system to update the grids, as many ticks as the [`SimulationControl`]
+/// allows, counting each one towards the next checkpoint of the world's [`History`].
+/// Each world with a [`WorldRuleSet`] or rules [`AppliesTo`] it is stepped by those alone, even
+/// while there are none, other worlds are stepped by the shared rules.
+pub fn grid_update(
+    cell_rules: Query<(&CellRule, Option<&AppliesTo>)>,
+    mut cell_worlds: Query<(Entity, &mut CellWorld, &mut History, Has<WorldRuleSet>)>,
+    simulation_state: Res<State<SimulationState>>,
+    mut simulation_control: ResMut<SimulationControl>,
+) {
+    if cell_worlds.is_empty() {
         warn!("No cell world found");
         return;
-    };
+    }
+
+    let ticks = simulation_control.ticks_this_update(*simulation_state.get());
+    if ticks == 0 {
+        return;
+    }
+    // The rules by the world they apply to, `None` for the shared rules
+    let mut rules_by_world: HashMap<Option<Entity>, Vec<&SimulationRule>> = HashMap::new();
+    for (rule, applies_to) in cell_rules.iter() {
+        rules_by_world
+            .entry(applies_to.map(|applies_to| applies_to.0))
+            .or_default()
+            .push(&rule.0);
+    }
+    for (entity, mut cell_world, mut history, has_rule_set) in cell_worlds.iter_mut() {
+        let own_rules = rules_by_world.get(&Some(entity));
+        let rules = match own_rules {
+            Some(rules) => rules.as_slice(),
+            None if has_rule_set => &[],
+            None => rules_by_world.get(&None).map_or(&[][..], Vec::as_slice),
+        };
+        for _ in 0..ticks {
+            if simulation_control.parallel {
+                cell_world.update_parallel(rules);
+            } else {
+                cell_world.update(rules);
+            }
+            history.tick(&cell_world);
+        }
+    }
+}
 
-    let rules: Vec<_> = cell_rules.iter().map(|r| r.clone()).collect();
-    cell_world.update(&rules);
+/// Bevy [`Update`] system to control the simulation from the keyboard.
+/// Space pauses and resumes, period steps a single tick, or ten with Shift,
+/// the brackets halve and double the tick rate, minus and plus change the ticks per update,
+/// and P switches between stepping on one thread and on all of them.
+pub fn simulation_keys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    simulation_control: Res<SimulationControl>,
+    mut commands: EventWriter<SimulationCommand>,
+) {
+    let shift = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        commands.send(SimulationCommand::TogglePause);
+    }
+    if keyboard_input.just_pressed(KeyCode::Period) {
+        commands.send(SimulationCommand::Step(if shift { 10 } else { 1 }));
+    }
+    if keyboard_input.just_pressed(KeyCode::BracketLeft) {
+        commands.send(SimulationCommand::SetTickRate(
+            simulation_control.tick_rate / 2.0,
+        ));
+    }
+    if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        commands.send(SimulationCommand::SetTickRate(
+            simulation_control.tick_rate * 2.0,
+        ));
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus) {
+        commands.send(SimulationCommand::SetTicksPerUpdate(
+            simulation_control.ticks_per_update.saturating_sub(1),
+        ));
+    }
+    if keyboard_input.just_pressed(KeyCode::Equal) {
+        commands.send(SimulationCommand::SetTicksPerUpdate(
+            simulation_control.ticks_per_update + 1,
+        ));
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyP) {
+        commands.send(SimulationCommand::SetParallel(!simulation_control.parallel));
+    }
 }
 
-/// Bevy [`Update`] system to update the visualisation of the world
+/// Bevy [`Update`] system to apply [`SimulationCommand`]s to the [`SimulationControl`], the
+/// [`SimulationState`] and the fixed timestep
+pub fn apply_simulation_commands(
+    mut commands: EventReader<SimulationCommand>,
+    mut simulation_control: ResMut<SimulationControl>,
+    simulation_state: Res<State<SimulationState>>,
+    mut next_simulation_state: ResMut<NextState<SimulationState>>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    for command in commands.read() {
+        match *command {
+            SimulationCommand::Pause => next_simulation_state.set(SimulationState::Paused),
+            SimulationCommand::Resume => next_simulation_state.set(SimulationState::Running),
+            SimulationCommand::TogglePause => {
+                next_simulation_state.set(match simulation_state.get() {
+                    SimulationState::Running => SimulationState::Paused,
+                    SimulationState::Paused => SimulationState::Running,
+                })
+            }
+            SimulationCommand::Step(ticks) => {
+                next_simulation_state.set(SimulationState::Paused);
+                simulation_control.pending_steps += ticks;
+            }
+            SimulationCommand::SetTickRate(tick_rate) => {
+                // Keep the rate sensible, a zero rate would make the fixed timestep infinite
+                let tick_rate = tick_rate.clamp(1.0, 1000.0);
+                simulation_control.tick_rate = tick_rate;
+                fixed_time.set_timestep_hz(tick_rate);
+                info!("Tick rate: {} Hz", tick_rate);
+            }
+            SimulationCommand::SetTicksPerUpdate(ticks) => {
+                simulation_control.ticks_per_update = ticks.max(1);
+                info!("Ticks per update: {}", simulation_control.ticks_per_update);
+            }
+            SimulationCommand::SetParallel(parallel) => {
+                simulation_control.parallel = parallel;
+                info!("Parallel stepping: {}", parallel);
+            }
+        }
+    }
+}
+
+/// Bevy [`Update`] system to update the visualisation of the worlds, each into its own texture,
+/// showing what the [`ViewMode`] asks for
 pub fn view_update(
     mut images: ResMut<Assets<Image>>,
     cell_worlds: Query<&CellWorld>,
-    sprites: Query<&Sprite, With<WorldTexture>>,
-    theme: Res<CatppuccinTheme>,
+    sprites: Query<(&Parent, &Sprite), With<WorldTexture>>,
+    view_mode: Res<ViewMode>,
 ) {
-    for cell_world in cell_worlds.iter() {
+    let materials = materials();
+    for (parent, sprite) in sprites.iter() {
+        let Ok(cell_world) = cell_worlds.get(parent.get()) else {
+            continue;
+        };
         let Dimensions { width, height } = cell_world.grid.dimensions();
 
         // Get the texture handle
-        if let Ok(sprite) = sprites.get_single() {
-            if let Some(texture) = images.get_mut(&sprite.image) {
-                // Update texture data based on grid state
-                let mut pixel_data = vec![0; (width * height * 4) as usize];
-
-                for y in 0..height {
-                    for x in 0..width {
-                        let index = ((y * width + x) * 4) as usize;
-                        let Ok(cell) = cell_world.grid.get(x, y) else {
-                            continue;
-                        };
-
-                        let color = cell.color(&theme.flavor).to_srgba();
-
-                        pixel_data[index] = (color.red * 255.0) as u8;
-                        pixel_data[index + 1] = (color.green * 255.0) as u8;
-                        pixel_data[index + 2] = (color.blue * 255.0) as u8;
-                        pixel_data[index + 3] = (color.alpha * 255.0) as u8;
+        if let Some(texture) = images.get_mut(&sprite.image) {
+            // Update texture data based on grid state
+            let mut pixel_data = vec![0; (width * height * 4) as usize];
+
+            for y in 0..height {
+                for x in 0..width {
+                    let index = ((y * width + x) * 4) as usize;
+                    let Ok(cell) = cell_world.grid.get(x, y) else {
+                        continue;
+                    };
+
+                    let color = match *view_mode {
+                        ViewMode::Materials => cell.color(&materials, x, y),
+                        ViewMode::Pressure => cell.pressure_color(),
                     }
-                }
+                    .to_srgba();
 
-                texture.data = pixel_data;
+                    pixel_data[index] = (color.red * 255.0) as u8;
+                    pixel_data[index + 1] = (color.green * 255.0) as u8;
+                    pixel_data[index + 2] = (color.blue * 255.0) as u8;
+                    pixel_data[index + 3] = (color.alpha * 255.0) as u8;
+                }
             }
+
+            texture.data = pixel_data;
+        }
+    }
+}
+
+/// Bevy [`Update`] system to switch the [`ViewMode`] between materials and pressure with Tab
+pub fn view_keys(keyboard_input: Res<ButtonInput<KeyCode>>, mut view_mode: ResMut<ViewMode>) {
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        view_mode.toggle();
+        info!("View: {:?}", *view_mode);
+    }
+}
+
+/// Bevy [`Update`] system to focus the world under the pointer. The focus stays put in the middle
+/// of a brush stroke, and moves to any other world if the focused one is gone.
+pub fn focus_world(
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    pointer_world_position: Res<PointerWorldPosition>,
+    cell_worlds: Query<(Entity, &CellWorld, &GlobalTransform)>,
+    mut focused_world: ResMut<FocusedWorld>,
+) {
+    let painting = mouse_button_input.pressed(MouseButton::Left)
+        && !mouse_button_input.just_pressed(MouseButton::Left);
+    if !painting {
+        let hovered = cell_worlds.iter().find(|(_, cell_world, transform)| {
+            cell_world.contains(cell_world.cell_at(transform, pointer_world_position.0))
+        });
+        if let Some((entity, _, _)) = hovered {
+            focused_world.0 = Some(entity);
         }
     }
+
+    if !focused_world
+        .0
+        .is_some_and(|entity| cell_worlds.contains(entity))
+    {
+        focused_world.0 = cell_worlds.iter().next().map(|(entity, _, _)| entity);
+    }
 }
 
-/// Bevy [`Update`] system to take input from the mouse
+/// Bevy [`Update`] system to paint into the [`FocusedWorld`] with the mouse, applying the [`Tool`]
+/// in the current [`ToolShape`] with the [`PaintBrush`]
+#[allow(clippy::too_many_arguments)]
 pub fn mouse_input(
-    mouse_button_input: ResMut<ButtonInput<MouseButton>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
     pointer_world_position: Res<PointerWorldPosition>,
-    mut cell_worlds: Query<&mut CellWorld>,
+    focused_world: Res<FocusedWorld>,
+    mut cell_worlds: Query<(&mut CellWorld, &mut History, &GlobalTransform)>,
     tool: Res<Tool>,
+    tool_shape: Res<ToolShape>,
+    brush: Res<PaintBrush>,
+    mut paint_stroke: ResMut<PaintStroke>,
     #[cfg(feature = "debug")] mut stats: ResMut<Stats>,
 ) {
-    if mouse_button_input.pressed(MouseButton::Left) {
-        let Ok(mut cell_world) = cell_worlds.get_single_mut() else {
-            return;
+    let Some(entity) = focused_world.0 else {
+        return;
+    };
+    let Ok((mut cell_world, mut history, transform)) = cell_worlds.get_mut(entity) else {
+        return;
+    };
+
+    let position = cell_world.cell_at(transform, pointer_world_position.0);
+    let mut rng = rand::rng();
+
+    let cells = match *tool_shape {
+        ToolShape::Freehand => {
+            if !mouse_button_input.pressed(MouseButton::Left) {
+                paint_stroke.last = None;
+                return;
+            }
+            // join up with the last frame, so fast strokes don't leave gaps
+            let from = paint_stroke.last.replace(position).unwrap_or(position);
+            let footprint: BTreeSet<_> = line(from, position)
+                .into_iter()
+                .flat_map(|(x, y)| brush.footprint(x, y))
+                .collect();
+            brush.spray(footprint, &mut rng)
+        }
+        ToolShape::Line | ToolShape::Rectangle => {
+            if mouse_button_input.just_pressed(MouseButton::Left) {
+                paint_stroke.start = Some(position);
+            }
+            if !mouse_button_input.just_released(MouseButton::Left) {
+                return;
+            }
+            let Some(start) = paint_stroke.start.take() else {
+                return;
+            };
+            if *tool_shape == ToolShape::Line {
+                let footprint: BTreeSet<_> = line(start, position)
+                    .into_iter()
+                    .flat_map(|(x, y)| brush.footprint(x, y))
+                    .collect();
+                brush.spray(footprint, &mut rng)
+            } else {
+                brush.spray(rectangle(start, position), &mut rng)
+            }
+        }
+        ToolShape::FloodFill => {
+            if !mouse_button_input.just_pressed(MouseButton::Left) {
+                return;
+            }
+            let (Ok(x), Ok(y)) = (usize::try_from(position.0), usize::try_from(position.1)) else {
+                return;
+            };
+            let area = flood_fill(&cell_world.grid, x, y)
+                .into_iter()
+                .map(|(x, y)| (x as isize, y as isize));
+            brush.spray(area, &mut rng)
+        }
+    };
+
+    let _painted = paint(&mut cell_world, &mut history, &tool, cells);
+
+    #[cfg(feature = "debug")]
+    {
+        stats.spawned_particles += _painted;
+    }
+}
+
+/// Applies the [`Tool`] to the given cells, skipping those outside the grid and those the tool
+/// leaves alone. Setting a cell also marks it and its neighbours as active. Returns the number of
+/// cells painted.
+fn paint(
+    cell_world: &mut CellWorld,
+    history: &mut History,
+    tool: &Tool,
+    cells: impl IntoIterator<Item = (isize, isize)>,
+) -> usize {
+    let mut painted = 0;
+    for (x, y) in cells {
+        let (Ok(x), Ok(y)) = (usize::try_from(x), usize::try_from(y)) else {
+            continue;
+        };
+        let Ok(before) = cell_world.grid.get(x, y).cloned() else {
+            continue;
+        };
+        let Some(after) = tool.apply(&before) else {
+            continue;
+        };
+        cell_world.set_cell(x, y, after.clone()).unwrap();
+        history.record(x, y, before, after);
+        painted += 1;
+    }
+    painted
+}
+
+/// Bevy [`Update`] system to resize the [`PaintBrush`] with the scroll wheel, change its density
+/// with Shift+scroll and toggle between a round and a square brush with Q.
+/// Ctrl+scroll is left to [`camera_zoom`].
+pub fn brush_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut brush: ResMut<PaintBrush>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyQ) {
+        brush.shape = match brush.shape {
+            BrushShape::Circle => BrushShape::Square,
+            BrushShape::Square => BrushShape::Circle,
         };
+    }
+
+    let scroll: f32 = mouse_wheel.read().map(|event| event.y).sum();
+    let control = keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if scroll == 0.0 || control {
+        return;
+    }
+    let step = scroll.signum();
 
-        let mut grid_position =
-            pointer_world_position.0 / cell_world.resolution as f32 * Vec2::new(1.0, -1.0);
-        grid_position.x += cell_world.grid.dimensions().width as f32 / 2.0;
-        grid_position.y += cell_world.grid.dimensions().height as f32 / 2.0;
+    if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        let density = (brush.density.value() + step * 0.1).clamp(0.1, 1.0);
+        brush.density = Percentage::new(density);
+    } else {
+        brush.radius = (brush.radius as isize + step as isize).clamp(0, MAX_BRUSH_RADIUS) as usize;
+    }
+}
 
-        let x = grid_position.x as usize;
-        let y = grid_position.y as usize;
+/// The largest radius the [`PaintBrush`] can be scrolled to
+const MAX_BRUSH_RADIUS: isize = 32;
 
-        // set the cell
-        if let Ok(cell) = cell_world.grid.get_mut(x, y) {
-            match *tool {
-                Tool::Despawn => {
-                    *cell = ParticleCell { content: None };
-                }
-                Tool::Spawn(particle_kind) => {
-                    *cell = ParticleCell {
-                        content: Some(Particle::new(particle_kind)),
-                    };
-                }
+/// Bevy [`Update`] system to preview the [`PaintBrush`] under the mouse, and the line or
+/// rectangle being drawn in the [`FocusedWorld`]
+#[allow(clippy::too_many_arguments)]
+pub fn draw_tool_preview(
+    mut gizmos: Gizmos,
+    pointer_world_position: Res<PointerWorldPosition>,
+    focused_world: Res<FocusedWorld>,
+    cell_worlds: Query<(&CellWorld, &GlobalTransform)>,
+    tool_shape: Res<ToolShape>,
+    brush: Res<PaintBrush>,
+    paint_stroke: Res<PaintStroke>,
+    theme: Res<CatppuccinTheme>,
+) {
+    let Some(entity) = focused_world.0 else {
+        return;
+    };
+    let Ok((cell_world, transform)) = cell_worlds.get(entity) else {
+        return;
+    };
+
+    let color = theme.flavor.overlay1;
+    let position = cell_world.cell_at(transform, pointer_world_position.0);
+    let center = cell_world.cell_center(transform, position.0, position.1);
+    let resolution = cell_world.resolution as f32 * transform.scale().x;
+
+    if matches!(*tool_shape, ToolShape::Freehand | ToolShape::Line) {
+        let extent = (brush.radius as f32 + 0.5) * resolution;
+        match brush.shape {
+            BrushShape::Circle => {
+                gizmos.circle_2d(center, extent, color);
+            }
+            BrushShape::Square => {
+                gizmos.rect_2d(center, Vec2::splat(extent * 2.0), color);
+            }
+        }
+    }
+
+    let Some(start) = paint_stroke.start else {
+        return;
+    };
+    let start = cell_world.cell_center(transform, start.0, start.1);
+    match *tool_shape {
+        ToolShape::Line => {
+            gizmos.line_2d(start, center, color);
+        }
+        ToolShape::Rectangle => {
+            let size = (center - start).abs() + Vec2::splat(resolution);
+            gizmos.rect_2d((start + center) / 2.0, size, color);
+        }
+        _ => {}
+    }
+}
+
+/// Bevy [`Update`] system to finish the current brush stroke once the mouse is released
+pub fn end_stroke(
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut histories: Query<&mut History>,
+) {
+    if !mouse_button_input.pressed(MouseButton::Left) {
+        for mut history in histories.iter_mut() {
+            history.end_stroke();
+        }
+    }
+}
+
+/// Bevy [`Update`] system to undo with Ctrl+Z, redo with Ctrl+Shift+Z and rewind the simulation
+/// to the last checkpoint with Backspace, all in the [`FocusedWorld`]
+pub fn history_keys(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    focused_world: Res<FocusedWorld>,
+    mut cell_worlds: Query<(&mut CellWorld, &mut History)>,
+    world_textures: Query<(Entity, &Parent), With<WorldTexture>>,
+) {
+    let Some(entity) = focused_world.0 else {
+        return;
+    };
+    let Ok((mut cell_world, mut history)) = cell_worlds.get_mut(entity) else {
+        return;
+    };
+    let control = keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    if control && keyboard_input.just_pressed(KeyCode::KeyZ) {
+        let done = if shift {
+            history.redo(&mut cell_world)
+        } else {
+            history.undo(&mut cell_world)
+        };
+        if !done {
+            info!("Nothing to {}", if shift { "redo" } else { "undo" });
+        }
+    } else if keyboard_input.just_pressed(KeyCode::Backspace) {
+        match history.rewind(&mut cell_world) {
+            Ok(true) => {
+                refresh_view(&mut commands, entity, &world_textures);
+                info!("Rewound, {} checkpoints left", history.checkpoints());
+            }
+            Ok(false) => info!("No checkpoint to rewind to"),
+            Err(error) => error!("Could not rewind: {}", error),
+        }
+    }
+}
+
+/// Replaces the texture of a world, for when its size may have changed
+fn refresh_view(
+    commands: &mut Commands,
+    cell_world: Entity,
+    world_textures: &Query<(Entity, &Parent), With<WorldTexture>>,
+) {
+    for (texture, parent) in world_textures.iter() {
+        if parent.get() == cell_world {
+            commands.entity(texture).despawn_recursive();
+        }
+    }
+    commands.entity(cell_world).remove::<View>();
+}
+
+/// How fast the camera pans with the keyboard, in pixels per second at a zoom of 1
+const CAMERA_PAN_SPEED: f32 = 600.0;
+
+/// How far the camera can zoom in and out, as the scale of its projection
+const CAMERA_ZOOM_RANGE: (f32, f32) = (0.05, 20.0);
+
+/// Bevy [`Update`] system to pan the camera by dragging with the middle mouse button, or with
+/// WASD and the arrow keys
+pub fn camera_pan(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    time: Res<Time>,
+    mut cameras: Query<(&mut Transform, &OrthographicProjection), With<MainCamera>>,
+) {
+    let Ok((mut transform, projection)) = cameras.get_single_mut() else {
+        return;
+    };
+
+    // dragging moves the world along with the mouse, screen space has y pointing down
+    let drag: Vec2 = mouse_motion.read().map(|event| event.delta).sum();
+    let mut offset = Vec2::ZERO;
+    if mouse_button_input.pressed(MouseButton::Middle) {
+        offset -= drag * Vec2::new(1.0, -1.0);
+    }
+
+    let mut direction = Vec2::ZERO;
+    if keyboard_input.any_pressed([KeyCode::KeyW, KeyCode::ArrowUp]) {
+        direction.y += 1.0;
+    }
+    if keyboard_input.any_pressed([KeyCode::KeyS, KeyCode::ArrowDown]) {
+        direction.y -= 1.0;
+    }
+    if keyboard_input.any_pressed([KeyCode::KeyA, KeyCode::ArrowLeft]) {
+        direction.x -= 1.0;
+    }
+    if keyboard_input.any_pressed([KeyCode::KeyD, KeyCode::ArrowRight]) {
+        direction.x += 1.0;
+    }
+    offset += direction.normalize_or_zero() * CAMERA_PAN_SPEED * time.delta_secs();
+
+    transform.translation += (offset * projection.scale).extend(0.0);
+}
+
+/// Bevy [`Update`] system to zoom the camera toward the mouse with Ctrl+scroll
+pub fn camera_zoom(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    pointer_world_position: Res<PointerWorldPosition>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    let scroll: f32 = mouse_wheel.read().map(|event| event.y).sum();
+    let control = keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if scroll == 0.0 || !control {
+        return;
+    }
+    let Ok((mut transform, mut projection)) = cameras.get_single_mut() else {
+        return;
+    };
+
+    let (min, max) = CAMERA_ZOOM_RANGE;
+    let scale = (projection.scale * 0.9_f32.powf(scroll)).clamp(min, max);
+    let ratio = scale / projection.scale;
+    projection.scale = scale;
+
+    // keep the point under the mouse where it is
+    let pointer = pointer_world_position.0.extend(transform.translation.z);
+    transform.translation = pointer + (transform.translation - pointer) * ratio;
+}
+
+/// Bevy [`Update`] system to fit the [`FocusedWorld`] into the window with Home
+pub fn camera_fit(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window>,
+    focused_world: Res<FocusedWorld>,
+    cell_worlds: Query<(&CellWorld, &GlobalTransform)>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Home) {
+        return;
+    }
+    let Some(entity) = focused_world.0 else {
+        return;
+    };
+    let (Ok(window), Ok((cell_world, world_transform)), Ok((mut transform, mut projection))) = (
+        windows.get_single(),
+        cell_worlds.get(entity),
+        cameras.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    let size = cell_world.size() * world_transform.scale().truncate();
+    let fit = (size / window.size()).max_element() * 1.05;
+    let (min, max) = CAMERA_ZOOM_RANGE;
+    projection.scale = fit.clamp(min, max);
+    transform.translation = world_transform
+        .translation()
+        .truncate()
+        .extend(transform.translation.z);
+}
+
+/// The key for the hotkey of a material, digits and letters only
+fn key_code_of(hotkey: char) -> Option<KeyCode> {
+    const DIGITS: [KeyCode; 10] = [
+        KeyCode::Digit0,
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::KeyA,
+        KeyCode::KeyB,
+        KeyCode::KeyC,
+        KeyCode::KeyD,
+        KeyCode::KeyE,
+        KeyCode::KeyF,
+        KeyCode::KeyG,
+        KeyCode::KeyH,
+        KeyCode::KeyI,
+        KeyCode::KeyJ,
+        KeyCode::KeyK,
+        KeyCode::KeyL,
+        KeyCode::KeyM,
+        KeyCode::KeyN,
+        KeyCode::KeyO,
+        KeyCode::KeyP,
+        KeyCode::KeyQ,
+        KeyCode::KeyR,
+        KeyCode::KeyS,
+        KeyCode::KeyT,
+        KeyCode::KeyU,
+        KeyCode::KeyV,
+        KeyCode::KeyW,
+        KeyCode::KeyX,
+        KeyCode::KeyY,
+        KeyCode::KeyZ,
+    ];
+    match hotkey.to_ascii_lowercase() {
+        digit @ '0'..='9' => Some(DIGITS[digit as usize - '0' as usize]),
+        letter @ 'a'..='z' => Some(LETTERS[letter as usize - 'a' as usize]),
+        _ => None,
+    }
+}
+
+/// How many degrees the heat tool warms or cools particles by, every frame it's held on them
+const HEAT_TOOL_DEGREES: f32 = 20.0;
+
+/// Bevy [`Update`] system to switch between tools. 1 picks the eraser, and the hotkey of a
+/// registered material picks it for the spawn tool, 2 sand, 3 water, 4 stone, 5 ice, 6 steam,
+/// 7 lava and 8 oil by default. H picks the tool that heats particles up and C the one that cools
+/// them down.
+/// Alt+hotkey picks the kind the spawn tool replaces, or toggles a kind the eraser erases.
+/// M toggles the spawn tool between overwriting and painting only empty cells, and makes the eraser
+/// erase every kind again.
+pub fn tool_switch(
+    keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut tool: ResMut<Tool>,
+    mut tool_shape: ResMut<ToolShape>,
+    mut paint_stroke: ResMut<PaintStroke>,
+) {
+    let kind = materials()
+        .iter()
+        .find(|(_, material)| {
+            material
+                .hotkey
+                .and_then(key_code_of)
+                .is_some_and(|key| keyboard_input.just_pressed(key))
+        })
+        .map(|(kind, _)| kind);
+    let alt = keyboard_input.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+
+    if keyboard_input.just_pressed(KeyCode::Digit1) {
+        if !matches!(*tool, Tool::Despawn(_)) {
+            *tool = Tool::Despawn(EraseFilter::All);
+        }
+    } else if keyboard_input.just_pressed(KeyCode::KeyH) {
+        *tool = Tool::Heat(HEAT_TOOL_DEGREES);
+    } else if keyboard_input.just_pressed(KeyCode::KeyC) {
+        *tool = Tool::Heat(-HEAT_TOOL_DEGREES);
+    } else if let Some(kind) = kind {
+        match (&mut *tool, alt) {
+            (Tool::Despawn(filter), true) => filter.toggle(kind),
+            (Tool::Despawn(_) | Tool::Heat(_), false) => {
+                *tool = Tool::Spawn(kind, PaintMode::default())
             }
+            (Tool::Heat(_), true) => {}
+            (Tool::Spawn(_, mode), true) => *mode = PaintMode::Replace(kind),
+            (Tool::Spawn(particle_kind, _), false) => *particle_kind = kind,
+        }
+    }
 
-            // Mark the cell and its neighbors as active
-            for dy in y.saturating_sub(1)..=(y + 1) {
-                for dx in x.saturating_sub(1)..=(x + 1) {
-                    cell_world.active_cells.mark_active(dx, dy);
+    if keyboard_input.just_pressed(KeyCode::KeyM) {
+        match &mut *tool {
+            Tool::Despawn(filter) => *filter = EraseFilter::All,
+            Tool::Heat(_) => {}
+            Tool::Spawn(_, mode) => {
+                *mode = match mode {
+                    PaintMode::Overwrite => PaintMode::OnlyEmpty,
+                    _ => PaintMode::Overwrite,
                 }
             }
+        }
+    }
+
+    let shape = if keyboard_input.just_pressed(KeyCode::KeyB) {
+        ToolShape::Freehand
+    } else if keyboard_input.just_pressed(KeyCode::KeyL) {
+        ToolShape::Line
+    } else if keyboard_input.just_pressed(KeyCode::KeyR) {
+        ToolShape::Rectangle
+    } else if keyboard_input.just_pressed(KeyCode::KeyF) {
+        ToolShape::FloodFill
+    } else {
+        return;
+    };
+    *tool_shape = shape;
+    *paint_stroke = PaintStroke::default();
+}
 
-            #[cfg(feature = "debug")]
-            {
-                stats.spawned_particles += 1;
+/// Bevy [`Update`] system to save the world with F5, load it again with F9 and export it as an
+/// image with F12
+pub fn snapshot_keys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    snapshot_path: Res<SnapshotPath>,
+    mut save_events: EventWriter<SaveWorld>,
+    mut load_events: EventWriter<LoadWorld>,
+    mut export_events: EventWriter<ExportImage>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        save_events.send(SaveWorld(snapshot_path.0.clone()));
+    } else if keyboard_input.just_pressed(KeyCode::F9) {
+        load_events.send(LoadWorld(snapshot_path.0.clone()));
+    } else if keyboard_input.just_pressed(KeyCode::F12) {
+        export_events.send(ExportImage(snapshot_path.0.with_extension("png")));
+    }
+}
+
+/// Bevy [`Update`] system to export the [`FocusedWorld`] as an image when an [`ExportImage`]
+/// event is sent
+pub fn export_image(
+    mut export_events: EventReader<ExportImage>,
+    focused_world: Res<FocusedWorld>,
+    cell_worlds: Query<&CellWorld>,
+    image_palette: Res<ImagePalette>,
+) {
+    let Some(Ok(cell_world)) = focused_world.0.map(|entity| cell_worlds.get(entity)) else {
+        return;
+    };
+
+    for ExportImage(path) in export_events.read() {
+        let result = File::create(path)
+            .map_err(|error| error.to_string())
+            .and_then(|file| {
+                cell_world
+                    .export_png(BufWriter::new(file), &image_palette.palette)
+                    .map_err(|error| error.to_string())
+            });
+        match result {
+            Ok(()) => info!("Exported world to {}", path.display()),
+            Err(error) => error!("Could not export world to {}: {}", path.display(), error),
+        }
+    }
+}
+
+/// Bevy [`Update`] system to replace the grid of the [`FocusedWorld`] when an [`ImportImage`]
+/// event is sent. The texture of the world is recreated, as the size of the image may differ,
+/// and the strokes of its [`History`] are forgotten.
+pub fn import_image(
+    mut commands: Commands,
+    mut import_events: EventReader<ImportImage>,
+    focused_world: Res<FocusedWorld>,
+    mut cell_worlds: Query<(&mut CellWorld, &mut History)>,
+    world_textures: Query<(Entity, &Parent), With<WorldTexture>>,
+    image_palette: Res<ImagePalette>,
+) {
+    let Some(entity) = focused_world.0 else {
+        return;
+    };
+    let Ok((mut cell_world, mut history)) = cell_worlds.get_mut(entity) else {
+        return;
+    };
+
+    for ImportImage(path) in import_events.read() {
+        let result = File::open(path)
+            .map_err(|error| error.to_string())
+            .and_then(|file| {
+                cell_world
+                    .import_png(
+                        BufReader::new(file),
+                        &image_palette.palette,
+                        image_palette.unknown_colors,
+                    )
+                    .map_err(|error| error.to_string())
+            });
+        match result {
+            Ok(()) => {
+                history.clear_strokes();
+                refresh_view(&mut commands, entity, &world_textures);
+                info!("Imported world from {}", path.display());
             }
+            Err(error) => error!("Could not import world from {}: {}", path.display(), error),
         }
     }
 }
 
-/// Bevy [`Update`] system to switch between tools, selects tool based on number keys
-pub fn tool_switch(keyboard_input: ResMut<ButtonInput<KeyCode>>, mut tool: ResMut<Tool>) {
-    if keyboard_input.just_pressed(KeyCode::Digit1) {
-        *tool = Tool::Despawn;
-    } else if keyboard_input.just_pressed(KeyCode::Digit2) {
-        *tool = Tool::Spawn(ParticleKind::Sand);
-    } else if keyboard_input.just_pressed(KeyCode::Digit3) {
-        *tool = Tool::Spawn(ParticleKind::Water);
-    } else if keyboard_input.just_pressed(KeyCode::Digit4) {
-        *tool = Tool::Spawn(ParticleKind::Stone);
+/// Bevy [`Update`] system to save the [`FocusedWorld`] when a [`SaveWorld`] event is sent
+pub fn save_world(
+    mut save_events: EventReader<SaveWorld>,
+    focused_world: Res<FocusedWorld>,
+    cell_worlds: Query<&CellWorld>,
+) {
+    let Some(Ok(cell_world)) = focused_world.0.map(|entity| cell_worlds.get(entity)) else {
+        return;
+    };
+
+    for SaveWorld(path) in save_events.read() {
+        let result = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            cell_world.save(&mut writer)?;
+            writer.flush()
+        });
+        match result {
+            Ok(()) => info!("Saved world to {}", path.display()),
+            Err(error) => error!("Could not save world to {}: {}", path.display(), error),
+        }
+    }
+}
+
+/// Bevy [`Update`] system to replace the [`FocusedWorld`] when a [`LoadWorld`] event is sent.
+/// The texture of the world is recreated, as the size of the loaded world may differ, and the
+/// strokes of its [`History`] are forgotten.
+pub fn load_world(
+    mut commands: Commands,
+    mut load_events: EventReader<LoadWorld>,
+    focused_world: Res<FocusedWorld>,
+    mut cell_worlds: Query<(&mut CellWorld, &mut History)>,
+    world_textures: Query<(Entity, &Parent), With<WorldTexture>>,
+) {
+    let Some(entity) = focused_world.0 else {
+        return;
+    };
+    let Ok((mut cell_world, mut history)) = cell_worlds.get_mut(entity) else {
+        return;
+    };
+
+    for LoadWorld(path) in load_events.read() {
+        let result = File::open(path)
+            .map_err(SnapshotError::Io)
+            .and_then(|file| CellWorld::load(&mut BufReader::new(file)));
+        match result {
+            Ok(loaded) => {
+                *cell_world = loaded;
+                history.clear_strokes();
+                refresh_view(&mut commands, entity, &world_textures);
+                info!("Loaded world from {}", path.display());
+            }
+            Err(error) => error!("Could not load world from {}: {}", path.display(), error),
+        }
     }
 }
 
@@ -432,20 +1033,33 @@ pub fn setup_tool_text(mut commands: Commands, theme: Res<CatppuccinTheme>) {
 }
 
 /// Bevy [`Update`] system to update the text to display the current tool
-pub fn update_tool_text(tool: Res<Tool>, mut tool_text: Query<&mut Text, With<ToolText>>) {
+pub fn update_tool_text(
+    tool: Res<Tool>,
+    tool_shape: Res<ToolShape>,
+    brush: Res<PaintBrush>,
+    simulation_state: Res<State<SimulationState>>,
+    mut tool_text: Query<&mut Text, With<ToolText>>,
+) {
     if let Ok(mut tool_text) = tool_text.get_single_mut() {
-        tool_text.0 = format!("Tool: {}", *tool);
+        let text = format!(
+            "Tool: {} | {:?} | {:?} brush, radius {}, density {}",
+            *tool, *tool_shape, brush.shape, brush.radius, brush.density
+        );
+        tool_text.0 = match simulation_state.get() {
+            SimulationState::Running => text,
+            SimulationState::Paused => format!("{} (paused)", text),
+        };
     }
 }
 
-/// Bevy [`Update`] system to turn on/off debugging when the player pressed D
+/// Bevy [`Update`] system to turn on/off debugging when the player pressed F3
 #[cfg(feature = "debug")]
 pub fn toggle_debug(
     keyboard_input: ResMut<ButtonInput<KeyCode>>,
     mut debug: ResMut<DebugMenuState>,
     mut event_writer: EventWriter<ToggleDebugMenu>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::KeyD) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
         debug.toggle();
         event_writer.send(ToggleDebugMenu);
     }
@@ -468,11 +1082,11 @@ pub fn toggle_debug_menu(
     }
 }
 
-/// Bevy [`Update`] system to draw gizmos outlining the active cells
+/// Bevy [`Update`] system to draw gizmos outlining the awake chunks and their dirty rectangles
 #[cfg(feature = "debug")]
 pub fn draw_active_cells(
     mut gizmos: Gizmos,
-    cell_worlds: Query<&CellWorld>,
+    cell_worlds: Query<(&CellWorld, &GlobalTransform)>,
     theme: Res<CatppuccinTheme>,
     debug_menu_state: Res<DebugMenuState>,
 ) {
@@ -480,19 +1094,21 @@ pub fn draw_active_cells(
         return;
     }
 
-    for cell_world in cell_worlds.iter() {
-        for (x, y) in cell_world.active_cells.cells.iter() {
-            gizmos.rect_2d(
-                Vec2::new(
-                    (*x as f32 - cell_world.grid.dimensions().width as f32 / 2.0 + 0.5)
-                        * cell_world.resolution as f32,
-                    (*y as f32 - cell_world.grid.dimensions().height as f32 / 2.0 + 0.5)
-                        * cell_world.resolution as f32
-                        * -1.0,
-                ),
-                Vec2::new(cell_world.resolution as f32, cell_world.resolution as f32),
-                theme.flavor.red,
-            );
+    for (cell_world, transform) in cell_worlds.iter() {
+        let outline = |gizmos: &mut Gizmos, rect: DirtyRect, color: Color| {
+            let min = cell_world.cell_center(transform, rect.min.0 as isize, rect.min.1 as isize);
+            let max = cell_world.cell_center(transform, rect.max.0 as isize, rect.max.1 as isize);
+            let cell = cell_world.resolution as f32 * transform.scale().truncate();
+            gizmos.rect_2d((min + max) / 2.0, (max - min).abs() + cell, color);
+        };
+
+        for ((chunk_x, chunk_y), chunk) in cell_world.chunks.awake() {
+            if let Some(bounds) = cell_world.chunks.bounds(chunk_x, chunk_y) {
+                outline(&mut gizmos, bounds, theme.flavor.overlay0);
+            }
+            if let Some(dirty) = chunk.dirty {
+                outline(&mut gizmos, dirty, theme.flavor.red);
+            }
         }
     }
 }
@@ -584,3 +1200,131 @@ pub fn particle_count_text(
         existing_particle_count.0 = format!("Existing: {}", stats.existing_particles);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use cell_particle::particle::{Particle, ParticleKind};
+    use cell_simulation::ParticleCell;
+
+    use super::*;
+
+    /// An app with a focused 4x4 world that has a stroke to undo and one to redo
+    fn app_with_strokes() -> App {
+        let mut app = App::new();
+        let mut history = History::new();
+        for x in 0..2 {
+            let sand = ParticleCell {
+                content: Some(Particle::new(ParticleKind::SAND)),
+            };
+            history.record(x, 0, ParticleCell::default(), sand);
+            history.end_stroke();
+        }
+        let mut cell_world = CellWorld::new(4, 4);
+        assert!(history.undo(&mut cell_world));
+        let entity = app.world_mut().spawn((cell_world, history)).id();
+        app.insert_resource(FocusedWorld(Some(entity)));
+        app
+    }
+
+    /// Whether the focused world has any stroke to undo or redo
+    fn has_strokes(app: &mut App) -> bool {
+        let world = app.world_mut();
+        let (mut cell_world, mut history) = world
+            .query::<(&mut CellWorld, &mut History)>()
+            .single_mut(world);
+        history.redo(&mut cell_world) || history.undo(&mut cell_world)
+    }
+
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cell_engine_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_load_world_forgets_strokes() {
+        let path = temporary_path("load_world.snapshot");
+        CellWorld::new(3, 3)
+            .save(&mut File::create(&path).unwrap())
+            .unwrap();
+
+        let mut app = app_with_strokes();
+        app.add_event::<LoadWorld>().add_systems(Update, load_world);
+        app.world_mut().send_event(LoadWorld(path.clone()));
+        app.update();
+        std::fs::remove_file(&path).unwrap();
+
+        let world = app.world_mut();
+        let cell_world = world.query::<&CellWorld>().single(world);
+        assert_eq!(
+            cell_world.grid.dimensions(),
+            Dimensions {
+                width: 3,
+                height: 3
+            }
+        );
+        assert!(!has_strokes(&mut app));
+    }
+
+    #[test]
+    fn test_import_image_forgets_strokes() {
+        let path = temporary_path("import_image.png");
+        let image_palette = ImagePalette::default();
+        CellWorld::new(3, 3)
+            .export_png(File::create(&path).unwrap(), &image_palette.palette)
+            .unwrap();
+
+        let mut app = app_with_strokes();
+        app.insert_resource(image_palette)
+            .add_event::<ImportImage>()
+            .add_systems(Update, import_image);
+        app.world_mut().send_event(ImportImage(path.clone()));
+        app.update();
+        std::fs::remove_file(&path).unwrap();
+
+        let world = app.world_mut();
+        let cell_world = world.query::<&CellWorld>().single(world);
+        assert_eq!(
+            cell_world.grid.dimensions(),
+            Dimensions {
+                width: 3,
+                height: 3
+            }
+        );
+        assert!(!has_strokes(&mut app));
+    }
+
+    #[test]
+    fn test_worlds_with_their_own_rule_set_ignore_the_shared_rules() {
+        let mut app = App::new();
+        app.insert_resource(State::new(SimulationState::Running))
+            .insert_resource(SimulationControl::default())
+            .add_systems(Update, grid_update);
+        let sand_fall =
+            parse_rules("rule sand_fall\nin\n    s\n    .\nout\n    .\n    s\n").unwrap();
+        app.world_mut()
+            .spawn_batch(sand_fall.into_iter().map(CellRule::from));
+        let world_with_sand = || {
+            let mut cell_world = CellWorld::new(1, 2);
+            let sand = ParticleCell {
+                content: Some(Particle::new(ParticleKind::SAND)),
+            };
+            cell_world.set_cell(0, 0, sand).unwrap();
+            cell_world
+        };
+        let shared = app.world_mut().spawn(world_with_sand()).id();
+        // Its own rule set hasn't got any rules (yet), so nothing happens
+        let own = app
+            .world_mut()
+            .spawn((world_with_sand(), WorldRuleSet(Handle::default())))
+            .id();
+        app.update();
+
+        let sand_at_top = |entity| {
+            let cell_world = app.world().get::<CellWorld>(entity).unwrap();
+            cell_world.grid.get(0, 0).unwrap().content.is_some()
+        };
+        assert!(!sand_at_top(shared));
+        assert!(sand_at_top(own));
+    }
+}
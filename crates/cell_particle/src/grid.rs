@@ -12,11 +12,22 @@ impl std::fmt::Display for Dimensions {
     }
 }
 
-#[derive(Debug)]
-pub struct Window<T: Clone + std::fmt::Debug> {
-    pub grid: Grid<T>,
-    pub x: usize,
-    pub y: usize,
+/// The part of a rectangle that is covered by a grid, as reported by clipping copies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clip {
+    /// Top left corner of the covered part, in the coordinates of the grid
+    pub position: (usize, usize),
+    /// Top left corner of the covered part, relative to the top left corner of the rectangle
+    pub offset: (usize, usize),
+    /// Size of the covered part
+    pub dimensions: Dimensions,
+}
+
+impl Clip {
+    /// Whether no part of the rectangle was covered
+    pub fn is_empty(&self) -> bool {
+        self.dimensions.width == 0 || self.dimensions.height == 0
+    }
 }
 
 #[derive(Debug)]
@@ -27,25 +38,110 @@ pub enum GridError {
     SubgridBiggerThanGrid,
 }
 
+/// How positions outside of a [`Grid`] are treated, `W` is the kind of wall used by
+/// [`BoundaryMode::Solid`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundaryMode<W> {
+    /// Anything crossing the edge is skipped
+    #[default]
+    Skip,
+    /// The grid wraps around at the edges, like a torus
+    Wrap,
+    /// Everything outside the grid is a wall of the given kind
+    Solid(W),
+    /// Everything outside the grid is empty, and anything moved there is deleted
+    Void,
+}
+
+impl<W> BoundaryMode<W> {
+    /// Resolves the position `(x, y)` against a grid of the given dimensions
+    pub fn resolve(&self, x: isize, y: isize, dimensions: &Dimensions) -> Resolved<'_, W> {
+        let (width, height) = (dimensions.width as isize, dimensions.height as isize);
+        if (0..width).contains(&x) && (0..height).contains(&y) {
+            return Resolved::Inside(x as usize, y as usize);
+        }
+
+        match self {
+            BoundaryMode::Skip => Resolved::Outside,
+            BoundaryMode::Wrap if width > 0 && height > 0 => {
+                Resolved::Inside(x.rem_euclid(width) as usize, y.rem_euclid(height) as usize)
+            }
+            BoundaryMode::Wrap => Resolved::Outside,
+            BoundaryMode::Solid(wall) => Resolved::Solid(wall),
+            BoundaryMode::Void => Resolved::Void,
+        }
+    }
+}
+
+/// A position resolved against the edges of a grid by a [`BoundaryMode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolved<'a, W> {
+    /// The position is inside the grid, possibly after wrapping around
+    Inside(usize, usize),
+    /// The position is outside the grid, inside a wall
+    Solid(&'a W),
+    /// The position is outside the grid, in the void
+    Void,
+    /// The position is outside the grid, and should be skipped
+    Outside,
+}
+
+/// A 2D grid of cells, stored row-major in a single contiguous buffer
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Grid<T: Clone + std::fmt::Debug> {
-    pub cells: Vec<Vec<T>>,
+    cells: Vec<T>,
+    width: usize,
+    height: usize,
 }
 
 impl<T: Clone + std::fmt::Debug> Grid<T> {
+    /// Creates a grid from a vector of rows, all rows must be of equal length
     pub fn new(cells: Vec<Vec<T>>) -> Result<Self, GridError> {
-        let grid = Grid { cells };
+        if cells.is_empty() {
+            return Err(GridError::EmptyGrid);
+        }
+
+        let width = cells[0].len();
+        if cells.iter().any(|row| row.len() != width) {
+            return Err(GridError::UnequalRowLengths);
+        }
+
+        let height = cells.len();
+        let grid = Grid {
+            cells: cells.into_iter().flatten().collect(),
+            width,
+            height,
+        };
+        grid.validate()?;
+        Ok(grid)
+    }
+
+    /// Creates a grid from a row-major buffer of cells
+    pub fn from_flat(width: usize, height: usize, cells: Vec<T>) -> Result<Self, GridError> {
+        if cells.len() != width * height {
+            return Err(GridError::UnequalRowLengths);
+        }
+
+        let grid = Grid {
+            cells,
+            width,
+            height,
+        };
         grid.validate()?;
         Ok(grid)
     }
 
+    /// Creates a grid of the given dimensions with every cell set to `value`
+    pub fn filled(width: usize, height: usize, value: T) -> Result<Self, GridError> {
+        Self::from_flat(width, height, vec![value; width * height])
+    }
+
     pub fn validate(&self) -> Result<(), GridError> {
-        if self.cells.is_empty() {
+        if self.width == 0 || self.height == 0 {
             return Err(GridError::EmptyGrid);
         }
 
-        let expected_width = self.cells[0].len();
-        if self.cells.iter().any(|row| row.len() != expected_width) {
+        if self.cells.len() != self.width * self.height {
             return Err(GridError::UnequalRowLengths);
         }
 
@@ -53,25 +149,31 @@ impl<T: Clone + std::fmt::Debug> Grid<T> {
     }
 
     pub fn dimensions(&self) -> Dimensions {
-        let height = self.cells.len();
-        let width = self.cells.first().map_or(0, |row| row.len());
-        Dimensions { width, height }
+        Dimensions {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Index into the underlying buffer of the cell at `(x, y)`, if it is inside the grid
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| y * self.width + x)
     }
 
     pub fn get(&self, x: usize, y: usize) -> Result<&T, GridError> {
-        self.cells
-            .get(y)
+        self.index(x, y)
+            .map(|i| &self.cells[i])
             .ok_or(GridError::OutOfBounds)
-            .and_then(|row| row.get(x).ok_or(GridError::OutOfBounds))
     }
 
     pub fn get_mut(&mut self, x: usize, y: usize) -> Result<&mut T, GridError> {
-        self.cells
-            .get_mut(y)
+        self.index(x, y)
+            .map(|i| &mut self.cells[i])
             .ok_or(GridError::OutOfBounds)
-            .and_then(|row| row.get_mut(x).ok_or(GridError::OutOfBounds))
     }
 
+    /// Copies the rectangle at `(x, y)` with the given size out of the grid.
+    /// Fails with [`GridError::OutOfBounds`] if any part of the rectangle lies outside the grid.
     pub fn get_subgrid(
         &self,
         x: usize,
@@ -79,52 +181,252 @@ impl<T: Clone + std::fmt::Debug> Grid<T> {
         width: usize,
         height: usize,
     ) -> Result<Self, GridError> {
-        let subgrid = self
-            .cells
-            .iter()
-            .skip(y)
-            .take(height)
-            .map(|row| row.iter().skip(x).take(width).cloned().collect())
-            .collect();
-        Ok(Grid { cells: subgrid })
+        if width == 0 || height == 0 {
+            return Err(GridError::EmptyGrid);
+        }
+        if !self.contains_rect(x, y, width, height) {
+            return Err(GridError::OutOfBounds);
+        }
+
+        let mut cells = Vec::with_capacity(width * height);
+        for row in self.rows().skip(y).take(height) {
+            cells.extend_from_slice(&row[x..x + width]);
+        }
+        Ok(Grid {
+            cells,
+            width,
+            height,
+        })
+    }
+
+    /// Copies the part of the rectangle at `(x, y)` with the given size that lies inside the
+    /// grid. The returned [`Clip`] tells which part of the rectangle was copied.
+    /// Fails with [`GridError::OutOfBounds`] if the rectangle doesn't overlap the grid at all.
+    pub fn get_subgrid_clipped(
+        &self,
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+    ) -> Result<(Self, Clip), GridError> {
+        let clip = self.clip(x, y, width, height);
+        if clip.is_empty() {
+            return Err(GridError::OutOfBounds);
+        }
+
+        let (clip_x, clip_y) = clip.position;
+        let subgrid = self.get_subgrid(
+            clip_x,
+            clip_y,
+            clip.dimensions.width,
+            clip.dimensions.height,
+        )?;
+        Ok((subgrid, clip))
     }
 
+    /// Copies `grid` into this grid with its top left corner at `(x, y)`.
+    /// Fails with [`GridError::SubgridBiggerThanGrid`] if `grid` can never fit, and with
+    /// [`GridError::OutOfBounds`] if it doesn't fit at the given position.
     pub fn set_subgrid(&mut self, x: usize, y: usize, grid: Self) -> Result<(), GridError> {
         let Dimensions { width, height } = grid.dimensions();
 
-        if self.dimensions().width < width || self.dimensions().height < height {
+        if self.width < width || self.height < height {
             return Err(GridError::SubgridBiggerThanGrid);
         }
+        if !self.contains_rect(x, y, width, height) {
+            return Err(GridError::OutOfBounds);
+        }
 
-        for (i, row) in self.cells.iter_mut().skip(y).take(height).enumerate() {
-            for (j, cell) in row.iter_mut().skip(x).take(width).enumerate() {
-                *cell = grid.cells[i][j].clone();
-            }
+        for (i, row) in grid.rows().enumerate() {
+            let start = (y + i) * self.width + x;
+            self.cells[start..start + width].clone_from_slice(row);
         }
         Ok(())
     }
 
+    /// Copies the part of `grid` that lands inside this grid when its top left corner is
+    /// placed at `(x, y)`, anything hanging over the edges is dropped.
+    /// The returned [`Clip`] tells which part of `grid` was copied, it is empty if nothing was.
+    pub fn blit_clipped(&mut self, x: isize, y: isize, grid: &Self) -> Clip {
+        let clip = self.clip(x, y, grid.width, grid.height);
+        let (clip_x, clip_y) = clip.position;
+        let (offset_x, offset_y) = clip.offset;
+        let Dimensions { width, height } = clip.dimensions;
+        if clip.is_empty() {
+            return clip;
+        }
+
+        for (i, row) in grid.rows().skip(offset_y).take(height).enumerate() {
+            let start = (clip_y + i) * self.width + clip_x;
+            self.cells[start..start + width].clone_from_slice(&row[offset_x..offset_x + width]);
+        }
+        clip
+    }
+
+    /// Whether the rectangle at `(x, y)` with the given size lies entirely inside the grid
+    pub fn contains_rect(&self, x: usize, y: usize, width: usize, height: usize) -> bool {
+        x.checked_add(width)
+            .is_some_and(|right| right <= self.width)
+            && y.checked_add(height)
+                .is_some_and(|bottom| bottom <= self.height)
+    }
+
+    /// Intersects the rectangle at `(x, y)` with the given size with the grid
+    pub fn clip(&self, x: isize, y: isize, width: usize, height: usize) -> Clip {
+        fn clip_axis(start: isize, length: usize, bound: usize) -> (usize, usize, usize) {
+            let end = start
+                .saturating_add_unsigned(length)
+                .clamp(0, bound as isize) as usize;
+            let clipped_start = start.clamp(0, bound as isize) as usize;
+            let offset = ((clipped_start as isize - start) as usize).min(length);
+            (clipped_start, offset, end.saturating_sub(clipped_start))
+        }
+
+        let (clip_x, offset_x, clip_width) = clip_axis(x, width, self.width);
+        let (clip_y, offset_y, clip_height) = clip_axis(y, height, self.height);
+        Clip {
+            position: (clip_x, clip_y),
+            offset: (offset_x, offset_y),
+            dimensions: Dimensions {
+                width: clip_width,
+                height: clip_height,
+            },
+        }
+    }
+
+    /// Resolves a position, which may lie outside the grid, using the given [`BoundaryMode`]
+    pub fn resolve<'m, W>(&self, x: isize, y: isize, mode: &'m BoundaryMode<W>) -> Resolved<'m, W> {
+        mode.resolve(x, y, &self.dimensions())
+    }
+
+    /// A borrowed, zero-copy view of the rectangle at `(x, y)` with the given size
+    pub fn view(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<GridView<'_, T>, GridError> {
+        if !self.contains_rect(x, y, width, height) {
+            return Err(GridError::OutOfBounds);
+        }
+
+        Ok(GridView {
+            grid: self,
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// A borrowed, zero-copy mutable view of the rectangle at `(x, y)` with the given size
+    pub fn view_mut(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<GridViewMut<'_, T>, GridError> {
+        if !self.contains_rect(x, y, width, height) {
+            return Err(GridError::OutOfBounds);
+        }
+
+        Ok(GridViewMut {
+            grid: self,
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// A view of the whole grid
+    pub fn as_view(&self) -> GridView<'_, T> {
+        GridView {
+            grid: self,
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// The cells of the grid as a row-major slice
+    pub fn as_slice(&self) -> &[T] {
+        &self.cells
+    }
+
+    /// The cells of the grid as a mutable row-major slice
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.cells
+    }
+
+    /// Iterates over the rows of the grid, from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.cells.chunks_exact(self.width)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &T> {
-        self.cells.iter().flat_map(|row| row.iter())
+        self.cells.iter()
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
-        self.cells.iter_mut().flat_map(|row| row.iter_mut())
+        self.cells.iter_mut()
+    }
+
+    /// Builds a new grid of the given size, where the cell at `(x, y)` is copied from the cell
+    /// of this grid at `source(x, y)`
+    fn remap(
+        &self,
+        width: usize,
+        height: usize,
+        source: impl Fn(usize, usize) -> (usize, usize),
+    ) -> Self {
+        let cells = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (source_x, source_y) = source(x, y);
+                self.cells[source_y * self.width + source_x].clone()
+            })
+            .collect();
+        Grid {
+            cells,
+            width,
+            height,
+        }
+    }
+
+    /// Mirrors the grid left to right
+    pub fn flip_x(&self) -> Self {
+        self.remap(self.width, self.height, |x, y| (self.width - 1 - x, y))
+    }
+
+    /// Mirrors the grid top to bottom
+    pub fn flip_y(&self) -> Self {
+        self.remap(self.width, self.height, |x, y| (x, self.height - 1 - y))
+    }
+
+    /// Rotates the grid 90 degrees clockwise
+    pub fn rotate_cw(&self) -> Self {
+        self.remap(self.height, self.width, |x, y| (y, self.height - 1 - x))
+    }
+
+    /// Mirrors the grid along its main diagonal, swapping rows and columns
+    pub fn transpose(&self) -> Self {
+        self.remap(self.height, self.width, |x, y| (y, x))
     }
 
     /// An windowed iterator that iterates over the grid in 2D windows of the given dimensions
     pub fn windowed(
         &'_ self,
         window_dimensions: Dimensions,
-    ) -> impl Iterator<Item = Window<T>> + '_ {
+    ) -> impl Iterator<Item = GridView<'_, T>> + '_ {
         let Dimensions { width, height } = self.dimensions();
-        (0..=height - window_dimensions.height).flat_map(move |y| {
-            (0..=width - window_dimensions.width).map(move |x| Window {
-                grid: self
-                    .get_subgrid(x, y, window_dimensions.width, window_dimensions.height)
-                    .unwrap(),
-                x,
-                y,
+        (0..=height.saturating_sub(window_dimensions.height)).flat_map(move |y| {
+            (0..=width.saturating_sub(window_dimensions.width)).filter_map(move |x| {
+                self.view(x, y, window_dimensions.width, window_dimensions.height)
+                    .ok()
             })
         })
     }
@@ -137,12 +439,591 @@ impl<T: Clone + std::fmt::Debug> From<Vec<Vec<T>>> for Grid<T> {
     }
 }
 
-/// Display for grid as a matrix of strings
-impl<T: Clone + std::fmt::Debug> std::fmt::Display for Grid<T> {
+/// Display for grid as a matrix of cells, one row per line and the cells of a row separated by
+/// spaces, so cells shouldn't contain any whitespace of their own
+impl<T: Clone + std::fmt::Debug + std::fmt::Display> std::fmt::Display for Grid<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for row in &self.cells {
-            write!(f, "{:?}\n", row)?;
+        for row in self.rows() {
+            let cells: Vec<_> = row.iter().map(|cell| cell.to_string()).collect();
+            writeln!(f, "{}", cells.join(" "))?;
         }
         Ok(())
     }
 }
+
+/// Error type for parsing a [`Grid`], `E` is the error of parsing a single cell.
+/// Rows and columns are counted from 1, blank lines are skipped and not counted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseGridError<E> {
+    /// There are no rows
+    Empty,
+    /// A row of a different length than the first one
+    UnequalRowLengths { row: usize },
+    /// A cell that couldn't be parsed
+    Cell { row: usize, column: usize, error: E },
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for ParseGridError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseGridError::Empty => write!(f, "The grid is empty"),
+            ParseGridError::UnequalRowLengths { row } => {
+                write!(f, "Row {} differs in length from the first row", row)
+            }
+            ParseGridError::Cell { row, column, error } => {
+                write!(f, "Row {}, column {}: {}", row, column, error)
+            }
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for ParseGridError<E> {}
+
+/// Parses a grid the way it is displayed
+impl<T: Clone + std::fmt::Debug + std::str::FromStr> std::str::FromStr for Grid<T> {
+    type Err = ParseGridError<T::Err>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rows: Vec<Vec<T>> = Vec::new();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let row = rows.len() + 1;
+            let cells = line
+                .split_whitespace()
+                .enumerate()
+                .map(|(column, cell)| {
+                    cell.parse().map_err(|error| ParseGridError::Cell {
+                        row,
+                        column: column + 1,
+                        error,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if rows.first().is_some_and(|first| first.len() != cells.len()) {
+                return Err(ParseGridError::UnequalRowLengths { row });
+            }
+            rows.push(cells);
+        }
+        Grid::new(rows).map_err(|_| ParseGridError::Empty)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Clone + std::fmt::Debug + std::fmt::Display> serde::Serialize for Grid<T> {
+    /// Serialised the same way it is displayed
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for Grid<T>
+where
+    T: Clone + std::fmt::Debug + std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let grid = <String as serde::Deserialize>::deserialize(deserializer)?;
+        grid.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A borrowed window into a rectangle of a [`Grid`], which does not copy any cells
+#[derive(Debug)]
+pub struct GridView<'a, T: Clone + std::fmt::Debug> {
+    grid: &'a Grid<T>,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<T: Clone + std::fmt::Debug> Clone for GridView<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Clone + std::fmt::Debug> Copy for GridView<'_, T> {}
+
+impl<'a, T: Clone + std::fmt::Debug> GridView<'a, T> {
+    /// Position of the top left corner of the view in the underlying grid
+    pub fn position(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        Dimensions {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Get the cell at `(x, y)`, relative to the top left corner of the view
+    pub fn get(&self, x: usize, y: usize) -> Result<&'a T, GridError> {
+        if x >= self.width || y >= self.height {
+            return Err(GridError::OutOfBounds);
+        }
+        self.grid.get(self.x + x, self.y + y)
+    }
+
+    /// Iterates over the rows of the view, from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &'a [T]> + 'a {
+        let Self { x, width, .. } = *self;
+        self.grid
+            .rows()
+            .skip(self.y)
+            .take(self.height)
+            .map(move |row| &row[x..x + width])
+    }
+
+    /// Iterates over the cells of the view in row-major order
+    pub fn iter(&self) -> impl Iterator<Item = &'a T> + 'a {
+        self.rows().flatten()
+    }
+
+    /// Copy the viewed cells into an owned [`Grid`], fails if the view is empty
+    pub fn to_grid(&self) -> Result<Grid<T>, GridError> {
+        self.grid
+            .get_subgrid(self.x, self.y, self.width, self.height)
+    }
+}
+
+impl<'a, T: Clone + std::fmt::Debug> From<&'a Grid<T>> for GridView<'a, T> {
+    fn from(grid: &'a Grid<T>) -> Self {
+        grid.as_view()
+    }
+}
+
+/// A mutably borrowed window into a rectangle of a [`Grid`], writes go straight to the grid
+#[derive(Debug)]
+pub struct GridViewMut<'a, T: Clone + std::fmt::Debug> {
+    grid: &'a mut Grid<T>,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<T: Clone + std::fmt::Debug> GridViewMut<'_, T> {
+    /// Position of the top left corner of the view in the underlying grid
+    pub fn position(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        Dimensions {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Get the cell at `(x, y)`, relative to the top left corner of the view
+    pub fn get(&self, x: usize, y: usize) -> Result<&T, GridError> {
+        if x >= self.width || y >= self.height {
+            return Err(GridError::OutOfBounds);
+        }
+        self.grid.get(self.x + x, self.y + y)
+    }
+
+    /// Get the cell at `(x, y)` mutably, relative to the top left corner of the view
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Result<&mut T, GridError> {
+        if x >= self.width || y >= self.height {
+            return Err(GridError::OutOfBounds);
+        }
+        self.grid.get_mut(self.x + x, self.y + y)
+    }
+
+    /// Reborrow as an immutable [`GridView`]
+    pub fn as_view(&self) -> GridView<'_, T> {
+        GridView {
+            grid: self.grid,
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Iterates over the rows of the view, from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.as_view().rows()
+    }
+
+    /// Iterates mutably over the rows of the view, from top to bottom
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        let Self { x, width, .. } = *self;
+        self.grid
+            .cells
+            .chunks_exact_mut(self.grid.width)
+            .skip(self.y)
+            .take(self.height)
+            .map(move |row| &mut row[x..x + width])
+    }
+
+    /// Iterates over the cells of the view in row-major order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.as_view().iter()
+    }
+
+    /// Iterates mutably over the cells of the view in row-major order
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.rows_mut().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(width: usize, height: usize) -> Grid<usize> {
+        Grid::from_flat(width, height, (0..width * height).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_new_is_row_major() {
+        let grid = Grid::new(vec![vec![0, 1, 2], vec![3, 4, 5]]).unwrap();
+        assert_eq!(grid, numbered(3, 2));
+        assert_eq!(
+            grid.dimensions(),
+            Dimensions {
+                width: 3,
+                height: 2
+            }
+        );
+        assert_eq!(*grid.get(2, 0).unwrap(), 2);
+        assert_eq!(*grid.get(0, 1).unwrap(), 3);
+        assert!(grid.get(3, 0).is_err());
+        assert!(grid.get(0, 2).is_err());
+    }
+
+    #[test]
+    fn test_display_and_parse() {
+        let grid = numbered(3, 2);
+        assert_eq!(grid.to_string(), "0 1 2\n3 4 5\n");
+        assert_eq!(grid.to_string().parse(), Ok(grid));
+        assert_eq!("\n 0  1\n\n2 3 \n".parse(), Ok(numbered(2, 2)));
+
+        assert_eq!("".parse::<Grid<usize>>(), Err(ParseGridError::Empty));
+        assert_eq!(
+            "0 1\n2\n".parse::<Grid<usize>>(),
+            Err(ParseGridError::UnequalRowLengths { row: 2 })
+        );
+        assert!(matches!(
+            "0 1\n2 x\n".parse::<Grid<usize>>(),
+            Err(ParseGridError::Cell {
+                row: 2,
+                column: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_grids() {
+        assert!(matches!(Grid::<u8>::new(vec![]), Err(GridError::EmptyGrid)));
+        assert!(matches!(
+            Grid::<u8>::new(vec![vec![]]),
+            Err(GridError::EmptyGrid)
+        ));
+        assert!(matches!(
+            Grid::new(vec![vec![1, 2], vec![3]]),
+            Err(GridError::UnequalRowLengths)
+        ));
+        assert!(matches!(
+            Grid::from_flat(2, 2, vec![1, 2, 3]),
+            Err(GridError::UnequalRowLengths)
+        ));
+    }
+
+    #[test]
+    fn test_subgrid_round_trip() {
+        let mut grid = numbered(4, 4);
+        let subgrid = grid.get_subgrid(1, 1, 2, 2).unwrap();
+        assert_eq!(subgrid, Grid::new(vec![vec![5, 6], vec![9, 10]]).unwrap());
+
+        grid.set_subgrid(2, 2, subgrid).unwrap();
+        assert_eq!(*grid.get(2, 2).unwrap(), 5);
+        assert_eq!(*grid.get(3, 3).unwrap(), 10);
+        assert_eq!(*grid.get(1, 1).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_transforms() {
+        // 0 1 2
+        // 3 4 5
+        let grid = numbered(3, 2);
+        assert_eq!(
+            grid.flip_x(),
+            Grid::new(vec![vec![2, 1, 0], vec![5, 4, 3]]).unwrap()
+        );
+        assert_eq!(
+            grid.flip_y(),
+            Grid::new(vec![vec![3, 4, 5], vec![0, 1, 2]]).unwrap()
+        );
+        assert_eq!(
+            grid.rotate_cw(),
+            Grid::new(vec![vec![3, 0], vec![4, 1], vec![5, 2]]).unwrap()
+        );
+        assert_eq!(
+            grid.transpose(),
+            Grid::new(vec![vec![0, 3], vec![1, 4], vec![2, 5]]).unwrap()
+        );
+
+        // A few identities of the symmetries of the square
+        assert_eq!(grid.flip_x().flip_x(), grid);
+        assert_eq!(grid.rotate_cw().rotate_cw(), grid.flip_x().flip_y());
+        assert_eq!(grid.rotate_cw().flip_x(), grid.transpose());
+        assert_eq!(grid.rotate_cw().rotate_cw().rotate_cw().rotate_cw(), grid);
+    }
+
+    #[test]
+    fn test_subgrid_out_of_bounds() {
+        let mut grid = numbered(4, 3);
+        assert!(matches!(
+            grid.get_subgrid(3, 0, 2, 1),
+            Err(GridError::OutOfBounds)
+        ));
+        assert!(matches!(
+            grid.get_subgrid(0, 2, 1, 2),
+            Err(GridError::OutOfBounds)
+        ));
+        assert!(matches!(
+            grid.get_subgrid(usize::MAX, 0, 2, 1),
+            Err(GridError::OutOfBounds)
+        ));
+        assert!(matches!(
+            grid.set_subgrid(3, 0, numbered(2, 2)),
+            Err(GridError::OutOfBounds)
+        ));
+        assert!(matches!(
+            grid.set_subgrid(0, 0, numbered(5, 1)),
+            Err(GridError::SubgridBiggerThanGrid)
+        ));
+        // Failed writes must leave the grid untouched
+        assert_eq!(grid, numbered(4, 3));
+    }
+
+    #[test]
+    fn test_clipped_copies() {
+        let grid = numbered(4, 3);
+        let (subgrid, clip) = grid.get_subgrid_clipped(-1, 1, 3, 5).unwrap();
+        assert_eq!(subgrid, Grid::new(vec![vec![4, 5], vec![8, 9]]).unwrap());
+        assert_eq!(clip.position, (0, 1));
+        assert_eq!(clip.offset, (1, 0));
+        assert!(grid.get_subgrid_clipped(4, 0, 1, 1).is_err());
+
+        let mut target = Grid::filled(3, 3, 0).unwrap();
+        let clip = target.blit_clipped(1, -1, &numbered(3, 2));
+        assert_eq!(
+            target,
+            Grid::new(vec![vec![0, 3, 4], vec![0, 0, 0], vec![0, 0, 0]]).unwrap()
+        );
+        assert_eq!(clip.offset, (0, 1));
+        assert!(target.blit_clipped(-3, 0, &numbered(3, 2)).is_empty());
+    }
+
+    /// Reference implementation of a clipping copy, one cell at a time
+    fn blit_reference(target: &mut Grid<usize>, x: isize, y: isize, source: &Grid<usize>) {
+        for (i, cell) in source.iter().enumerate() {
+            let (sx, sy) = ((i % source.width) as isize, (i / source.width) as isize);
+            if let Resolved::Inside(tx, ty) =
+                target.resolve(x + sx, y + sy, &BoundaryMode::<()>::Skip)
+            {
+                *target.get_mut(tx, ty).unwrap() = *cell;
+            }
+        }
+    }
+
+    /// Checks every copy operation against the reference for one rectangle
+    fn check_rectangle(grid: &Grid<usize>, x: isize, y: isize, width: usize, height: usize) {
+        let Dimensions {
+            width: grid_width,
+            height: grid_height,
+        } = grid.dimensions();
+        let inside = x >= 0
+            && y >= 0
+            && x as usize + width <= grid_width
+            && y as usize + height <= grid_height;
+
+        if x >= 0 && y >= 0 {
+            let subgrid = grid.get_subgrid(x as usize, y as usize, width, height);
+            assert_eq!(subgrid.is_ok(), inside && width > 0 && height > 0);
+            if let Ok(subgrid) = subgrid {
+                for (i, cell) in subgrid.iter().enumerate() {
+                    let (sx, sy) = (x as usize + i % width, y as usize + i / width);
+                    assert_eq!(cell, grid.get(sx, sy).unwrap());
+                }
+            }
+        }
+
+        let clip = grid.clip(x, y, width, height);
+        match grid.get_subgrid_clipped(x, y, width, height) {
+            Ok((subgrid, returned_clip)) => {
+                assert_eq!(returned_clip, clip);
+                assert_eq!(subgrid.dimensions(), clip.dimensions);
+                assert!(subgrid.iter().all(|cell| {
+                    let (cx, cy) = (cell % grid_width, cell / grid_width);
+                    cx as isize >= x
+                        && cy as isize >= y
+                        && ((cx as isize - x) as usize) < width
+                        && ((cy as isize - y) as usize) < height
+                }));
+            }
+            Err(_) => assert!(clip.is_empty()),
+        }
+
+        if width == 0 || height == 0 {
+            return;
+        }
+        let source = Grid::from_flat(
+            width,
+            height,
+            (0..width * height).map(|i| 1000 + i).collect(),
+        )
+        .unwrap();
+
+        let mut expected = grid.clone();
+        blit_reference(&mut expected, x, y, &source);
+        let mut clipped = grid.clone();
+        let clip = clipped.blit_clipped(x, y, &source);
+        assert_eq!(clipped, expected);
+        assert_eq!(
+            clipped.iter().filter(|cell| **cell >= 1000).count(),
+            clip.dimensions.width * clip.dimensions.height
+        );
+
+        if x >= 0 && y >= 0 {
+            let mut set = grid.clone();
+            let result = set.set_subgrid(x as usize, y as usize, source);
+            assert_eq!(result.is_ok(), inside);
+            assert_eq!(set, if inside { expected } else { grid.clone() });
+        }
+    }
+
+    #[test]
+    fn test_every_rectangle_of_small_grid() {
+        let grid = numbered(4, 3);
+        for x in -6..8 {
+            for y in -6..8 {
+                for width in 0..7 {
+                    for height in 0..7 {
+                        check_rectangle(&grid, x, y, width, height);
+                    }
+                }
+            }
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_rectangles_match_reference(
+            grid_width in 1usize..24,
+            grid_height in 1usize..24,
+            x in -32isize..32,
+            y in -32isize..32,
+            width in 0usize..32,
+            height in 0usize..32,
+        ) {
+            check_rectangle(&numbered(grid_width, grid_height), x, y, width, height);
+        }
+    }
+
+    #[test]
+    fn test_view_does_not_copy() {
+        let grid = numbered(4, 3);
+        let view = grid.view(1, 1, 2, 2).unwrap();
+        assert_eq!(view.position(), (1, 1));
+        assert_eq!(
+            view.dimensions(),
+            Dimensions {
+                width: 2,
+                height: 2
+            }
+        );
+        assert!(std::ptr::eq(
+            view.get(0, 0).unwrap(),
+            grid.get(1, 1).unwrap()
+        ));
+        assert_eq!(*view.get(1, 1).unwrap(), 10);
+        assert!(view.get(2, 0).is_err());
+        assert!(grid.view(3, 0, 2, 1).is_err());
+    }
+
+    #[test]
+    fn test_view_rows_and_iter() {
+        let grid = numbered(4, 3);
+        let view = grid.view(1, 1, 2, 2).unwrap();
+        let rows: Vec<_> = view.rows().collect();
+        assert_eq!(rows, vec![&[5, 6][..], &[9, 10][..]]);
+        assert_eq!(view.iter().copied().collect::<Vec<_>>(), vec![5, 6, 9, 10]);
+        assert_eq!(GridView::from(&grid).iter().count(), 12);
+    }
+
+    #[test]
+    fn test_view_mut_writes_through() {
+        let mut grid = numbered(4, 3);
+        let mut view = grid.view_mut(2, 1, 2, 2).unwrap();
+        assert_eq!(
+            view.dimensions(),
+            Dimensions {
+                width: 2,
+                height: 2
+            }
+        );
+        *view.get_mut(0, 0).unwrap() = 100;
+        for cell in view.iter_mut() {
+            *cell += 1;
+        }
+        assert!(view.get_mut(2, 0).is_err());
+        assert_eq!(
+            view.iter().copied().collect::<Vec<_>>(),
+            vec![101, 8, 11, 12]
+        );
+
+        assert_eq!(*grid.get(2, 1).unwrap(), 101);
+        assert_eq!(*grid.get(1, 1).unwrap(), 5);
+        assert_eq!(*grid.get(3, 2).unwrap(), 12);
+        assert!(grid.view_mut(3, 2, 2, 2).is_err());
+    }
+
+    #[test]
+    fn test_boundary_modes() {
+        let grid = numbered(3, 2);
+        let skip = BoundaryMode::<char>::Skip;
+        let wall = BoundaryMode::Solid('#');
+
+        assert_eq!(grid.resolve(2, 1, &skip), Resolved::Inside(2, 1));
+        assert_eq!(grid.resolve(3, 1, &skip), Resolved::Outside);
+        assert_eq!(grid.resolve(-1, 0, &wall), Resolved::Solid(&'#'));
+        assert_eq!(
+            grid.resolve(0, 2, &BoundaryMode::<char>::Void),
+            Resolved::Void
+        );
+
+        let wrap = BoundaryMode::<char>::Wrap;
+        assert_eq!(grid.resolve(3, 0, &wrap), Resolved::Inside(0, 0));
+        assert_eq!(grid.resolve(-1, -1, &wrap), Resolved::Inside(2, 1));
+        assert_eq!(grid.resolve(-4, 5, &wrap), Resolved::Inside(2, 1));
+    }
+
+    #[test]
+    fn test_windowed() {
+        let grid = numbered(3, 3);
+        let windows: Vec<_> = grid
+            .windowed(Dimensions {
+                width: 2,
+                height: 2,
+            })
+            .map(|view| view.position())
+            .collect();
+        assert_eq!(windows, vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert_eq!(
+            grid.windowed(Dimensions {
+                width: 4,
+                height: 1
+            })
+            .count(),
+            0
+        );
+    }
+}
@@ -1,3 +1,30 @@
+//! The particles, grids and rules of a cell world. Particles, states, occupancies, grids and rules
+//! can all be written as text with [`std::fmt::Display`] and read back with [`std::str::FromStr`],
+//! and with the `serde` feature they are serialised as that same text.
+
+/// Implements `serde`'s traits for types through their `Display` and `FromStr`, so they're
+/// serialised as the same text everywhere. Does nothing without the `serde` feature.
+macro_rules! serde_as_str {
+    ($($ty:ty),*) => {
+        $(
+            #[cfg(feature = "serde")]
+            impl serde::Serialize for $ty {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+
+            #[cfg(feature = "serde")]
+            impl<'de> serde::Deserialize<'de> for $ty {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+                    text.parse().map_err(serde::de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
 pub mod particle;
 pub mod rule;
 pub mod grid;
@@ -1,14 +1,39 @@
-use strum_macros::EnumIter;
+use super::{materials, ParseParticleError};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
-pub enum ParticleKind {
-    Sand,
-    Water,
-    Stone,
+/// The id of a material in a [`super::MaterialRegistry`], which tells you everything else about it.
+/// The built-in materials have constants of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParticleKind(pub u8);
+
+impl ParticleKind {
+    pub const SAND: ParticleKind = ParticleKind(0);
+    pub const WATER: ParticleKind = ParticleKind(1);
+    pub const STONE: ParticleKind = ParticleKind(2);
+    pub const ICE: ParticleKind = ParticleKind(3);
+    pub const STEAM: ParticleKind = ParticleKind(4);
+    pub const LAVA: ParticleKind = ParticleKind(5);
+    pub const OIL: ParticleKind = ParticleKind(6);
 }
 
 impl std::fmt::Display for ParticleKind {
+    /// The name of the material in the registry, or its id if it isn't registered
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        match materials().get(*self) {
+            Some(material) => write!(f, "{}", material.name),
+            None => write!(f, "#{}", self.0),
+        }
+    }
+}
+
+impl std::str::FromStr for ParticleKind {
+    type Err = ParseParticleError;
+
+    /// Parses the name of a registered material, ignoring case, or an id written as `#3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || ParseParticleError::UnknownMaterial(s.to_string());
+        match s.strip_prefix('#') {
+            Some(id) => id.parse().map(ParticleKind).map_err(|_| unknown()),
+            None => materials().by_name(s).ok_or_else(unknown),
+        }
     }
 }
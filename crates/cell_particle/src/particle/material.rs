@@ -0,0 +1,500 @@
+use std::collections::BTreeSet;
+use std::sync::{LazyLock, RwLock, RwLockReadGuard};
+
+use super::{ParticleKind, ParticleState};
+
+/// Symbols rule files and worlds use for things other than materials
+const RESERVED_SYMBOLS: [char; 4] = ['.', '*', '?', '~'];
+
+/// Everything there is to know about a kind of particle, registered in a [`MaterialRegistry`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    /// The name rules and tools refer to the material by, unique within a registry
+    pub name: String,
+    /// The character standing for the material in rule files and worlds
+    pub symbol: Option<char>,
+    /// Colour of the material as RGB
+    pub color: [u8; 3],
+    /// How much the colour varies from particle to particle, from 0 to 1
+    pub color_variance: f32,
+    /// The state new particles of the material start out with
+    pub state: ParticleState,
+    /// Free-form tags such as `powder` or `liquid`, for rules and tools to group materials by
+    pub tags: BTreeSet<String>,
+    /// The key that picks the material as the tool, a digit or a letter
+    pub hotkey: Option<char>,
+    /// How readily heat flows through the material, from 0 for none to 1
+    pub conductivity: f32,
+    /// What the material turns into when it gets too hot or too cold, the first that applies wins
+    pub transitions: Vec<PhaseTransition>,
+}
+
+impl Material {
+    /// A grey material with the default state, no symbol, tags or hotkey
+    pub fn new(name: impl Into<String>) -> Self {
+        Material {
+            name: name.into(),
+            symbol: None,
+            color: [0x80, 0x80, 0x80],
+            color_variance: 0.0,
+            state: ParticleState::default(),
+            tags: BTreeSet::new(),
+            hotkey: None,
+            conductivity: 0.5,
+            transitions: Vec::new(),
+        }
+    }
+
+    pub fn with_symbol(mut self, symbol: char) -> Self {
+        self.symbol = Some(symbol);
+        self
+    }
+
+    pub fn with_color(mut self, color: [u8; 3]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_color_variance(mut self, color_variance: f32) -> Self {
+        self.color_variance = color_variance.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_state(mut self, state: ParticleState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.insert(tag.into());
+        self
+    }
+
+    pub fn with_hotkey(mut self, hotkey: char) -> Self {
+        self.hotkey = Some(hotkey.to_ascii_lowercase());
+        self
+    }
+
+    pub fn with_conductivity(mut self, conductivity: f32) -> Self {
+        self.conductivity = conductivity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Turns particles of the material into `into` once they are hotter than `temperature`
+    pub fn with_transition_above(mut self, temperature: f32, into: ParticleKind) -> Self {
+        self.transitions.push(PhaseTransition {
+            when: Threshold::Above(temperature),
+            into,
+        });
+        self
+    }
+
+    /// Turns particles of the material into `into` once they are colder than `temperature`
+    pub fn with_transition_below(mut self, temperature: f32, into: ParticleKind) -> Self {
+        self.transitions.push(PhaseTransition {
+            when: Threshold::Below(temperature),
+            into,
+        });
+        self
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    /// What a particle of the material at the given temperature turns into, if anything
+    pub fn transition_at(&self, temperature: f32) -> Option<ParticleKind> {
+        self.transitions
+            .iter()
+            .find(|transition| transition.when.is_met_by(temperature))
+            .map(|transition| transition.into)
+    }
+
+    /// The colour of a particle of the material at the given cell, varied by up to
+    /// [`Material::color_variance`]. The same cell always gets the same colour.
+    pub fn color_at(&self, x: usize, y: usize) -> [u8; 3] {
+        if self.color_variance == 0.0 {
+            return self.color;
+        }
+        // A cheap integer hash of the position, good enough to break up flat areas of colour
+        let mut hash = (x as u32).wrapping_mul(0x9e37_79b1) ^ (y as u32).wrapping_mul(0x85eb_ca77);
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x2c1b_3c6d);
+        hash ^= hash >> 12;
+        let shade = (hash & 0xff) as f32 / 255.0 * 2.0 - 1.0;
+        let factor = 1.0 + shade * self.color_variance;
+        self.color
+            .map(|channel| (channel as f32 * factor).round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+/// A value a condition is met beyond, such as the temperature in degrees Celsius a
+/// [`PhaseTransition`] happens at, or the pressure in kilo Pascals a rule needs to apply
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Above(f32),
+    Below(f32),
+}
+
+impl Threshold {
+    pub fn is_met_by(&self, value: f32) -> bool {
+        match *self {
+            Threshold::Above(threshold) => value > threshold,
+            Threshold::Below(threshold) => value < threshold,
+        }
+    }
+}
+
+/// A material turning into another, like water boiling into steam. The particle keeps its
+/// temperature and takes the rest of its state from the new material.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhaseTransition {
+    pub when: Threshold,
+    pub into: ParticleKind,
+}
+
+/// Error type for registering a [`Material`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaterialError {
+    /// A material with the same name is already registered
+    DuplicateName(String),
+    /// Another material already uses the symbol
+    DuplicateSymbol(char),
+    /// Another material already uses the hotkey
+    DuplicateHotkey(char),
+    /// The symbol means something else in rule files and worlds
+    ReservedSymbol(char),
+    /// Every [`ParticleKind`] id is taken
+    Full,
+}
+
+impl std::fmt::Display for MaterialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaterialError::DuplicateName(name) => {
+                write!(f, "A material named `{}` is already registered", name)
+            }
+            MaterialError::DuplicateSymbol(symbol) => {
+                write!(f, "Symbol `{}` is already used by another material", symbol)
+            }
+            MaterialError::DuplicateHotkey(hotkey) => {
+                write!(f, "Hotkey `{}` is already used by another material", hotkey)
+            }
+            MaterialError::ReservedSymbol(symbol) => {
+                write!(
+                    f,
+                    "Symbol `{}` is reserved and can't stand for a material",
+                    symbol
+                )
+            }
+            MaterialError::Full => write!(f, "No more materials can be registered"),
+        }
+    }
+}
+
+impl std::error::Error for MaterialError {}
+
+/// The materials particles can be made of, each given a [`ParticleKind`] id when registered.
+/// The default registry holds the built-in sand, water and stone, along with ice, steam and lava
+/// for water and stone to melt, freeze and boil into, and oil, which floats on water.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialRegistry {
+    /// The materials, indexed by their id
+    materials: Vec<Material>,
+}
+
+impl MaterialRegistry {
+    /// A registry without any materials, not even the built-in ones
+    pub fn empty() -> Self {
+        MaterialRegistry {
+            materials: Vec::new(),
+        }
+    }
+
+    /// Registers a material, returning the id it was given
+    pub fn register(&mut self, material: Material) -> Result<ParticleKind, MaterialError> {
+        if self.by_name(&material.name).is_some() {
+            return Err(MaterialError::DuplicateName(material.name));
+        }
+        if let Some(symbol) = material.symbol {
+            if RESERVED_SYMBOLS.contains(&symbol) {
+                return Err(MaterialError::ReservedSymbol(symbol));
+            }
+            if self.by_symbol(symbol).is_some() {
+                return Err(MaterialError::DuplicateSymbol(symbol));
+            }
+        }
+        if let Some(hotkey) = material.hotkey {
+            if self.by_hotkey(hotkey).is_some() {
+                return Err(MaterialError::DuplicateHotkey(hotkey));
+            }
+        }
+        // The last id is kept free, so every kind fits in a byte along with empty cells
+        if self.materials.len() >= u8::MAX as usize {
+            return Err(MaterialError::Full);
+        }
+        self.materials.push(material);
+        Ok(ParticleKind(self.materials.len() as u8 - 1))
+    }
+
+    pub fn get(&self, kind: ParticleKind) -> Option<&Material> {
+        self.materials.get(kind.0 as usize)
+    }
+
+    /// All materials by their id, in the order they were registered
+    pub fn iter(&self) -> impl Iterator<Item = (ParticleKind, &Material)> {
+        self.materials
+            .iter()
+            .enumerate()
+            .map(|(id, material)| (ParticleKind(id as u8), material))
+    }
+
+    /// The ids of all materials, in the order they were registered
+    pub fn kinds(&self) -> impl Iterator<Item = ParticleKind> + '_ {
+        self.iter().map(|(kind, _)| kind)
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    /// The material with the name, ignoring case
+    pub fn by_name(&self, name: &str) -> Option<ParticleKind> {
+        self.iter()
+            .find(|(_, material)| material.name.eq_ignore_ascii_case(name))
+            .map(|(kind, _)| kind)
+    }
+
+    pub fn by_symbol(&self, symbol: char) -> Option<ParticleKind> {
+        self.iter()
+            .find(|(_, material)| material.symbol == Some(symbol))
+            .map(|(kind, _)| kind)
+    }
+
+    /// The material picked by the key, ignoring case
+    pub fn by_hotkey(&self, hotkey: char) -> Option<ParticleKind> {
+        let hotkey = hotkey.to_ascii_lowercase();
+        self.iter()
+            .find(|(_, material)| material.hotkey == Some(hotkey))
+            .map(|(kind, _)| kind)
+    }
+
+    /// The materials with the tag
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = ParticleKind> + 'a {
+        self.iter()
+            .filter(move |(_, material)| material.has_tag(tag))
+            .map(|(kind, _)| kind)
+    }
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        let mut registry = MaterialRegistry::empty();
+        let built_in = [
+            Material::new("sand")
+                .with_symbol('s')
+                .with_color([0xf9, 0xe2, 0xaf])
+                .with_color_variance(0.06)
+                .with_state(ParticleState {
+                    density: 1.5,
+                    ..Default::default()
+                })
+                .with_tag("powder")
+                .with_hotkey('2')
+                .with_conductivity(0.3),
+            Material::new("water")
+                .with_symbol('w')
+                .with_color([0x89, 0xb4, 0xfa])
+                .with_color_variance(0.02)
+                .with_state(ParticleState {
+                    density: 1.0,
+                    ..Default::default()
+                })
+                .with_tag("liquid")
+                .with_hotkey('3')
+                .with_conductivity(0.6)
+                .with_transition_above(100.0, ParticleKind::STEAM)
+                .with_transition_below(0.0, ParticleKind::ICE),
+            Material::new("stone")
+                .with_symbol('#')
+                .with_color([0x58, 0x5b, 0x70])
+                .with_color_variance(0.08)
+                .with_state(ParticleState {
+                    density: 2.65,
+                    ..Default::default()
+                })
+                .with_tag("solid")
+                .with_hotkey('4')
+                .with_conductivity(0.8)
+                .with_transition_above(1200.0, ParticleKind::LAVA),
+            Material::new("ice")
+                .with_symbol('i')
+                .with_color([0x89, 0xdc, 0xeb])
+                .with_color_variance(0.04)
+                .with_state(ParticleState {
+                    temperature: -10.0,
+                    density: 0.92,
+                    ..Default::default()
+                })
+                .with_tag("solid")
+                .with_hotkey('5')
+                .with_conductivity(0.9)
+                .with_transition_above(0.0, ParticleKind::WATER),
+            Material::new("steam")
+                .with_symbol('v')
+                .with_color([0xcd, 0xd6, 0xf4])
+                .with_color_variance(0.05)
+                .with_state(ParticleState {
+                    temperature: 120.0,
+                    density: 0.0006,
+                    ..Default::default()
+                })
+                .with_tag("gas")
+                .with_hotkey('6')
+                .with_conductivity(0.1)
+                .with_transition_below(100.0, ParticleKind::WATER),
+            Material::new("lava")
+                .with_symbol('l')
+                .with_color([0xfa, 0xb3, 0x87])
+                .with_color_variance(0.1)
+                .with_state(ParticleState {
+                    temperature: 1500.0,
+                    density: 2.5,
+                    ..Default::default()
+                })
+                .with_tag("liquid")
+                .with_hotkey('7')
+                .with_conductivity(0.5)
+                .with_transition_below(1000.0, ParticleKind::STONE),
+            Material::new("oil")
+                .with_symbol('o')
+                .with_color([0xcb, 0xa6, 0xf7])
+                .with_color_variance(0.03)
+                .with_state(ParticleState {
+                    density: 0.8,
+                    ..Default::default()
+                })
+                .with_tag("liquid")
+                .with_hotkey('8')
+                .with_conductivity(0.15),
+        ];
+        for material in built_in {
+            registry.register(material).unwrap();
+        }
+        registry
+    }
+}
+
+/// The registry shared by the whole program, starting out as the [`MaterialRegistry::default`]
+static MATERIALS: LazyLock<RwLock<MaterialRegistry>> =
+    LazyLock::new(|| RwLock::new(MaterialRegistry::default()));
+
+/// The materials shared by the whole program, which rule files, worlds, tools and new particles
+/// look up. Don't hold on to it while registering a material on the same thread, that deadlocks.
+pub fn materials() -> RwLockReadGuard<'static, MaterialRegistry> {
+    MATERIALS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Registers a material in the registry shared by the whole program, see [`materials`]
+pub fn register_material(material: Material) -> Result<ParticleKind, MaterialError> {
+    MATERIALS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .register(material)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_built_in_materials() {
+        let registry = MaterialRegistry::default();
+        assert_eq!(registry.by_name("Sand"), Some(ParticleKind::SAND));
+        assert_eq!(registry.by_symbol('w'), Some(ParticleKind::WATER));
+        assert_eq!(registry.by_hotkey('4'), Some(ParticleKind::STONE));
+        assert_eq!(
+            registry.get(ParticleKind::STONE).unwrap().state.density,
+            2.65
+        );
+        assert_eq!(
+            registry.tagged("liquid").collect::<Vec<_>>(),
+            vec![ParticleKind::WATER, ParticleKind::LAVA, ParticleKind::OIL]
+        );
+    }
+
+    #[test]
+    fn test_phase_transitions() {
+        let registry = MaterialRegistry::default();
+        let water = registry.get(ParticleKind::WATER).unwrap();
+        assert_eq!(water.transition_at(20.0), None);
+        assert_eq!(water.transition_at(100.5), Some(ParticleKind::STEAM));
+        assert_eq!(water.transition_at(-1.0), Some(ParticleKind::ICE));
+        let stone = registry.get(ParticleKind::STONE).unwrap();
+        assert_eq!(stone.transition_at(1100.0), None);
+        assert_eq!(stone.transition_at(1300.0), Some(ParticleKind::LAVA));
+        // Every transition lands on a material that doesn't turn straight back
+        for (_, material) in registry.iter() {
+            for transition in &material.transitions {
+                let temperature = match transition.when {
+                    Threshold::Above(threshold) => threshold + 1.0,
+                    Threshold::Below(threshold) => threshold - 1.0,
+                };
+                let into = registry.get(transition.into).unwrap();
+                assert_eq!(into.transition_at(temperature), None, "{}", material.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_register_material() {
+        let mut registry = MaterialRegistry::default();
+        let honey = registry
+            .register(
+                Material::new("honey")
+                    .with_symbol('h')
+                    .with_color([0xf9, 0xa8, 0x25])
+                    .with_tag("liquid")
+                    .with_hotkey('H'),
+            )
+            .unwrap();
+        assert_eq!(honey, ParticleKind(7));
+        assert_eq!(registry.by_hotkey('h'), Some(honey));
+        assert_eq!(registry.tagged("liquid").count(), 4);
+
+        assert_eq!(
+            registry.register(Material::new("HONEY")),
+            Err(MaterialError::DuplicateName("HONEY".to_string()))
+        );
+        assert_eq!(
+            registry.register(Material::new("tar").with_symbol('s')),
+            Err(MaterialError::DuplicateSymbol('s'))
+        );
+        assert_eq!(
+            registry.register(Material::new("tar").with_symbol('*')),
+            Err(MaterialError::ReservedSymbol('*'))
+        );
+        assert_eq!(
+            registry.register(Material::new("tar").with_hotkey('2')),
+            Err(MaterialError::DuplicateHotkey('2'))
+        );
+    }
+
+    #[test]
+    fn test_color_variance() {
+        let flat = Material::new("flat").with_color([100, 100, 100]);
+        assert_eq!(flat.color_at(3, 4), [100, 100, 100]);
+
+        let varied = flat.with_color_variance(0.5);
+        assert_eq!(varied.color_at(3, 4), varied.color_at(3, 4));
+        let colors: BTreeSet<_> = (0..16).map(|x| varied.color_at(x, 0)).collect();
+        assert!(colors.len() > 1);
+        assert!(colors.iter().all(|color| (50..=150).contains(&color[0])));
+    }
+}
@@ -1,7 +1,12 @@
 mod kind;
+mod material;
 mod state;
 
 pub use kind::ParticleKind;
+pub use material::{
+    materials, register_material, Material, MaterialError, MaterialRegistry, PhaseTransition,
+    Threshold,
+};
 pub use state::ParticleState;
 
 #[derive(Debug, Clone)]
@@ -11,8 +16,9 @@ pub struct Particle {
 }
 
 impl Particle {
+    /// A particle of the material, in the material's default state
     pub fn new(kind: ParticleKind) -> Self {
-        let state = ParticleState::from_kind(kind.clone());
+        let state = ParticleState::from_kind(kind);
         Self { kind, state }
     }
 }
@@ -26,43 +32,128 @@ impl PartialEq for Particle {
 impl Eq for Particle {}
 
 impl std::fmt::Display for Particle {
+    /// The name of its material, followed by its state after an `@` if it isn't the default of
+    /// the material, e.g. `sand` or `water@80C,101.325kPa,1g/cm3`
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.kind)
+        write!(f, "{}", self.kind)?;
+        if self.state != ParticleState::from_kind(self.kind) {
+            write!(f, "@{}", self.state)?;
+        }
+        Ok(())
     }
 }
 
+impl std::str::FromStr for Particle {
+    type Err = ParseParticleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('@') {
+            Some((kind, state)) => Ok(Particle {
+                kind: kind.parse()?,
+                state: state.parse()?,
+            }),
+            None => Ok(Particle::new(s.parse()?)),
+        }
+    }
+}
+
+/// Error type for parsing a [`ParticleKind`], [`ParticleState`] or [`Particle`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseParticleError {
+    /// No material with the name is registered in the [`materials`]
+    UnknownMaterial(String),
+    /// A state that isn't written like `20C,101.325kPa,1.5g/cm3`
+    InvalidState(String),
+}
+
+impl std::fmt::Display for ParseParticleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseParticleError::UnknownMaterial(name) => {
+                write!(f, "`{}` is not a registered material", name)
+            }
+            ParseParticleError::InvalidState(state) => {
+                write!(f, "`{}` is not a particle state", state)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseParticleError {}
+
+serde_as_str!(ParticleKind, ParticleState, Particle);
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_new_particle() {
-        let particle = Particle::new(ParticleKind::Sand);
-        assert_eq!(particle.kind, ParticleKind::Sand);
-        assert_eq!(particle.state, ParticleState::from_kind(ParticleKind::Sand));
+        let particle = Particle::new(ParticleKind::SAND);
+        assert_eq!(particle.kind, ParticleKind::SAND);
+        assert_eq!(particle.state, ParticleState::from_kind(ParticleKind::SAND));
     }
 
     #[test]
     fn test_particle_equality() {
-        let p1 = Particle::new(ParticleKind::Sand);
-        let p2 = Particle::new(ParticleKind::Sand);
-        let p3 = Particle::new(ParticleKind::Water);
+        let p1 = Particle::new(ParticleKind::SAND);
+        let p2 = Particle::new(ParticleKind::SAND);
+        let p3 = Particle::new(ParticleKind::WATER);
 
         // Particles are equal if they have the same kind, regardless of state
         assert_eq!(p1, p2);
         assert_ne!(p1, p3);
 
         // Even with different states, same kinds are equal
-        let mut p4 = Particle::new(ParticleKind::Sand);
+        let mut p4 = Particle::new(ParticleKind::SAND);
         p4.state.temperature = 100.0;
         assert_eq!(p1, p4);
     }
 
+    #[test]
+    fn test_display_and_parse() {
+        let sand = Particle::new(ParticleKind::SAND);
+        assert_eq!(sand.to_string(), "sand");
+        assert_eq!("Sand".parse(), Ok(ParticleKind::SAND));
+        assert_eq!("#7".parse(), Ok(ParticleKind(7)));
+        assert_eq!(ParticleKind(200).to_string(), "#200");
+
+        let mut hot_water = Particle::new(ParticleKind::WATER);
+        hot_water.state.temperature = 80.5;
+        assert_eq!(hot_water.to_string(), "water@80.5C,101.325kPa,1g/cm3");
+        let parsed: Particle = hot_water.to_string().parse().unwrap();
+        assert_eq!(parsed.state, hot_water.state);
+
+        assert_eq!(
+            "lead".parse::<Particle>(),
+            Err(ParseParticleError::UnknownMaterial("lead".to_string()))
+        );
+        assert_eq!(
+            "sand@hot".parse::<Particle>(),
+            Err(ParseParticleError::InvalidState("hot".to_string()))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let mut stone = Particle::new(ParticleKind::STONE);
+        stone.state.pressure = 200.0;
+        let json = serde_json::to_string(&stone).unwrap();
+        assert_eq!(json, "\"stone@20C,200kPa,2.65g/cm3\"");
+        let parsed: Particle = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.state, stone.state);
+        assert_eq!(
+            serde_json::from_str::<ParticleKind>("\"water\"").unwrap(),
+            ParticleKind::WATER
+        );
+    }
+
     #[test]
     fn test_particle_state_initialization() {
-        let sand = Particle::new(ParticleKind::Sand);
-        let water = Particle::new(ParticleKind::Water);
-        let stone = Particle::new(ParticleKind::Stone);
+        let sand = Particle::new(ParticleKind::SAND);
+        let water = Particle::new(ParticleKind::WATER);
+        let stone = Particle::new(ParticleKind::STONE);
 
         // Check that each particle type gets the correct default density
         assert_eq!(sand.state.density, 1.5);
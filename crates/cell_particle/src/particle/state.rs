@@ -1,4 +1,4 @@
-use super::ParticleKind;
+use super::{materials, ParseParticleError, ParticleKind};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParticleState {
@@ -16,21 +16,48 @@ impl ParticleState {
         Self { temperature, pressure, density }
     }
 
-    /// Creates a default particle state from the given particle kind
+    /// The state new particles of the given kind start out with, as registered in the
+    /// [`super::materials`]. Kinds that aren't registered get the default state.
     pub fn from_kind(kind: ParticleKind) -> Self {
-        match kind {
-            ParticleKind::Sand => ParticleState {
-                density: 1.5,
-                ..Default::default()
-            },
-            ParticleKind::Water => ParticleState {
-                density: 1.0,
-                ..Default::default()
-            },
-            ParticleKind::Stone => ParticleState {
-                density: 2.65,
-                ..Default::default()
-            },
+        materials()
+            .get(kind)
+            .map(|material| material.state.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for ParticleState {
+    /// Written as `20C,101.325kPa,1.5g/cm3`, without any spaces
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}C,{}kPa,{}g/cm3",
+            self.temperature, self.pressure, self.density
+        )
+    }
+}
+
+impl std::str::FromStr for ParticleState {
+    type Err = ParseParticleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseParticleError::InvalidState(s.to_string());
+        let mut values = s.split(',');
+        let mut value = |unit: &str| -> Result<f32, ParseParticleError> {
+            values
+                .next()
+                .and_then(|value| value.trim().strip_suffix(unit))
+                .and_then(|number| number.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let state = ParticleState {
+            temperature: value("C")?,
+            pressure: value("kPa")?,
+            density: value("g/cm3")?,
+        };
+        match values.next() {
+            Some(_) => Err(invalid()),
+            None => Ok(state),
         }
     }
 }
@@ -1,6 +1,14 @@
+mod parser;
+mod symmetry;
+
 use percentage::Percentage;
 
-use crate::grid::{Dimensions, Grid};
+use crate::grid::{Dimensions, Grid, GridView};
+
+pub use parser::{
+    parse_rules, parse_rules_with, ParseError, ParseErrorKind, PressureCondition, RuleDefinition,
+};
+pub use symmetry::{ParseSymmetryError, Symmetry};
 
 /// A type similar to [`Option`], but with a few extra tricks
 #[derive(Debug, Clone)]
@@ -12,6 +20,10 @@ pub enum Occupancy<T> {
     OccupiedByAny,
     /// We don't care whether the cell is occupied or not, same as pattern matching `_`
     Unknown,
+    /// The cell is occupied by something lighter than what moves into it. Matches like
+    /// [`Occupancy::OccupiedByAny`], what "lighter" means is left to whoever applies the rule.
+    /// In an output it stands for whatever occupied the lighter cells of the input.
+    OccupiedByLighter,
     /// The cell is not occupied, should be thought of as [`Option::None`]
     Vacant,
 }
@@ -23,6 +35,11 @@ impl<T: PartialEq> PartialEq for Occupancy<T> {
             (Occupancy::OccupiedByAny, Occupancy::OccupiedByAny) => true,
             (Occupancy::OccupiedBy(_), Occupancy::OccupiedByAny) => true,
             (Occupancy::OccupiedByAny, Occupancy::OccupiedBy(_)) => true,
+            (Occupancy::OccupiedByLighter, Occupancy::OccupiedByLighter) => true,
+            (Occupancy::OccupiedByLighter, Occupancy::OccupiedBy(_)) => true,
+            (Occupancy::OccupiedBy(_), Occupancy::OccupiedByLighter) => true,
+            (Occupancy::OccupiedByLighter, Occupancy::OccupiedByAny) => true,
+            (Occupancy::OccupiedByAny, Occupancy::OccupiedByLighter) => true,
             (Occupancy::Unknown, _) => true,
             (_, Occupancy::Unknown) => true,
             (Occupancy::Vacant, Occupancy::Vacant) => true,
@@ -31,6 +48,53 @@ impl<T: PartialEq> PartialEq for Occupancy<T> {
     }
 }
 
+/// Written the way rule files write cells: `.` vacant, `*` occupied by anything, `?` don't care,
+/// `~` occupied by something lighter, and otherwise whatever occupies the cell
+impl<T: std::fmt::Display> std::fmt::Display for Occupancy<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Occupancy::OccupiedBy(occupant) => write!(f, "{}", occupant),
+            Occupancy::OccupiedByAny => write!(f, "*"),
+            Occupancy::Unknown => write!(f, "?"),
+            Occupancy::OccupiedByLighter => write!(f, "~"),
+            Occupancy::Vacant => write!(f, "."),
+        }
+    }
+}
+
+impl<T: std::str::FromStr> std::str::FromStr for Occupancy<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "." => Ok(Occupancy::Vacant),
+            "*" => Ok(Occupancy::OccupiedByAny),
+            "?" => Ok(Occupancy::Unknown),
+            "~" => Ok(Occupancy::OccupiedByLighter),
+            _ => s.parse().map(Occupancy::OccupiedBy),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: std::fmt::Display> serde::Serialize for Occupancy<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for Occupancy<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let occupancy = <String as serde::Deserialize>::deserialize(deserializer)?;
+        occupancy.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Input<T: Clone + PartialEq + std::fmt::Debug> {
     pub grid: Grid<T>,
@@ -87,6 +151,8 @@ impl std::error::Error for RuleError {}
 pub struct Rule<T: Clone + PartialEq + std::fmt::Debug> {
     pub input: Input<T>,
     pub output: Vec<Output<T>>,
+    /// Mirrored and rotated variants of the rule that also apply, see [`Rule::expand`]
+    pub symmetry: Symmetry,
 }
 
 impl<T: Clone + PartialEq + std::fmt::Debug> Rule<T> {
@@ -119,34 +185,90 @@ impl<T: Clone + PartialEq + std::fmt::Debug> Rule<T> {
         Ok(())
     }
 
-    /// Creates a new rule and validates the grid dimensions
+    /// Creates a new rule without any symmetry and validates the grid dimensions
     pub fn new(input: Input<T>, output: Vec<Output<T>>) -> Result<Self, RuleError> {
-        let rule = Rule { input, output };
+        let rule = Rule {
+            input,
+            output,
+            symmetry: Symmetry::None,
+        };
         rule.validate()?;
         Ok(rule)
     }
 
+    /// Sets the symmetry of the rule
+    pub fn with_symmetry(mut self, symmetry: Symmetry) -> Self {
+        self.symmetry = symmetry;
+        self
+    }
+
+    /// Expands the rule into all the distinct variants given by its [`Symmetry`], the rule
+    /// itself first. Variants that turn out identical to an earlier one are dropped, e.g.
+    /// mirroring a rule that is already symmetric. The variants have no symmetry of their own.
+    pub fn expand(&self) -> Vec<Self> {
+        let mut variants: Vec<Self> = Vec::new();
+        for transform in self.symmetry.transforms() {
+            let variant = Rule {
+                input: Input {
+                    grid: transform.apply(&self.input.grid),
+                },
+                output: self
+                    .output
+                    .iter()
+                    .map(|output| Output {
+                        grid: transform.apply(&output.grid),
+                        probability: output.probability,
+                    })
+                    .collect(),
+                symmetry: Symmetry::None,
+            };
+
+            // `PartialEq` on cells may be a loose match, like it is for `Occupancy`, so compare
+            // the exact structure through `Debug` instead
+            let key = format!("{:?}", (&variant.input, &variant.output));
+            if !variants
+                .iter()
+                .any(|existing| format!("{:?}", (&existing.input, &existing.output)) == key)
+            {
+                variants.push(variant);
+            }
+        }
+        variants
+    }
+
     /// Get the dimensions of the rule
     pub fn dimensions(&self) -> Dimensions {
         self.input.grid.dimensions()
     }
 
-    /// Check if the rule matches on the given grid.
+    /// Check if the rule matches on the given grid or view.
     /// The rule matches if the input grid matches the rule's input grid.
-    pub fn matches(&self, grid: &Grid<T>) -> bool {
+    pub fn matches<'a>(&self, grid: impl Into<GridView<'a, T>>) -> bool
+    where
+        T: 'a,
+    {
+        self.matches_with(grid, |expected, cell| expected == cell)
+    }
+
+    /// Check if the rule matches on a view of cells of another type, using `predicate` to
+    /// compare each cell of the rule's input grid to the corresponding cell of the view.
+    /// This allows matching directly on the world without converting it first.
+    pub fn matches_with<'a, U: Clone + std::fmt::Debug + 'a>(
+        &self,
+        grid: impl Into<GridView<'a, U>>,
+        predicate: impl Fn(&T, &U) -> bool,
+    ) -> bool {
+        let grid = grid.into();
         if self.input.grid.dimensions() != grid.dimensions() {
             return false;
         }
 
         // Check if the input grid matches the rule's input grid
-        for (i, row) in self.input.grid.cells.iter().enumerate() {
-            for (j, cell) in row.iter().enumerate() {
-                if cell != &grid.cells[i][j] {
-                    return false;
-                }
-            }
-        }
-        true
+        self.input
+            .grid
+            .iter()
+            .zip(grid.iter())
+            .all(|(expected, cell)| predicate(expected, cell))
     }
 }
 
@@ -156,14 +278,35 @@ mod tests {
 
     use super::*;
 
+    fn sand_falls() -> Rule<Occupancy<ParticleKind>> {
+        Rule::new(
+            Input {
+                grid: Grid::new(vec![
+                    vec![Occupancy::OccupiedBy(ParticleKind::SAND)],
+                    vec![Occupancy::Vacant],
+                ])
+                .unwrap(),
+            },
+            vec![Output {
+                grid: Grid::new(vec![
+                    vec![Occupancy::Vacant],
+                    vec![Occupancy::OccupiedBy(ParticleKind::SAND)],
+                ])
+                .unwrap(),
+                probability: Percentage::new(1.0),
+            }],
+        )
+        .unwrap()
+    }
+
     #[test]
     fn test_rule_validation() {
         // Create the input and outputs
         let input = Input {
-            grid: Grid::new(vec![vec![Particle::new(ParticleKind::Sand)]]).unwrap(),
+            grid: Grid::new(vec![vec![Particle::new(ParticleKind::SAND)]]).unwrap(),
         };
         let output = vec![Output {
-            grid: Grid::new(vec![vec![Particle::new(ParticleKind::Sand)]]).unwrap(),
+            grid: Grid::new(vec![vec![Particle::new(ParticleKind::SAND)]]).unwrap(),
             probability: Percentage::new(1.0),
         }];
 
@@ -179,4 +322,149 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_matches_on_view() {
+        let rule = sand_falls();
+        let world = Grid::new(vec![
+            vec![Some(ParticleKind::SAND), Some(ParticleKind::SAND)],
+            vec![None, Some(ParticleKind::WATER)],
+        ])
+        .unwrap();
+        let occupancy = |expected: &Occupancy<ParticleKind>, cell: &Option<ParticleKind>| match cell
+        {
+            Some(kind) => *expected == Occupancy::OccupiedBy(*kind),
+            None => *expected == Occupancy::Vacant,
+        };
+
+        assert!(rule.matches_with(world.view(0, 0, 1, 2).unwrap(), occupancy));
+        assert!(!rule.matches_with(world.view(1, 0, 1, 2).unwrap(), occupancy));
+        // Views of the wrong size never match
+        assert!(!rule.matches_with(&world, occupancy));
+
+        let kinds = Grid::new(vec![
+            vec![Occupancy::OccupiedBy(ParticleKind::SAND)],
+            vec![Occupancy::Vacant],
+        ])
+        .unwrap();
+        assert!(rule.matches(&kinds));
+        assert!(rule.matches(kinds.view(0, 0, 1, 2).unwrap()));
+    }
+
+    #[test]
+    fn test_expand_drops_duplicates() {
+        // A vertical rule is its own mirror image
+        assert_eq!(
+            sand_falls().with_symmetry(Symmetry::FlipX).expand().len(),
+            1
+        );
+        assert_eq!(
+            sand_falls().with_symmetry(Symmetry::FlipY).expand().len(),
+            2
+        );
+        assert_eq!(
+            sand_falls().with_symmetry(Symmetry::Rotate).expand().len(),
+            4
+        );
+        assert_eq!(sand_falls().with_symmetry(Symmetry::Full).expand().len(), 4);
+        assert_eq!(sand_falls().expand().len(), 1);
+    }
+
+    #[test]
+    fn test_expand_keeps_variants_differing_in_wildcards() {
+        use Occupancy::*;
+        let sand = OccupiedBy(ParticleKind::SAND);
+        let rule = |input: Vec<Vec<_>>, output: Vec<Vec<_>>| {
+            Rule::new(
+                Input {
+                    grid: Grid::new(input).unwrap(),
+                },
+                vec![Output {
+                    grid: Grid::new(output).unwrap(),
+                    probability: Percentage::new(1.0),
+                }],
+            )
+            .unwrap()
+            .with_symmetry(Symmetry::FlipX)
+        };
+
+        // `?` equals anything and `*` equals any material, but the mirror images still differ
+        let fall = rule(
+            vec![vec![sand.clone(), Unknown], vec![Vacant, Unknown]],
+            vec![vec![Vacant, Unknown], vec![sand.clone(), Unknown]],
+        );
+        assert_eq!(fall.expand().len(), 2);
+        let swap = rule(
+            vec![vec![sand.clone(), OccupiedByAny]],
+            vec![vec![OccupiedByAny, sand.clone()]],
+        );
+        let variants = swap.expand();
+        assert_eq!(variants.len(), 2);
+        assert!(matches!(
+            variants[1].input.grid.get(0, 0),
+            Ok(OccupiedByAny)
+        ));
+    }
+
+    #[test]
+    fn test_expand_mirrors_diagonal_rule() {
+        use Occupancy::*;
+        let sand = OccupiedBy(ParticleKind::SAND);
+        let slide_right = Rule::new(
+            Input {
+                grid: Grid::new(vec![
+                    vec![sand.clone(), Unknown],
+                    vec![OccupiedByAny, Vacant],
+                ])
+                .unwrap(),
+            },
+            vec![Output {
+                grid: Grid::new(vec![
+                    vec![Vacant, Unknown],
+                    vec![OccupiedByAny, sand.clone()],
+                ])
+                .unwrap(),
+                probability: Percentage::new(1.0),
+            }],
+        )
+        .unwrap()
+        .with_symmetry(Symmetry::FlipX);
+
+        let variants = slide_right.expand();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].symmetry, Symmetry::None);
+        let slide_left = &variants[1];
+        assert!(slide_left.matches(
+            &Grid::new(vec![
+                vec![Vacant, sand.clone()],
+                vec![Vacant, OccupiedByAny]
+            ])
+            .unwrap()
+        ));
+        assert!(!slide_left.matches(
+            &Grid::new(vec![
+                vec![sand.clone(), Vacant],
+                vec![OccupiedByAny, Vacant]
+            ])
+            .unwrap()
+        ));
+        assert_eq!(
+            slide_left.output[0].grid,
+            Grid::new(vec![vec![Unknown, Vacant], vec![sand, OccupiedByAny]]).unwrap()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let occupancies: Vec<Occupancy<ParticleKind>> =
+            serde_json::from_str(r#"[".", "*", "?", "~", "stone"]"#).unwrap();
+        assert_eq!(
+            occupancies
+                .iter()
+                .map(|occupancy| occupancy.to_string())
+                .collect::<Vec<_>>(),
+            vec![".", "*", "?", "~", "stone"]
+        );
+    }
 }
@@ -0,0 +1,1025 @@
+use std::collections::HashMap;
+
+use percentage::Percentage;
+
+use crate::grid::Grid;
+use crate::particle::{materials, MaterialRegistry, ParticleKind, Threshold};
+
+use super::{Input, Occupancy, Output, Rule, RuleError, Symmetry};
+
+/// A rule as written in a rule file, along with the settings that live outside of [`Rule`]
+#[derive(Debug, Clone)]
+pub struct RuleDefinition {
+    /// Name given to the rule after the `rule` keyword
+    pub name: String,
+    /// The rule itself
+    pub rule: Rule<Occupancy<ParticleKind>>,
+    /// The priority of the rule, if any
+    pub priority: Option<usize>,
+    /// The pressure every particle the input asks for by material must be under, if any
+    pub pressure: Option<PressureCondition>,
+}
+
+/// A condition on the pressure on the particles a rule moves, in kilo Pascals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PressureCondition {
+    /// On all of the pressure on the particle, written as `pressure <above|below> <kPa>`
+    Pressure(Threshold),
+    /// On the pressure on the particle beyond that of the air and the particles resting on it,
+    /// written as `overpressure <above|below> <kPa>`. This is what pushes liquid up the lower
+    /// side of a U-tube.
+    Overpressure(Threshold),
+}
+
+/// What went wrong while parsing a rule file
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    /// A line started with a word that is not a keyword
+    UnknownKeyword(String),
+    /// A keyword was given more arguments than it takes
+    UnexpectedArgument(String),
+    /// A keyword was missing its argument
+    MissingArgument(&'static str),
+    /// A pattern contains a character that is not in the legend
+    UnknownSymbol(char),
+    /// A legend entry refers to something that is not an occupancy, a registered material or a
+    /// material id
+    UnknownLegendValue(String),
+    /// A legend entry tries to define something other than a single character
+    InvalidLegendSymbol(String),
+    /// The symmetry is not one of `none`, `flip_x`, `flip_y`, `rotate` or `full`
+    UnknownSymmetry(String),
+    /// The priority is not a non-negative whole number
+    InvalidPriority(String),
+    /// The probability is not a number or a percentage
+    InvalidProbability(String),
+    /// The pressure is not written as `above <kPa>` or `below <kPa>`
+    InvalidPressure(String),
+    /// A setting or block was given before the first `rule`
+    OutsideRule,
+    /// An indented pattern row was found outside of an `in` or `out` block
+    PatternOutsideBlock,
+    /// A pattern row is not as long as the rows before it
+    UnequalRowLengths,
+    /// An `in` or `out` block has no rows
+    EmptyPattern,
+    /// A rule has more than one `in` block
+    DuplicateInput,
+    /// A rule has no `in` block
+    MissingInput,
+    /// A rule has no `out` blocks
+    MissingOutput,
+    /// The rule was parsed, but is not a valid [`Rule`]
+    InvalidRule(RuleError),
+    /// A single rule was expected, but there were this many
+    ExpectedOneRule(usize),
+    /// An `in` pattern asks for something lighter, but not for any material to be lighter than
+    LighterWithoutMaterial,
+    /// An `out` pattern puts back something lighter, but the `in` pattern has none to take
+    LighterWithoutInput,
+}
+
+impl std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseErrorKind::UnknownKeyword(word) => write!(f, "Unknown keyword `{}`", word),
+            ParseErrorKind::UnexpectedArgument(word) => write!(f, "Unexpected argument `{}`", word),
+            ParseErrorKind::MissingArgument(what) => write!(f, "Missing {}", what),
+            ParseErrorKind::UnknownSymbol(symbol) => {
+                write!(f, "Symbol `{}` is not in the legend", symbol)
+            }
+            ParseErrorKind::UnknownLegendValue(value) => write!(
+                f,
+                "`{}` is neither vacant, any, unknown nor a material",
+                value
+            ),
+            ParseErrorKind::InvalidLegendSymbol(symbol) => {
+                write!(f, "Legend symbol `{}` must be a single character", symbol)
+            }
+            ParseErrorKind::UnknownSymmetry(symmetry) => write!(
+                f,
+                "Unknown symmetry `{}`, expected none, flip_x, flip_y, rotate or full",
+                symmetry
+            ),
+            ParseErrorKind::InvalidPriority(priority) => {
+                write!(f, "Invalid priority `{}`", priority)
+            }
+            ParseErrorKind::InvalidProbability(probability) => {
+                write!(f, "Invalid probability `{}`", probability)
+            }
+            ParseErrorKind::InvalidPressure(pressure) => write!(
+                f,
+                "Invalid pressure `{}`, expected above or below a number of kPa",
+                pressure
+            ),
+            ParseErrorKind::OutsideRule => write!(f, "Expected `rule` first"),
+            ParseErrorKind::PatternOutsideBlock => {
+                write!(f, "Pattern row outside of an `in` or `out` block")
+            }
+            ParseErrorKind::UnequalRowLengths => {
+                write!(f, "Pattern row is not as long as the rows above it")
+            }
+            ParseErrorKind::EmptyPattern => write!(f, "Pattern has no rows"),
+            ParseErrorKind::DuplicateInput => write!(f, "Rule has more than one `in` block"),
+            ParseErrorKind::MissingInput => write!(f, "Rule has no `in` block"),
+            ParseErrorKind::MissingOutput => write!(f, "Rule has no `out` block"),
+            ParseErrorKind::InvalidRule(error) => write!(f, "{}", error),
+            ParseErrorKind::ExpectedOneRule(count) => {
+                write!(f, "Expected a single rule, found {}", count)
+            }
+            ParseErrorKind::LighterWithoutMaterial => {
+                write!(f, "Pattern has no material for `~` to be lighter than")
+            }
+            ParseErrorKind::LighterWithoutInput => {
+                write!(f, "Pattern has a `~`, but the `in` pattern has none")
+            }
+        }
+    }
+}
+
+/// Error from [`parse_rules`], pointing at the line and column where it went wrong
+#[derive(Debug)]
+pub struct ParseError {
+    /// Line of the error, starting at 1
+    pub line: usize,
+    /// Column of the error, starting at 1
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The symbols every rule file starts out with, along with the symbol of every material in
+/// `materials`
+fn default_legend(materials: &MaterialRegistry) -> HashMap<char, Occupancy<ParticleKind>> {
+    let mut legend = HashMap::from([
+        ('.', Occupancy::Vacant),
+        ('*', Occupancy::OccupiedByAny),
+        ('?', Occupancy::Unknown),
+        ('~', Occupancy::OccupiedByLighter),
+    ]);
+    for (kind, material) in materials.iter() {
+        if let Some(symbol) = material.symbol {
+            legend.insert(symbol, Occupancy::OccupiedBy(kind));
+        }
+    }
+    legend
+}
+
+/// Parses the value of a legend entry, either an occupancy, the name of a material in `materials`
+/// or a material id written as `#3`, the way a [`ParticleKind`] that isn't registered displays
+fn parse_legend_value(
+    value: &str,
+    materials: &MaterialRegistry,
+) -> Option<Occupancy<ParticleKind>> {
+    match value {
+        "vacant" => Some(Occupancy::Vacant),
+        "any" => Some(Occupancy::OccupiedByAny),
+        "unknown" => Some(Occupancy::Unknown),
+        "lighter" => Some(Occupancy::OccupiedByLighter),
+        _ => match value.strip_prefix('#') {
+            Some(id) => id.parse().ok().map(ParticleKind),
+            None => materials.by_name(value),
+        }
+        .map(Occupancy::OccupiedBy),
+    }
+}
+
+/// Parses a pressure condition such as `above 120`, in kilo Pascals
+fn parse_pressure(comparison: &str, value: &str) -> Option<Threshold> {
+    let value = value.parse().ok().filter(|value: &f32| value.is_finite())?;
+    match comparison {
+        "above" => Some(Threshold::Above(value)),
+        "below" => Some(Threshold::Below(value)),
+        _ => None,
+    }
+}
+
+/// An `in` or `out` block being parsed
+struct Block {
+    line: usize,
+    probability: Option<Percentage>,
+    rows: Vec<Vec<Occupancy<ParticleKind>>>,
+    /// The line and column of the first cell asking for something lighter
+    lighter: Option<(usize, usize)>,
+}
+
+/// A rule being parsed
+struct PendingRule {
+    line: usize,
+    name: String,
+    priority: Option<usize>,
+    pressure: Option<PressureCondition>,
+    symmetry: Symmetry,
+    input: Option<Block>,
+    outputs: Vec<Block>,
+}
+
+impl Block {
+    fn into_grid(self) -> Result<Grid<Occupancy<ParticleKind>>, ParseError> {
+        Grid::new(self.rows).map_err(|_| ParseError {
+            line: self.line,
+            column: 1,
+            kind: ParseErrorKind::EmptyPattern,
+        })
+    }
+}
+
+impl PendingRule {
+    fn finish(self) -> Result<RuleDefinition, ParseError> {
+        let error = |kind| ParseError {
+            line: self.line,
+            column: 1,
+            kind,
+        };
+        let input = self
+            .input
+            .ok_or_else(|| error(ParseErrorKind::MissingInput))?;
+        if self.outputs.is_empty() {
+            return Err(error(ParseErrorKind::MissingOutput));
+        }
+        if let Some((line, column)) = input.lighter {
+            let mut cells = input.rows.iter().flatten();
+            if !cells.any(|cell| matches!(cell, Occupancy::OccupiedBy(_))) {
+                return Err(ParseError {
+                    line,
+                    column,
+                    kind: ParseErrorKind::LighterWithoutMaterial,
+                });
+            }
+        }
+        let unmatched_lighter = self.outputs.iter().find_map(|output| output.lighter);
+        if let (None, Some((line, column))) = (input.lighter, unmatched_lighter) {
+            return Err(ParseError {
+                line,
+                column,
+                kind: ParseErrorKind::LighterWithoutInput,
+            });
+        }
+
+        // Outputs without a probability share whatever probability is left over evenly
+        let given: Percentage = self.outputs.iter().filter_map(|o| o.probability).sum();
+        let unspecified = self
+            .outputs
+            .iter()
+            .filter(|o| o.probability.is_none())
+            .count();
+        let share = match unspecified {
+            0 => Percentage::new(0.0),
+            n => Percentage::new((1.0 - given.value()) / n as f32),
+        };
+
+        let mut output = Vec::with_capacity(self.outputs.len());
+        for block in self.outputs {
+            let probability = block.probability.unwrap_or(share);
+            output.push(Output {
+                grid: block.into_grid()?,
+                probability,
+            });
+        }
+        let input = Input {
+            grid: input.into_grid()?,
+        };
+
+        let rule = Rule::new(input, output)
+            .map_err(|e| error(ParseErrorKind::InvalidRule(e)))?
+            .with_symmetry(self.symmetry);
+        Ok(RuleDefinition {
+            name: self.name,
+            rule,
+            priority: self.priority,
+            pressure: self.pressure,
+        })
+    }
+}
+
+/// Parses a set of rules from their text representation.
+///
+/// A rule starts with `rule <name>` and is followed by its settings and blocks:
+/// - `priority <n>` sets the priority, rules without one are applied in random order
+/// - `pressure <above|below> <kPa>` only lets the rule apply where every particle the `in` pattern
+///   asks for by material is under more or less pressure than that, and
+///   `overpressure <above|below> <kPa>` does the same for the pressure beyond that of the air and
+///   the particles resting on them
+/// - `symmetry <none|flip_x|flip_y|rotate|full>` makes mirrored or rotated variants apply too
+/// - `in` starts the pattern the rule matches
+/// - `out [probability]` starts one possible result, the probability is written as `25%` or
+///   `0.25`. Outputs without one share what is left of 100% evenly.
+///
+/// Pattern rows are indented, and every character is one cell, looked up in the legend:
+/// `.` vacant, `*` occupied by anything, `?` don't care, `~` occupied by something lighter than
+/// every particle the `in` pattern asks for by material, and the symbol of every registered
+/// material, such as `s` sand, `w` water and `#` stone. A `~` in an `out` pattern is where what
+/// was lighter goes, so `s` over `~` turned into `~` over `s` sinks sand through anything lighter.
+/// An `in` pattern with a `~` has to ask for at least one material, and an `out` pattern can only
+/// have a `~` if the `in` pattern has one.
+/// `legend <symbol> <vacant|any|unknown|lighter|material>` adds or changes a symbol for the rest
+/// of the file, materials are looked up by name in the [`crate::particle::materials`], or given by
+/// id as `#3`.
+/// Everything after `//` on a line is a comment.
+///
+/// ```text
+/// // Sand slides off of anything, both to the left and to the right
+/// rule sand_slide
+/// symmetry flip_x
+/// in
+///     s?
+///     *.
+/// out
+///     .?
+///     *s
+/// ```
+pub fn parse_rules(source: &str) -> Result<Vec<RuleDefinition>, ParseError> {
+    parse_rules_with(source, &materials())
+}
+
+/// Parses a set of rules like [`parse_rules`], but looks materials up in `materials` instead of
+/// the registry shared by the whole program
+pub fn parse_rules_with(
+    source: &str,
+    materials: &MaterialRegistry,
+) -> Result<Vec<RuleDefinition>, ParseError> {
+    let mut legend = default_legend(materials);
+    let mut rules = Vec::new();
+    let mut pending: Option<PendingRule> = None;
+    // Whether the pattern rows being read belong to the input or the last output
+    let mut in_block: Option<bool> = None;
+
+    for (index, raw_line) in source.lines().enumerate() {
+        let line = index + 1;
+        let content = raw_line.split("//").next().unwrap_or_default().trim_end();
+        let trimmed = content.trim_start();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let indent = content.chars().count() - trimmed.chars().count();
+        // Columns count characters, a missing argument is reported one space past the line
+        let end_column = content.chars().count() + 2;
+        let error = |column: usize, kind| ParseError { line, column, kind };
+
+        // Indented lines are pattern rows
+        if indent > 0 {
+            let block = match (pending.as_mut(), in_block) {
+                (Some(rule), Some(true)) => rule.input.as_mut(),
+                (Some(rule), Some(false)) => rule.outputs.last_mut(),
+                _ => None,
+            }
+            .ok_or_else(|| error(indent + 1, ParseErrorKind::PatternOutsideBlock))?;
+
+            let mut row = Vec::with_capacity(trimmed.len());
+            for (offset, symbol) in trimmed.chars().enumerate() {
+                let column = indent + offset + 1;
+                let cell = legend
+                    .get(&symbol)
+                    .ok_or_else(|| error(column, ParseErrorKind::UnknownSymbol(symbol)))?;
+                if matches!(cell, Occupancy::OccupiedByLighter) && block.lighter.is_none() {
+                    block.lighter = Some((line, column));
+                }
+                row.push(cell.clone());
+            }
+            if block
+                .rows
+                .first()
+                .is_some_and(|first| first.len() != row.len())
+            {
+                return Err(error(indent + 1, ParseErrorKind::UnequalRowLengths));
+            }
+            block.rows.push(row);
+            continue;
+        }
+
+        // Anything else is a keyword with its arguments, each paired with its column
+        let mut words = Vec::new();
+        let mut rest = content;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            let word = rest[start..].split_whitespace().next().unwrap_or_default();
+            let offset = content.len() - rest.len() + start;
+            words.push((content[..offset].chars().count() + 1, word));
+            rest = &rest[start + word.len()..];
+        }
+        let (keyword, arguments) = (words[0].1, &words[1..]);
+        let max_arguments = match keyword {
+            "in" => 0,
+            "legend" | "pressure" | "overpressure" => 2,
+            _ => 1,
+        };
+        if let Some(&(column, extra)) = arguments.get(max_arguments) {
+            return Err(error(
+                column,
+                ParseErrorKind::UnexpectedArgument(extra.to_string()),
+            ));
+        }
+        let argument = |what| {
+            arguments
+                .first()
+                .copied()
+                .ok_or_else(|| error(end_column, ParseErrorKind::MissingArgument(what)))
+        };
+        in_block = None;
+
+        if keyword == "rule" {
+            let (_, name) = argument("name")?;
+            if let Some(rule) = pending.take() {
+                rules.push(rule.finish()?);
+            }
+            pending = Some(PendingRule {
+                line,
+                name: name.to_string(),
+                priority: None,
+                pressure: None,
+                symmetry: Symmetry::None,
+                input: None,
+                outputs: Vec::new(),
+            });
+            continue;
+        }
+
+        if keyword == "legend" {
+            let (column, symbol) = argument("legend symbol")?;
+            let &(value_column, value) = arguments.get(1).ok_or_else(|| {
+                error(end_column, ParseErrorKind::MissingArgument("legend value"))
+            })?;
+            let mut chars = symbol.chars();
+            let (Some(character), None) = (chars.next(), chars.next()) else {
+                return Err(error(
+                    column,
+                    ParseErrorKind::InvalidLegendSymbol(symbol.to_string()),
+                ));
+            };
+            let occupancy = parse_legend_value(value, materials).ok_or_else(|| {
+                error(
+                    value_column,
+                    ParseErrorKind::UnknownLegendValue(value.to_string()),
+                )
+            })?;
+            legend.insert(character, occupancy);
+            continue;
+        }
+
+        if !matches!(
+            keyword,
+            "priority" | "pressure" | "overpressure" | "symmetry" | "in" | "out"
+        ) {
+            return Err(error(
+                1,
+                ParseErrorKind::UnknownKeyword(keyword.to_string()),
+            ));
+        }
+        let rule = pending
+            .as_mut()
+            .ok_or_else(|| error(1, ParseErrorKind::OutsideRule))?;
+
+        match keyword {
+            "priority" => {
+                let (column, priority) = argument("priority")?;
+                rule.priority = Some(priority.parse().map_err(|_| {
+                    error(
+                        column,
+                        ParseErrorKind::InvalidPriority(priority.to_string()),
+                    )
+                })?);
+            }
+            "pressure" | "overpressure" => {
+                let (what, condition): (_, fn(_) -> _) = match keyword {
+                    "pressure" => ("pressure", PressureCondition::Pressure),
+                    _ => ("overpressure", PressureCondition::Overpressure),
+                };
+                let (column, comparison) = argument(what)?;
+                let &(_, value) = arguments.get(1).ok_or_else(|| {
+                    error(
+                        end_column,
+                        ParseErrorKind::MissingArgument("pressure value"),
+                    )
+                })?;
+                let threshold = parse_pressure(comparison, value).ok_or_else(|| {
+                    error(
+                        column,
+                        ParseErrorKind::InvalidPressure(format!("{} {}", comparison, value)),
+                    )
+                })?;
+                rule.pressure = Some(condition(threshold));
+            }
+            "symmetry" => {
+                let (column, symmetry) = argument("symmetry")?;
+                rule.symmetry = symmetry.parse().map_err(|_| {
+                    error(
+                        column,
+                        ParseErrorKind::UnknownSymmetry(symmetry.to_string()),
+                    )
+                })?;
+            }
+            "in" => {
+                if rule.input.is_some() {
+                    return Err(error(1, ParseErrorKind::DuplicateInput));
+                }
+                rule.input = Some(Block {
+                    line,
+                    probability: None,
+                    rows: Vec::new(),
+                    lighter: None,
+                });
+                in_block = Some(true);
+            }
+            _ => {
+                let probability = match arguments.first() {
+                    Some(&(column, probability)) => Some(probability.parse().map_err(|_| {
+                        error(
+                            column,
+                            ParseErrorKind::InvalidProbability(probability.to_string()),
+                        )
+                    })?),
+                    None => None,
+                };
+                rule.outputs.push(Block {
+                    line,
+                    probability,
+                    rows: Vec::new(),
+                    lighter: None,
+                });
+                in_block = Some(false);
+            }
+        }
+    }
+
+    if let Some(rule) = pending {
+        rules.push(rule.finish()?);
+    }
+    Ok(rules)
+}
+
+/// Written the way [`parse_rules`] reads it: the `symmetry`, if any, then the `in` block and
+/// every `out` block with its probability. Materials without a symbol of their own are given one
+/// with a `legend` line first.
+impl std::fmt::Display for Rule<Occupancy<ParticleKind>> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let legend = default_legend(&materials());
+        let mut symbols: HashMap<ParticleKind, char> = legend
+            .iter()
+            .filter_map(|(&symbol, occupancy)| match occupancy {
+                Occupancy::OccupiedBy(kind) => Some((*kind, symbol)),
+                _ => None,
+            })
+            .collect();
+        let mut unused = ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .filter(|symbol| !legend.contains_key(symbol));
+        let grids = || std::iter::once(&self.input.grid).chain(self.output.iter().map(|o| &o.grid));
+        for cell in grids().flat_map(|grid| grid.iter()) {
+            if let Occupancy::OccupiedBy(kind) = cell {
+                if !symbols.contains_key(kind) {
+                    let symbol = unused.next().ok_or(std::fmt::Error)?;
+                    writeln!(f, "legend {} {}", symbol, kind)?;
+                    symbols.insert(*kind, symbol);
+                }
+            }
+        }
+
+        let block = |f: &mut std::fmt::Formatter<'_>, grid: &Grid<_>| -> std::fmt::Result {
+            for row in grid.rows() {
+                write!(f, "    ")?;
+                for cell in row {
+                    let symbol = match cell {
+                        Occupancy::OccupiedBy(kind) => symbols[kind],
+                        Occupancy::OccupiedByAny => '*',
+                        Occupancy::Unknown => '?',
+                        Occupancy::OccupiedByLighter => '~',
+                        Occupancy::Vacant => '.',
+                    };
+                    write!(f, "{}", symbol)?;
+                }
+                writeln!(f)?;
+            }
+            Ok(())
+        };
+        if self.symmetry != Symmetry::None {
+            writeln!(f, "symmetry {}", self.symmetry)?;
+        }
+        writeln!(f, "in")?;
+        block(f, &self.input.grid)?;
+        for output in &self.output {
+            writeln!(f, "out {}", output.probability)?;
+            block(f, &output.grid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Written the way [`parse_rules`] reads it, the `rule` line and settings followed by the
+/// [`Rule`] itself
+impl std::fmt::Display for RuleDefinition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "rule {}", self.name)?;
+        if let Some(priority) = self.priority {
+            writeln!(f, "priority {}", priority)?;
+        }
+        if let Some(condition) = self.pressure {
+            let (keyword, threshold) = match condition {
+                PressureCondition::Pressure(threshold) => ("pressure", threshold),
+                PressureCondition::Overpressure(threshold) => ("overpressure", threshold),
+            };
+            match threshold {
+                Threshold::Above(value) => writeln!(f, "{} above {}", keyword, value)?,
+                Threshold::Below(value) => writeln!(f, "{} below {}", keyword, value)?,
+            }
+        }
+        write!(f, "{}", self.rule)
+    }
+}
+
+/// Parses a single rule with [`parse_rules`]
+impl std::str::FromStr for RuleDefinition {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = parse_rules(s)?;
+        if rules.len() == 1 {
+            return Ok(rules.remove(0));
+        }
+        // Point at the second rule, or the start if there are none
+        let line = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                let content = line.split("//").next().unwrap_or_default();
+                !content.starts_with(char::is_whitespace)
+                    && content.split_whitespace().next() == Some("rule")
+            })
+            .nth(1)
+            .map_or(1, |(index, _)| index + 1);
+        Err(ParseError {
+            line,
+            column: 1,
+            kind: ParseErrorKind::ExpectedOneRule(rules.len()),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RuleDefinition {
+    /// Serialised the same way it is displayed
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for RuleDefinition {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rule = <String as serde::Deserialize>::deserialize(deserializer)?;
+        rule.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const SAND_AND_WATER: &str = "
+// Sand
+rule sand_fall
+in
+    s
+    .
+out
+    .
+    s
+
+rule sand_slide
+symmetry flip_x
+in
+    s?
+    *.
+out
+    .?
+    *s
+
+// Water spreads out, but only sometimes
+legend ~ water
+rule water_spread
+priority 2
+symmetry flip_x
+in
+    ~.
+    **
+out 25%
+    .~
+    **
+out // stays put the rest of the time
+    ~.
+    **
+";
+
+    fn error_at(source: &str) -> (usize, usize, ParseErrorKind) {
+        let error = parse_rules(source).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules(SAND_AND_WATER).unwrap();
+        assert_eq!(rules.len(), 3);
+
+        let sand_fall = &rules[0];
+        assert_eq!(sand_fall.name, "sand_fall");
+        assert_eq!(sand_fall.priority, None);
+        assert_eq!(sand_fall.rule.symmetry, Symmetry::None);
+        assert!(sand_fall.rule.matches(
+            &Grid::new(vec![
+                vec![Occupancy::OccupiedBy(ParticleKind::SAND)],
+                vec![Occupancy::Vacant],
+            ])
+            .unwrap()
+        ));
+
+        let sand_slide = &rules[1];
+        assert_eq!(sand_slide.rule.symmetry, Symmetry::FlipX);
+        assert_eq!(sand_slide.rule.expand().len(), 2);
+
+        let water_spread = &rules[2];
+        assert_eq!(water_spread.priority, Some(2));
+        assert_eq!(water_spread.rule.output.len(), 2);
+        assert!((water_spread.rule.output[0].probability.value() - 0.25).abs() < 1e-5);
+        assert!((water_spread.rule.output[1].probability.value() - 0.75).abs() < 1e-5);
+        assert!(water_spread.rule.matches(
+            &Grid::new(vec![
+                vec![
+                    Occupancy::OccupiedBy(ParticleKind::WATER),
+                    Occupancy::Vacant
+                ],
+                vec![
+                    Occupancy::OccupiedBy(ParticleKind::STONE),
+                    Occupancy::OccupiedBy(ParticleKind::SAND),
+                ],
+            ])
+            .unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_registered_materials_are_in_the_legend() {
+        use crate::particle::Material;
+
+        // A registry of its own, so the material doesn't show up in any other test
+        let mut registry = MaterialRegistry::default();
+        let clay = registry
+            .register(Material::new("clay").with_symbol('c'))
+            .unwrap();
+        let rules = parse_rules_with(
+            "rule clay_sinks\nlegend ~ CLAY\nin\n    c\n    ~\nout\n    ~\n    c\n",
+            &registry,
+        )
+        .unwrap();
+        assert!(rules[0].rule.matches(
+            &Grid::new(vec![
+                vec![Occupancy::OccupiedBy(clay)],
+                vec![Occupancy::OccupiedBy(clay)],
+            ])
+            .unwrap()
+        ));
+        assert!(matches!(
+            parse_rules("rule a\nin\n    c\n").unwrap_err().kind,
+            ParseErrorKind::UnknownSymbol('c')
+        ));
+    }
+
+    #[test]
+    fn test_lighter_symbol() {
+        let rules = parse_rules("rule sand_sink\nin\n    s\n    ~\nout\n    ~\n    s\n").unwrap();
+        let sand_sink = &rules[0].rule;
+        assert_eq!(sand_sink.output[0].grid.to_string(), "~\nsand\n");
+        // Whether what's below is lighter is up to the simulation, any particle matches
+        assert!(sand_sink.matches(
+            &Grid::new(vec![
+                vec![Occupancy::OccupiedBy(ParticleKind::SAND)],
+                vec![Occupancy::OccupiedBy(ParticleKind::WATER)],
+            ])
+            .unwrap()
+        ));
+        assert!(!sand_sink.matches(
+            &Grid::new(vec![
+                vec![Occupancy::OccupiedBy(ParticleKind::SAND)],
+                vec![Occupancy::Vacant],
+            ])
+            .unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_pressure_condition() {
+        let rules =
+            parse_rules("rule burst\npressure above 140.5\nin\n    *\n    .\nout\n    .\n    *\n")
+                .unwrap();
+        assert_eq!(
+            rules[0].pressure,
+            Some(PressureCondition::Pressure(Threshold::Above(140.5)))
+        );
+        let rules =
+            parse_rules("rule rise\noverpressure above 2\nin\n    .\n    w\nout\n    w\n    .\n")
+                .unwrap();
+        assert_eq!(
+            rules[0].pressure,
+            Some(PressureCondition::Overpressure(Threshold::Above(2.0)))
+        );
+        assert_eq!(parse_pressure("below", "90"), Some(Threshold::Below(90.0)));
+        assert_eq!(parse_pressure("below", "NaN"), None);
+    }
+
+    #[test]
+    fn test_errors_point_at_the_problem() {
+        assert!(matches!(
+            error_at("rule a\nin\n    s\n    x\n"),
+            (4, 5, ParseErrorKind::UnknownSymbol('x'))
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    s.\n    .\n"),
+            (4, 5, ParseErrorKind::UnequalRowLengths)
+        ));
+        assert!(matches!(
+            error_at("rule a\npriority high\n"),
+            (2, 10, ParseErrorKind::InvalidPriority(_))
+        ));
+        assert!(matches!(
+            error_at("rule a\nsymmetry sideways\n"),
+            (2, 10, ParseErrorKind::UnknownSymmetry(_))
+        ));
+        assert!(matches!(
+            error_at("in\n    s\n"),
+            (1, 1, ParseErrorKind::OutsideRule)
+        ));
+        assert!(matches!(
+            error_at("rule a\n    s\n"),
+            (2, 5, ParseErrorKind::PatternOutsideBlock)
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    s\nout 150%\n    s\n"),
+            (4, 5, ParseErrorKind::InvalidProbability(_))
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    *.\n    .~\nout\n    *~\n    ..\n"),
+            (4, 6, ParseErrorKind::LighterWithoutMaterial)
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    s\n    .\nout\n    .\n    s\nout\n    ~\n    s\n"),
+            (9, 5, ParseErrorKind::LighterWithoutInput)
+        ));
+        assert!(matches!(
+            error_at("rule a\npressure around 100\n"),
+            (2, 10, ParseErrorKind::InvalidPressure(_))
+        ));
+        assert!(matches!(
+            error_at("rule a\npressure above\n"),
+            (2, 16, ParseErrorKind::MissingArgument(_))
+        ));
+        assert!(matches!(
+            error_at("legend ~ mud\n"),
+            (1, 10, ParseErrorKind::UnknownLegendValue(_))
+        ));
+        assert!(matches!(
+            error_at("rule a\nrotate\n"),
+            (2, 1, ParseErrorKind::UnknownKeyword(_))
+        ));
+    }
+
+    #[test]
+    fn test_display_round_trips_through_parse_rules() {
+        // A material that isn't registered, which displays by its id
+        let clay = ParticleKind(200);
+        let source = format!(
+            "{}\nrule sink\npressure above 120.5\nin\n    s\n    ~\nout\n    ~\n    s\n\n\
+             legend c #200\nrule clay_fall\noverpressure below 3\nin\n    c\n    .\n\
+             out 25%\n    .\n    c\nout 75%\n    c\n    .\n",
+            SAND_AND_WATER
+        );
+        let rules = parse_rules(&source).unwrap();
+        assert_eq!(rules.len(), 5);
+
+        for rule in &rules {
+            let text = rule.to_string();
+            let parsed = parse_rules(&text).unwrap();
+            assert_eq!(parsed.len(), 1);
+            assert_eq!(parsed[0].to_string(), text);
+            assert_eq!(parsed[0].name, rule.name);
+            assert_eq!(parsed[0].priority, rule.priority);
+            assert_eq!(parsed[0].pressure, rule.pressure);
+            assert_eq!(parsed[0].rule.symmetry, rule.rule.symmetry);
+        }
+        assert_eq!(
+            rules[2].to_string(),
+            "rule water_spread\npriority 2\nsymmetry flip_x\nin\n    w.\n    **\nout 25%\n    .w\n    \
+             **\nout 75%\n    w.\n    **\n"
+        );
+        // The material has no symbol, so it is given one
+        let clay_fall = rules[4].to_string();
+        assert!(clay_fall.starts_with("rule clay_fall\noverpressure below 3\nlegend a #200\n"));
+        let parsed = parse_rules(&clay_fall).unwrap();
+        assert!(matches!(
+            parsed[0].rule.input.grid.get(0, 0),
+            Ok(Occupancy::OccupiedBy(kind)) if *kind == clay
+        ));
+    }
+
+    #[test]
+    fn test_rule_definition_from_str() {
+        let sand_fall: RuleDefinition = "rule sand_fall\nin\n    s\n    .\nout\n    .\n    s\n"
+            .parse()
+            .unwrap();
+        assert_eq!(sand_fall.name, "sand_fall");
+        assert_eq!(
+            sand_fall
+                .to_string()
+                .parse::<RuleDefinition>()
+                .unwrap()
+                .to_string(),
+            sand_fall.to_string()
+        );
+
+        let error = "// nothing\n".parse::<RuleDefinition>().unwrap_err();
+        assert!(matches!(
+            (error.line, error.kind),
+            (1, ParseErrorKind::ExpectedOneRule(0))
+        ));
+        let error = SAND_AND_WATER.parse::<RuleDefinition>().unwrap_err();
+        assert!(matches!(
+            (error.line, error.kind),
+            (11, ParseErrorKind::ExpectedOneRule(3))
+        ));
+        let error = "rule a\nin\n    x\n".parse::<RuleDefinition>().unwrap_err();
+        assert!(matches!((error.line, error.column), (3, 5)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let rules = parse_rules(SAND_AND_WATER).unwrap();
+        let json = serde_json::to_string(&rules).unwrap();
+        let parsed: Vec<RuleDefinition> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            parsed.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            rules.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+        assert!(serde_json::from_str::<RuleDefinition>(r#""rule a\nin\n""#).is_err());
+    }
+
+    #[test]
+    fn test_columns_count_characters() {
+        assert!(matches!(
+            error_at("legend é mud\n"),
+            (1, 10, ParseErrorKind::UnknownLegendValue(_))
+        ));
+        assert!(matches!(
+            error_at("rule grüße extra\n"),
+            (1, 12, ParseErrorKind::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            error_at("legend é\n"),
+            (1, 10, ParseErrorKind::MissingArgument(_))
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    s\n\u{3000}\u{3000}x\n"),
+            (4, 3, ParseErrorKind::UnknownSymbol('x'))
+        ));
+    }
+
+    #[test]
+    fn test_rule_level_errors() {
+        assert!(matches!(
+            error_at("rule a\nout\n    s\n"),
+            (1, 1, ParseErrorKind::MissingInput)
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    s\nrule b\n"),
+            (1, 1, ParseErrorKind::MissingOutput)
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\nout\n    s\n"),
+            (2, 1, ParseErrorKind::EmptyPattern)
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    s\nout\n    ss\n"),
+            (
+                1,
+                1,
+                ParseErrorKind::InvalidRule(RuleError::DimensionMismatch { .. })
+            )
+        ));
+        assert!(matches!(
+            error_at("rule a\nin\n    s\nout 50%\n    s\nout 30%\n    .\n"),
+            (
+                1,
+                1,
+                ParseErrorKind::InvalidRule(RuleError::OutputNotInProbabilisticUnity { .. })
+            )
+        ));
+
+        let error = parse_rules("rule a\nin\n    s\n    x\n").unwrap_err();
+        assert_eq!(error.to_string(), "4:5: Symbol `x` is not in the legend");
+    }
+}
@@ -0,0 +1,131 @@
+use crate::grid::Grid;
+
+/// The symmetries of a rule, i.e. which mirrored and rotated copies of it also apply.
+/// See [`super::Rule::expand`] for turning a rule into all of its variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symmetry {
+    /// Only the rule as written applies
+    #[default]
+    None,
+    /// The rule also applies mirrored left to right
+    FlipX,
+    /// The rule also applies mirrored top to bottom
+    FlipY,
+    /// The rule also applies rotated by 90, 180 and 270 degrees
+    Rotate,
+    /// The rule applies in all 8 rotations and reflections of the square (the dihedral group D4)
+    Full,
+}
+
+impl std::fmt::Display for Symmetry {
+    /// Written the way rule files name it: `none`, `flip_x`, `flip_y`, `rotate` or `full`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Symmetry::None => "none",
+            Symmetry::FlipX => "flip_x",
+            Symmetry::FlipY => "flip_y",
+            Symmetry::Rotate => "rotate",
+            Symmetry::Full => "full",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Error type for parsing a [`Symmetry`] that doesn't exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSymmetryError(pub String);
+
+impl std::fmt::Display for ParseSymmetryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` is not a symmetry", self.0)
+    }
+}
+
+impl std::error::Error for ParseSymmetryError {}
+
+impl std::str::FromStr for Symmetry {
+    type Err = ParseSymmetryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Symmetry::None),
+            "flip_x" => Ok(Symmetry::FlipX),
+            "flip_y" => Ok(Symmetry::FlipY),
+            "rotate" => Ok(Symmetry::Rotate),
+            "full" => Ok(Symmetry::Full),
+            _ => Err(ParseSymmetryError(s.to_string())),
+        }
+    }
+}
+
+serde_as_str!(Symmetry);
+
+/// A single transformation of a grid, one element of a [`Symmetry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Transform {
+    /// Number of clockwise quarter turns
+    quarter_turns: u8,
+    /// Whether to mirror left to right after turning
+    flip_x: bool,
+    /// Whether to mirror top to bottom after turning
+    flip_y: bool,
+}
+
+impl Transform {
+    const IDENTITY: Self = Self::rotation(0);
+
+    const fn rotation(quarter_turns: u8) -> Self {
+        Self {
+            quarter_turns,
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+
+    /// Applies the transformation to a grid
+    pub(crate) fn apply<T: Clone + std::fmt::Debug>(&self, grid: &Grid<T>) -> Grid<T> {
+        let mut grid = grid.clone();
+        for _ in 0..self.quarter_turns {
+            grid = grid.rotate_cw();
+        }
+        if self.flip_x {
+            grid = grid.flip_x();
+        }
+        if self.flip_y {
+            grid = grid.flip_y();
+        }
+        grid
+    }
+}
+
+impl Symmetry {
+    /// All transformations making up the symmetry, starting with the identity
+    pub(crate) fn transforms(&self) -> Vec<Transform> {
+        let rotations = (0..4).map(Transform::rotation);
+        match self {
+            Symmetry::None => vec![Transform::IDENTITY],
+            Symmetry::FlipX => vec![
+                Transform::IDENTITY,
+                Transform {
+                    flip_x: true,
+                    ..Transform::IDENTITY
+                },
+            ],
+            Symmetry::FlipY => vec![
+                Transform::IDENTITY,
+                Transform {
+                    flip_y: true,
+                    ..Transform::IDENTITY
+                },
+            ],
+            Symmetry::Rotate => rotations.collect(),
+            Symmetry::Full => rotations
+                .clone()
+                .chain(rotations.map(|rotation| Transform {
+                    flip_x: true,
+                    ..rotation
+                }))
+                .collect(),
+        }
+    }
+}
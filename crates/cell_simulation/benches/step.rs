@@ -0,0 +1,146 @@
+//! Compares stepping a large world on one thread with [`Simulation::step`] against
+//! [`Simulation::step_parallel`] on thread pools of different sizes, both with every chunk awake
+//! and with most of them asleep. Run with `cargo bench -p cell_simulation`.
+
+use cell_particle::{
+    particle::{Particle, ParticleKind},
+    rule::parse_rules,
+};
+use cell_simulation::{DirtyRect, Simulation, SimulationRule};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+const RULES: &str = "
+rule sand_fall
+in
+    s
+    .
+out
+    .
+    s
+
+rule sand_slide
+symmetry flip_x
+in
+    s?
+    *.
+out
+    .?
+    *s
+
+rule water_fall
+priority 0
+in
+    w
+    .
+out
+    .
+    w
+
+rule water_slide
+priority 1
+symmetry flip_x
+in
+    w?
+    w.
+out
+    .?
+    ww
+
+rule water_spread
+priority 2
+symmetry flip_x
+in
+    w.
+    **
+out
+    .w
+    **
+";
+
+fn rules() -> Vec<SimulationRule> {
+    parse_rules(RULES)
+        .unwrap()
+        .into_iter()
+        .map(SimulationRule::from)
+        .flat_map(|rule| rule.variants().collect::<Vec<_>>())
+        .collect()
+}
+
+/// A square world with its top half filled with sand and water at random, all of it asleep
+fn world(size: usize) -> Simulation {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let mut simulation = Simulation::new(size, size).with_seed(0);
+    for y in 0..size / 2 {
+        for x in 0..size {
+            let kind = match rng.random_range(0..3) {
+                0 => ParticleKind::SAND,
+                1 => ParticleKind::WATER,
+                _ => continue,
+            };
+            simulation.grid.get_mut(x, y).unwrap().content = Some(Particle::new(kind));
+        }
+    }
+    simulation
+}
+
+/// The thread counts to step in parallel on, a few fixed ones and all threads of the machine
+fn thread_counts() -> Vec<usize> {
+    let mut counts = vec![1, 2, 4, rayon::current_num_threads()];
+    counts.sort();
+    counts.dedup();
+    counts
+}
+
+fn step(c: &mut Criterion) {
+    let rules = rules();
+    let mut group = c.benchmark_group("step");
+    group.sample_size(10);
+
+    for size in [512, 1024] {
+        let mut awake = world(size);
+        awake.chunks.wake_all();
+        // Only a strip along the left edge is awake, as in a world that has mostly settled
+        let mut mostly_asleep = world(size);
+        mostly_asleep.chunks.mark_rect_active(DirtyRect {
+            min: (0, 0),
+            max: (size / 16, size - 1),
+        });
+
+        for (name, simulation) in [("awake", &awake), ("mostly_asleep", &mostly_asleep)] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("sequential/{}", name), size),
+                simulation,
+                |b, world| {
+                    b.iter_batched(
+                        || world.clone(),
+                        |mut world| world.step(&rules),
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+            for threads in thread_counts() {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                group.bench_with_input(
+                    BenchmarkId::new(format!("parallel/{}/{}_threads", name, threads), size),
+                    simulation,
+                    |b, world| {
+                        b.iter_batched(
+                            || world.clone(),
+                            |mut world| pool.install(|| world.step_parallel(&rules)),
+                            BatchSize::LargeInput,
+                        )
+                    },
+                );
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, step);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+use cell_particle::{
+    particle::{Particle, ParticleKind},
+    rule::Occupancy,
+};
+
+/// Wrapper cell for [`Particle`], which optionally contains a [`Particle`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParticleCell {
+    pub content: Option<Particle>,
+}
+
+impl ParticleCell {
+    /// The [`Occupancy`] of the cell, as rules see it
+    pub fn occupancy(&self) -> Occupancy<ParticleKind> {
+        match &self.content {
+            Some(particle) => Occupancy::OccupiedBy(particle.kind),
+            None => Occupancy::Vacant,
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::BTreeSet;
+
+use cell_particle::grid::Dimensions;
+
+/// Side of a chunk in cells, unless the world is given another with
+/// [`crate::Simulation::with_chunk_size`]
+pub const DEFAULT_CHUNK_SIZE: usize = 64;
+
+/// A rectangle of cells in world coordinates, `min` and `max` both included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+}
+
+impl DirtyRect {
+    /// A rectangle of a single cell
+    pub fn cell(x: usize, y: usize) -> Self {
+        DirtyRect {
+            min: (x, y),
+            max: (x, y),
+        }
+    }
+
+    /// Grows the rectangle just enough to include the cell
+    pub fn include(&mut self, x: usize, y: usize) {
+        self.min = (self.min.0.min(x), self.min.1.min(y));
+        self.max = (self.max.0.max(x), self.max.1.max(y));
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&y)
+    }
+
+    /// The overlap of two rectangles, if they overlap at all
+    pub fn intersection(&self, other: &DirtyRect) -> Option<DirtyRect> {
+        let min = (self.min.0.max(other.min.0), self.min.1.max(other.min.1));
+        let max = (self.max.0.min(other.max.0), self.max.1.min(other.max.1));
+        (min.0 <= max.0 && min.1 <= max.1).then_some(DirtyRect { min, max })
+    }
+
+    /// The number of cells in the rectangle
+    pub fn area(&self) -> usize {
+        (self.max.0 - self.min.0 + 1) * (self.max.1 - self.min.1 + 1)
+    }
+
+    /// The cells of the rectangle, column by column
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize)> {
+        let DirtyRect { min, max } = *self;
+        (min.0..=max.0).flat_map(move |x| (min.1..=max.1).map(move |y| (x, y)))
+    }
+}
+
+/// A square part of the world and the cells in it that may change
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Chunk {
+    /// The cells that may change this step, [`None`] while the chunk sleeps
+    pub dirty: Option<DirtyRect>,
+    /// The cells that may change next step, the chunk falls asleep if there are none
+    pub next_dirty: Option<DirtyRect>,
+}
+
+impl Chunk {
+    /// Whether any cell of the chunk is looked at this step
+    pub fn is_awake(&self) -> bool {
+        self.dirty.is_some()
+    }
+}
+
+/// Grows the rectangle to include the cell, or starts one at the cell
+fn include(rect: &mut Option<DirtyRect>, x: usize, y: usize) {
+    match rect {
+        Some(rect) => rect.include(x, y),
+        None => *rect = Some(DirtyRect::cell(x, y)),
+    }
+}
+
+/// The world split into square chunks, each keeping track of the cells in it that may change.
+/// A step only looks at the dirty rectangles of the chunks that are awake, so the still parts of
+/// a large world cost nothing. Chunks along the right and bottom edges may be cut short.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunks {
+    /// Side of a chunk in cells
+    size: usize,
+    /// Size of the world in cells
+    world: Dimensions,
+    /// Size of the world in chunks
+    dimensions: Dimensions,
+    /// The chunks in row-major order
+    chunks: Vec<Chunk>,
+    /// Cells a rule has already changed this step
+    affected: BTreeSet<(usize, usize)>,
+}
+
+impl Chunks {
+    /// Splits a world of `width` by `height` cells into chunks of `size` by `size` cells, all
+    /// asleep
+    pub fn new(width: usize, height: usize, size: usize) -> Self {
+        let size = size.max(1);
+        let dimensions = Dimensions {
+            width: width.div_ceil(size),
+            height: height.div_ceil(size),
+        };
+        let count = dimensions.width * dimensions.height;
+        Chunks {
+            size,
+            world: Dimensions { width, height },
+            dimensions,
+            chunks: vec![Chunk::default(); count],
+            affected: BTreeSet::new(),
+        }
+    }
+
+    /// The same world split into chunks of another size, with the same cells dirty
+    pub fn resized(&self, size: usize) -> Self {
+        let mut resized = Chunks::new(self.world.width, self.world.height, size);
+        for chunk in &self.chunks {
+            if let Some(dirty) = chunk.dirty {
+                resized.mark_rect_active(dirty);
+            }
+            if let Some(next_dirty) = chunk.next_dirty {
+                resized.mark_rect_for_next_frame(next_dirty);
+            }
+        }
+        resized.affected = self.affected.clone();
+        resized
+    }
+
+    /// Side of a chunk in cells
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// How many chunks there are across and down the world
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions.clone()
+    }
+
+    /// The chunk the cell lies in
+    pub fn chunk_of(&self, x: usize, y: usize) -> (usize, usize) {
+        (x / self.size, y / self.size)
+    }
+
+    pub fn get(&self, chunk_x: usize, chunk_y: usize) -> Option<&Chunk> {
+        if chunk_x >= self.dimensions.width || chunk_y >= self.dimensions.height {
+            return None;
+        }
+        self.chunks.get(chunk_y * self.dimensions.width + chunk_x)
+    }
+
+    /// All the cells of a chunk
+    pub fn bounds(&self, chunk_x: usize, chunk_y: usize) -> Option<DirtyRect> {
+        self.get(chunk_x, chunk_y)?;
+        let min = (chunk_x * self.size, chunk_y * self.size);
+        let max = (
+            ((chunk_x + 1) * self.size).min(self.world.width) - 1,
+            ((chunk_y + 1) * self.size).min(self.world.height) - 1,
+        );
+        Some(DirtyRect { min, max })
+    }
+
+    /// All chunks by their position, in row-major order
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), &Chunk)> {
+        let width = self.dimensions.width;
+        self.chunks
+            .iter()
+            .enumerate()
+            .map(move |(index, chunk)| ((index % width, index / width), chunk))
+    }
+
+    /// The chunks that are awake this step, in row-major order
+    pub fn awake(&self) -> impl Iterator<Item = ((usize, usize), &Chunk)> {
+        self.iter().filter(|(_, chunk)| chunk.is_awake())
+    }
+
+    /// Whether the cell is part of this step
+    pub fn is_dirty(&self, x: usize, y: usize) -> bool {
+        let (chunk_x, chunk_y) = self.chunk_of(x, y);
+        self.get(chunk_x, chunk_y)
+            .and_then(|chunk| chunk.dirty)
+            .is_some_and(|dirty| dirty.contains(x, y))
+    }
+
+    /// The same world with the dirty rectangle of every awake chunk grown by `border` cells on
+    /// each side, waking the chunks around it. Nothing is marked for the next step.
+    pub fn grown(&self, border: usize) -> Self {
+        let mut grown = Chunks::new(self.world.width, self.world.height, self.size);
+        for (_, chunk) in self.awake() {
+            let Some(DirtyRect { min, max }) = chunk.dirty else {
+                continue;
+            };
+            grown.mark_rect_active(DirtyRect {
+                min: (min.0.saturating_sub(border), min.1.saturating_sub(border)),
+                max: (
+                    (max.0 + border).min(self.world.width - 1),
+                    (max.1 + border).min(self.world.height - 1),
+                ),
+            });
+        }
+        grown
+    }
+
+    /// The number of cells looked at this step
+    pub fn active_cell_count(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| chunk.dirty.map(|dirty| dirty.area()))
+            .sum()
+    }
+
+    fn chunk_mut(&mut self, x: usize, y: usize) -> &mut Chunk {
+        let (chunk_x, chunk_y) = self.chunk_of(x, y);
+        &mut self.chunks[chunk_y * self.dimensions.width + chunk_x]
+    }
+
+    /// Makes the cell part of this step, waking its chunk
+    pub fn mark_active(&mut self, x: usize, y: usize) {
+        include(&mut self.chunk_mut(x, y).dirty, x, y);
+    }
+
+    /// Makes the cell part of the next step, waking its chunk then. This is how changes near the
+    /// border of a chunk wake its neighbour.
+    pub fn mark_for_next_frame(&mut self, x: usize, y: usize) {
+        include(&mut self.chunk_mut(x, y).next_dirty, x, y);
+    }
+
+    /// Makes every cell of the rectangle part of this step, waking the chunks it touches
+    pub fn mark_rect_active(&mut self, rect: DirtyRect) {
+        self.mark_rect(rect, |chunk| &mut chunk.dirty);
+    }
+
+    /// Makes every cell of the rectangle part of the next step
+    pub fn mark_rect_for_next_frame(&mut self, rect: DirtyRect) {
+        self.mark_rect(rect, |chunk| &mut chunk.next_dirty);
+    }
+
+    fn mark_rect(&mut self, rect: DirtyRect, dirty: impl Fn(&mut Chunk) -> &mut Option<DirtyRect>) {
+        let (first_x, first_y) = self.chunk_of(rect.min.0, rect.min.1);
+        let (last_x, last_y) = self.chunk_of(rect.max.0, rect.max.1);
+        for chunk_y in first_y..=last_y.min(self.dimensions.height.saturating_sub(1)) {
+            for chunk_x in first_x..=last_x.min(self.dimensions.width.saturating_sub(1)) {
+                let Some(overlap) = self
+                    .bounds(chunk_x, chunk_y)
+                    .and_then(|bounds| bounds.intersection(&rect))
+                else {
+                    continue;
+                };
+                let chunk = &mut self.chunks[chunk_y * self.dimensions.width + chunk_x];
+                include(dirty(chunk), overlap.min.0, overlap.min.1);
+                include(dirty(chunk), overlap.max.0, overlap.max.1);
+            }
+        }
+    }
+
+    /// Wakes every chunk with all of its cells dirty
+    pub fn wake_all(&mut self) {
+        for chunk_y in 0..self.dimensions.height {
+            for chunk_x in 0..self.dimensions.width {
+                let bounds = self.bounds(chunk_x, chunk_y);
+                self.chunks[chunk_y * self.dimensions.width + chunk_x].dirty = bounds;
+            }
+        }
+    }
+
+    pub fn mark_affected(&mut self, x: usize, y: usize) {
+        self.affected.insert((x, y));
+    }
+
+    /// Whether a rule has already changed the cell this step
+    pub fn is_affected(&self, x: usize, y: usize) -> bool {
+        self.affected.contains(&(x, y))
+    }
+
+    /// Moves on to the next step, chunks with nothing left to do fall asleep
+    pub fn update(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.dirty = chunk.next_dirty.take();
+        }
+        self.affected.clear();
+    }
+}
+
+impl Default for Chunks {
+    fn default() -> Self {
+        Chunks::new(0, 0, DEFAULT_CHUNK_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirty_rect_grows_and_chunks_sleep() {
+        let mut chunks = Chunks::new(10, 6, 4);
+        assert_eq!(
+            chunks.dimensions(),
+            Dimensions {
+                width: 3,
+                height: 2
+            }
+        );
+        assert_eq!(
+            chunks.bounds(2, 1),
+            Some(DirtyRect {
+                min: (8, 4),
+                max: (9, 5)
+            })
+        );
+        assert_eq!(chunks.bounds(3, 0), None);
+
+        chunks.mark_active(1, 1);
+        chunks.mark_active(2, 3);
+        assert_eq!(
+            chunks.get(0, 0).unwrap().dirty,
+            Some(DirtyRect {
+                min: (1, 1),
+                max: (2, 3)
+            })
+        );
+        assert_eq!(chunks.awake().count(), 1);
+        assert_eq!(chunks.active_cell_count(), 6);
+
+        chunks.update();
+        assert_eq!(chunks.awake().count(), 0);
+    }
+
+    #[test]
+    fn test_marking_across_a_border_wakes_the_neighbours() {
+        let mut chunks = Chunks::new(8, 8, 4);
+        chunks.mark_rect_for_next_frame(DirtyRect {
+            min: (3, 3),
+            max: (4, 4),
+        });
+        chunks.update();
+
+        let awake: Vec<_> = chunks.awake().map(|(position, _)| position).collect();
+        assert_eq!(awake, vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert_eq!(chunks.get(1, 1).unwrap().dirty, Some(DirtyRect::cell(4, 4)));
+
+        let resized = chunks.resized(8);
+        assert_eq!(
+            resized.get(0, 0).unwrap().dirty,
+            Some(DirtyRect {
+                min: (3, 3),
+                max: (4, 4)
+            })
+        );
+    }
+
+    #[test]
+    fn test_growing_wakes_the_chunks_around() {
+        let mut chunks = Chunks::new(12, 12, 4);
+        chunks.mark_active(5, 5);
+        let grown = chunks.grown(4);
+
+        let awake: Vec<_> = grown.awake().map(|(position, _)| position).collect();
+        assert_eq!(awake.len(), 9);
+        assert_eq!(
+            grown.get(0, 0).unwrap().dirty,
+            Some(DirtyRect {
+                min: (1, 1),
+                max: (3, 3)
+            })
+        );
+        assert!(grown.is_dirty(9, 9));
+        assert!(!grown.is_dirty(10, 9));
+        assert!(!chunks.is_dirty(4, 5));
+        assert!(chunks.is_dirty(5, 5));
+    }
+}
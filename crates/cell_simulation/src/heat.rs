@@ -0,0 +1,249 @@
+//! Heat flowing between neighbouring particles and the phase transitions it sets off.
+//! Every particle holds the same amount of heat per degree, so the sum of all temperatures is the
+//! thermal energy of the world. Empty cells and the edges of the grid don't let any heat through.
+//! Heat only flows where the world is awake, so the still parts of a large world cost nothing.
+
+use cell_particle::{
+    grid::{Dimensions, Grid},
+    particle::{materials, Particle, ParticleState},
+};
+use rayon::prelude::*;
+
+use crate::{Chunks, DirtyRect, ParticleCell};
+
+/// The coldest a particle can get, in degrees Celsius
+pub const ABSOLUTE_ZERO: f32 = -273.15;
+
+/// The share of the difference in temperature two neighbouring particles of perfect conductors
+/// even out in a step. With four neighbours a particle never gives away more than it has.
+const EXCHANGE_RATE: f32 = 0.25;
+
+/// Changes in temperature smaller than this, in degrees Celsius, don't keep a particle warming up
+/// or cooling down
+const TEMPERATURE_EPSILON: f32 = 0.01;
+
+/// Lets heat flow for one step between each particle in the dirty cells of `region` and the
+/// particles above, below and beside it, as fast as the worse conductor of the two allows. Heat
+/// doesn't flow out of the region, and what one particle gives the other takes, so the
+/// [`thermal_energy`] of the grid stays the same. Spreads the chunks over all threads if
+/// `parallel`, which gives the same result. Returns the cells whose temperature changed, in the
+/// order of the chunks.
+pub fn diffuse_heat(
+    grid: &mut Grid<ParticleCell>,
+    region: &Chunks,
+    parallel: bool,
+) -> Vec<(usize, usize)> {
+    let Dimensions { width, height } = grid.dimensions();
+    let materials = materials();
+    let heat = |x: usize, y: usize| -> Option<(f32, f32)> {
+        if !region.is_dirty(x, y) {
+            return None;
+        }
+        let particle = grid.get(x, y).ok()?.content.as_ref()?;
+        let conductivity = materials
+            .get(particle.kind)
+            .map_or(0.0, |material| material.conductivity);
+        Some((particle.state.temperature, conductivity))
+    };
+
+    let temperature_after = |(x, y): (usize, usize)| -> Option<(usize, usize, f32)> {
+        let (temperature, conductivity) = heat(x, y)?;
+        let neighbours = [
+            (x > 0).then(|| (x - 1, y)),
+            (x + 1 < width).then(|| (x + 1, y)),
+            (y > 0).then(|| (x, y - 1)),
+            (y + 1 < height).then(|| (x, y + 1)),
+        ];
+        let flow: f32 = neighbours
+            .into_iter()
+            .flatten()
+            .filter_map(|(x, y)| heat(x, y))
+            .map(|(other, other_conductivity)| {
+                EXCHANGE_RATE * conductivity.min(other_conductivity) * (other - temperature)
+            })
+            .sum();
+        Some((x, y, temperature + flow))
+    };
+    let dirty: Vec<DirtyRect> = region
+        .awake()
+        .filter_map(|(_, chunk)| chunk.dirty)
+        .collect();
+    let temperatures: Vec<(usize, usize, f32)> = if parallel {
+        dirty
+            .par_iter()
+            .flat_map_iter(|dirty| dirty.cells().filter_map(temperature_after))
+            .collect()
+    } else {
+        dirty
+            .iter()
+            .flat_map(|dirty| dirty.cells().filter_map(temperature_after))
+            .collect()
+    };
+    drop(materials);
+
+    let mut changed = Vec::new();
+    for (x, y, temperature) in temperatures {
+        let Ok(ParticleCell {
+            content: Some(particle),
+        }) = grid.get_mut(x, y)
+        else {
+            continue;
+        };
+        if (particle.state.temperature - temperature).abs() > TEMPERATURE_EPSILON {
+            changed.push((x, y));
+        }
+        particle.state.temperature = temperature;
+    }
+    changed
+}
+
+/// Turns every particle in the dirty cells of `region` that got too hot or too cold for its
+/// material into what the material turns into, see
+/// [`cell_particle::particle::Material::transitions`]. The particle keeps its temperature and
+/// takes the rest of its state from the new material. Returns the cells that changed, in the
+/// order of the chunks.
+pub fn apply_phase_transitions(
+    grid: &mut Grid<ParticleCell>,
+    region: &Chunks,
+) -> Vec<(usize, usize)> {
+    let materials = materials();
+    let mut changed = Vec::new();
+    let cells = region
+        .awake()
+        .filter_map(|(_, chunk)| chunk.dirty)
+        .flat_map(|dirty| dirty.cells());
+    for (x, y) in cells {
+        let Ok(ParticleCell {
+            content: Some(particle),
+        }) = grid.get_mut(x, y)
+        else {
+            continue;
+        };
+        let temperature = particle.state.temperature;
+        let Some(into) = materials
+            .get(particle.kind)
+            .and_then(|material| material.transition_at(temperature))
+        else {
+            continue;
+        };
+        let state = materials
+            .get(into)
+            .map(|material| material.state.clone())
+            .unwrap_or_default();
+        *particle = Particle {
+            kind: into,
+            state: ParticleState {
+                temperature,
+                ..state
+            },
+        };
+        changed.push((x, y));
+    }
+    changed
+}
+
+/// The sum of the temperatures of all particles, which heat flowing around doesn't change
+pub fn thermal_energy(grid: &Grid<ParticleCell>) -> f64 {
+    grid.iter()
+        .filter_map(|cell| cell.content.as_ref())
+        .map(|particle| particle.state.temperature as f64)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use cell_particle::particle::ParticleKind;
+
+    use super::*;
+    use crate::DEFAULT_CHUNK_SIZE;
+
+    fn cell(kind: ParticleKind, temperature: f32) -> ParticleCell {
+        let mut particle = Particle::new(kind);
+        particle.state.temperature = temperature;
+        ParticleCell {
+            content: Some(particle),
+        }
+    }
+
+    /// Every cell of the grid, awake
+    fn everywhere(grid: &Grid<ParticleCell>) -> Chunks {
+        let Dimensions { width, height } = grid.dimensions();
+        let mut chunks = Chunks::new(width, height, DEFAULT_CHUNK_SIZE);
+        chunks.wake_all();
+        chunks
+    }
+
+    #[test]
+    fn test_heat_flows_from_hot_to_cold() {
+        let mut grid = Grid::new(vec![vec![
+            cell(ParticleKind::STONE, 100.0),
+            cell(ParticleKind::STONE, 0.0),
+            ParticleCell::default(),
+            cell(ParticleKind::STONE, 50.0),
+        ]])
+        .unwrap();
+        let before = thermal_energy(&grid);
+        let region = everywhere(&grid);
+        let changed = diffuse_heat(&mut grid, &region, false);
+        assert_eq!(changed, vec![(0, 0), (1, 0)]);
+
+        let temperatures: Vec<_> = grid
+            .iter()
+            .map(|cell| cell.content.as_ref().map(|p| p.state.temperature))
+            .collect();
+        assert_eq!(temperatures, vec![Some(80.0), Some(20.0), None, Some(50.0)]);
+        assert_eq!(thermal_energy(&grid), before);
+    }
+
+    #[test]
+    fn test_heat_stays_in_the_region() {
+        let mut grid = Grid::new(vec![vec![
+            cell(ParticleKind::STONE, 0.0),
+            cell(ParticleKind::STONE, 100.0),
+            cell(ParticleKind::STONE, 0.0),
+        ]])
+        .unwrap();
+        let mut region = Chunks::new(3, 1, 2);
+        region.mark_active(0, 0);
+        region.mark_active(1, 0);
+        let before = thermal_energy(&grid);
+        diffuse_heat(&mut grid, &region, false);
+
+        let temperatures: Vec<_> = grid
+            .iter()
+            .map(|cell| cell.content.as_ref().map(|p| p.state.temperature))
+            .collect();
+        assert_eq!(temperatures, vec![Some(20.0), Some(80.0), Some(0.0)]);
+        assert_eq!(thermal_energy(&grid), before);
+    }
+
+    #[test]
+    fn test_phase_transitions_keep_the_temperature() {
+        let mut grid = Grid::new(vec![vec![
+            cell(ParticleKind::WATER, 101.0),
+            cell(ParticleKind::ICE, 5.0),
+            cell(ParticleKind::SAND, 2000.0),
+            cell(ParticleKind::STONE, 1300.0),
+        ]])
+        .unwrap();
+        let region = everywhere(&grid);
+        let changed = apply_phase_transitions(&mut grid, &region);
+        assert_eq!(changed, vec![(0, 0), (1, 0), (3, 0)]);
+
+        let steam = grid.get(0, 0).unwrap().content.as_ref().unwrap();
+        assert_eq!(steam.kind, ParticleKind::STEAM);
+        assert_eq!(steam.state.temperature, 101.0);
+        assert_eq!(
+            steam.state.density,
+            ParticleState::from_kind(ParticleKind::STEAM).density
+        );
+        assert_eq!(
+            grid.get(1, 0).unwrap().occupancy(),
+            cell(ParticleKind::WATER, 5.0).occupancy()
+        );
+        assert_eq!(
+            grid.get(3, 0).unwrap().occupancy(),
+            cell(ParticleKind::LAVA, 0.0).occupancy()
+        );
+    }
+}
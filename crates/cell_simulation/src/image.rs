@@ -0,0 +1,334 @@
+//! Import and export of grids as PNG images, with a [`Palette`] mapping kinds of particles to
+//! colours. Transparent pixels are empty cells.
+
+use std::io::{BufRead, Seek, Write};
+
+use cell_particle::{
+    grid::Grid,
+    particle::{materials, Particle, ParticleKind},
+};
+use png::{BitDepth, ColorType, Transformations};
+
+use crate::ParticleCell;
+
+/// An RGB colour
+pub type Rgb = [u8; 3];
+
+/// Maps each kind of particle to a colour, for importing and exporting images
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<(ParticleKind, Rgb)>,
+}
+
+impl Palette {
+    /// A palette without any colours
+    pub fn empty() -> Self {
+        Palette { colors: Vec::new() }
+    }
+
+    /// Sets the colour of a kind of particle, replacing any colour it had before
+    pub fn with_color(mut self, kind: ParticleKind, color: Rgb) -> Self {
+        match self
+            .colors
+            .iter_mut()
+            .find(|(existing, _)| *existing == kind)
+        {
+            Some((_, existing)) => *existing = color,
+            None => self.colors.push((kind, color)),
+        }
+        self
+    }
+
+    /// The colour of a kind of particle
+    pub fn color_of(&self, kind: ParticleKind) -> Option<Rgb> {
+        self.colors
+            .iter()
+            .find(|(existing, _)| *existing == kind)
+            .map(|(_, color)| *color)
+    }
+
+    /// The kind of particle with exactly this colour
+    pub fn kind_of(&self, color: Rgb) -> Option<ParticleKind> {
+        self.colors
+            .iter()
+            .find(|(_, existing)| *existing == color)
+            .map(|(kind, _)| *kind)
+    }
+
+    /// The kind of particle with the colour closest to this one, by squared distance in RGB
+    pub fn nearest(&self, color: Rgb) -> Option<ParticleKind> {
+        let distance = |other: &Rgb| -> u32 {
+            color
+                .iter()
+                .zip(other)
+                .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+                .sum()
+        };
+        self.colors
+            .iter()
+            .min_by_key(|(_, existing)| distance(existing))
+            .map(|(kind, _)| *kind)
+    }
+}
+
+impl Default for Palette {
+    /// The colours of the registered materials, see [`cell_particle::particle::materials`]
+    fn default() -> Self {
+        materials()
+            .iter()
+            .fold(Palette::empty(), |palette, (kind, material)| {
+                palette.with_color(kind, material.color)
+            })
+    }
+}
+
+/// What to do with colours of an imported image that aren't in the [`Palette`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownColors {
+    /// Fail with [`ImageError::UnknownColors`]
+    #[default]
+    Error,
+    /// Use the kind of particle with the nearest colour
+    SnapToNearest,
+}
+
+/// A pixel whose colour isn't in the [`Palette`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownColor {
+    pub x: usize,
+    pub y: usize,
+    pub color: Rgb,
+}
+
+/// Error type for importing and exporting images
+#[derive(Debug)]
+pub enum ImageError {
+    /// The image could not be decoded
+    Decoding(png::DecodingError),
+    /// The image could not be encoded
+    Encoding(png::EncodingError),
+    /// The image has no pixels
+    EmptyImage,
+    /// Pixels with colours that aren't in the palette, in row-major order
+    UnknownColors(Vec<UnknownColor>),
+    /// A kind of particle in the grid has no colour in the palette
+    MissingColor(ParticleKind),
+}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::Decoding(error) => write!(f, "Could not decode image: {}", error),
+            ImageError::Encoding(error) => write!(f, "Could not encode image: {}", error),
+            ImageError::EmptyImage => write!(f, "The image is empty"),
+            ImageError::UnknownColors(pixels) => {
+                write!(
+                    f,
+                    "{} pixels have colours not in the palette:",
+                    pixels.len()
+                )?;
+                for pixel in pixels.iter().take(10) {
+                    let [r, g, b] = pixel.color;
+                    write!(
+                        f,
+                        " #{:02x}{:02x}{:02x} at ({}, {})",
+                        r, g, b, pixel.x, pixel.y
+                    )?;
+                }
+                if pixels.len() > 10 {
+                    write!(f, " ...")?;
+                }
+                Ok(())
+            }
+            ImageError::MissingColor(kind) => write!(f, "No colour in the palette for {:?}", kind),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Reads a grid from a PNG image, one cell per pixel. Transparent pixels are empty cells, all
+/// other pixels are looked up in the `palette`, and handled by `unknown_colors` if not found.
+pub fn import_png(
+    reader: impl BufRead + Seek,
+    palette: &Palette,
+    unknown_colors: UnknownColors,
+) -> Result<Grid<ParticleCell>, ImageError> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(ImageError::Decoding)?;
+    let mut buffer = vec![0; reader.output_buffer_size().ok_or(ImageError::EmptyImage)?];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(ImageError::Decoding)?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    if width == 0 || height == 0 {
+        return Err(ImageError::EmptyImage);
+    }
+
+    let mut cells = Vec::with_capacity(width * height);
+    let mut unknown = Vec::new();
+    for y in 0..height {
+        let row = &buffer[y * info.line_size..(y + 1) * info.line_size];
+        for x in 0..width {
+            // Everything is 8 bits per channel after the transformations
+            let (color, alpha) = match info.color_type {
+                ColorType::Rgba => {
+                    let pixel = &row[x * 4..x * 4 + 4];
+                    ([pixel[0], pixel[1], pixel[2]], pixel[3])
+                }
+                ColorType::Rgb => {
+                    let pixel = &row[x * 3..x * 3 + 3];
+                    ([pixel[0], pixel[1], pixel[2]], u8::MAX)
+                }
+                ColorType::GrayscaleAlpha => ([row[x * 2]; 3], row[x * 2 + 1]),
+                ColorType::Grayscale | ColorType::Indexed => ([row[x]; 3], u8::MAX),
+            };
+
+            let kind = if alpha == 0 {
+                None
+            } else {
+                match (palette.kind_of(color), unknown_colors) {
+                    (Some(kind), _) => Some(kind),
+                    (None, UnknownColors::SnapToNearest) => palette.nearest(color),
+                    (None, UnknownColors::Error) => {
+                        unknown.push(UnknownColor { x, y, color });
+                        None
+                    }
+                }
+            };
+            cells.push(ParticleCell {
+                content: kind.map(Particle::new),
+            });
+        }
+    }
+
+    if !unknown.is_empty() {
+        return Err(ImageError::UnknownColors(unknown));
+    }
+    Ok(Grid::from_flat(width, height, cells).unwrap())
+}
+
+/// Writes a grid as an RGBA PNG image, one pixel per cell, with empty cells transparent
+pub fn export_png(
+    writer: impl Write,
+    grid: &Grid<ParticleCell>,
+    palette: &Palette,
+) -> Result<(), ImageError> {
+    let dimensions = grid.dimensions();
+    let mut data = Vec::with_capacity(dimensions.width * dimensions.height * 4);
+    for cell in grid.iter() {
+        match &cell.content {
+            Some(particle) => {
+                let [r, g, b] = palette
+                    .color_of(particle.kind)
+                    .ok_or(ImageError::MissingColor(particle.kind))?;
+                data.extend([r, g, b, u8::MAX]);
+            }
+            None => data.extend([0; 4]),
+        }
+    }
+
+    let mut encoder = png::Encoder::new(writer, dimensions.width as u32, dimensions.height as u32);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(ImageError::Encoding)?;
+    writer
+        .write_image_data(&data)
+        .map_err(ImageError::Encoding)?;
+    writer.finish().map_err(ImageError::Encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn kinds(grid: &Grid<ParticleCell>) -> Vec<Option<ParticleKind>> {
+        grid.iter()
+            .map(|cell| cell.content.as_ref().map(|particle| particle.kind))
+            .collect()
+    }
+
+    fn rgb_png(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut grid = Grid::filled(3, 2, ParticleCell::default()).unwrap();
+        for (x, y, kind) in [
+            (0, 0, ParticleKind::SAND),
+            (2, 0, ParticleKind::WATER),
+            (1, 1, ParticleKind::STONE),
+        ] {
+            grid.get_mut(x, y).unwrap().content = Some(Particle::new(kind));
+        }
+
+        let palette = Palette::default();
+        let mut bytes = Vec::new();
+        export_png(&mut bytes, &grid, &palette).unwrap();
+        let imported = import_png(Cursor::new(bytes), &palette, UnknownColors::Error).unwrap();
+        assert_eq!(imported.dimensions(), grid.dimensions());
+        assert_eq!(kinds(&imported), kinds(&grid));
+    }
+
+    #[test]
+    fn test_unknown_colors() {
+        let palette = Palette::empty()
+            .with_color(ParticleKind::SAND, [255, 255, 0])
+            .with_color(ParticleKind::WATER, [0, 0, 255]);
+        let image = rgb_png(2, 2, &[255, 255, 0, 10, 10, 200, 0, 0, 255, 250, 240, 10]);
+
+        match import_png(Cursor::new(&image), &palette, UnknownColors::Error) {
+            Err(ImageError::UnknownColors(pixels)) => assert_eq!(
+                pixels,
+                vec![
+                    UnknownColor {
+                        x: 1,
+                        y: 0,
+                        color: [10, 10, 200]
+                    },
+                    UnknownColor {
+                        x: 1,
+                        y: 1,
+                        color: [250, 240, 10]
+                    },
+                ]
+            ),
+            other => panic!("Expected unknown colours, got {:?}", other),
+        }
+
+        let snapped = import_png(Cursor::new(&image), &palette, UnknownColors::SnapToNearest);
+        assert_eq!(
+            kinds(&snapped.unwrap()),
+            vec![
+                Some(ParticleKind::SAND),
+                Some(ParticleKind::WATER),
+                Some(ParticleKind::WATER),
+                Some(ParticleKind::SAND),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_needs_every_color() {
+        let stone = ParticleCell {
+            content: Some(Particle::new(ParticleKind::STONE)),
+        };
+        let grid = Grid::filled(1, 1, stone).unwrap();
+        let palette = Palette::empty();
+        assert!(matches!(
+            export_png(Vec::new(), &grid, &palette),
+            Err(ImageError::MissingColor(ParticleKind::STONE))
+        ));
+    }
+}
@@ -0,0 +1,22 @@
+//! The simulation of a world of particles, free of any engine or renderer.
+//! Drive a [`Simulation`] by calling [`Simulation::step`] with the rules of the world.
+
+mod cell;
+mod chunk;
+mod heat;
+mod image;
+mod paint;
+mod pressure;
+mod rule;
+mod simulation;
+mod snapshot;
+
+pub use cell::*;
+pub use chunk::*;
+pub use heat::*;
+pub use image::*;
+pub use paint::*;
+pub use pressure::*;
+pub use rule::*;
+pub use simulation::*;
+pub use snapshot::*;
@@ -0,0 +1,206 @@
+//! Shapes for painting into a grid: brushes, lines, rectangles and flood fills.
+//! Positions are signed, so shapes may reach past the edges of the grid, it's up to the painter
+//! to skip what falls outside.
+
+use std::collections::VecDeque;
+
+use cell_particle::grid::{Dimensions, Grid};
+use percentage::Percentage;
+use rand::Rng;
+
+use crate::ParticleCell;
+
+/// The shape of a [`Brush`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrushShape {
+    #[default]
+    Circle,
+    Square,
+}
+
+/// A brush to paint cells with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Brush {
+    pub shape: BrushShape,
+    /// Distance from the centre to the edge of the brush in cells, a radius of 0 paints one cell
+    pub radius: usize,
+    /// The chance of each covered cell to be painted, below 100% it works like a spray can
+    pub density: Percentage,
+}
+
+impl Brush {
+    pub fn new(shape: BrushShape, radius: usize) -> Self {
+        Brush {
+            shape,
+            radius,
+            density: Percentage::new(1.0),
+        }
+    }
+
+    pub fn with_density(mut self, density: Percentage) -> Self {
+        self.density = density;
+        self
+    }
+
+    /// All the cells covered by the brush centred at `(x, y)`
+    pub fn footprint(&self, x: isize, y: isize) -> impl Iterator<Item = (isize, isize)> {
+        let radius = self.radius as isize;
+        let shape = self.shape;
+        (-radius..=radius)
+            .flat_map(move |dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter(move |(dx, dy)| match shape {
+                BrushShape::Square => true,
+                // Slightly wider than the exact circle, which gives rounder small brushes
+                BrushShape::Circle => dx * dx + dy * dy <= radius * (radius + 1),
+            })
+            .map(move |(dx, dy)| (x + dx, y + dy))
+    }
+
+    /// The cells covered by the brush centred at `(x, y)` that the [`Brush::density`] lets
+    /// through
+    pub fn stamp(&self, x: isize, y: isize, rng: &mut impl Rng) -> Vec<(isize, isize)> {
+        self.spray(self.footprint(x, y), rng)
+    }
+
+    /// The cells the [`Brush::density`] lets through
+    pub fn spray<T>(&self, cells: impl IntoIterator<Item = T>, rng: &mut impl Rng) -> Vec<T> {
+        let density = self.density.value();
+        cells
+            .into_iter()
+            .filter(|_| density >= 1.0 || rng.random::<f32>() < density)
+            .collect()
+    }
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush::new(BrushShape::default(), 0)
+    }
+}
+
+/// The cells on the line from `from` to `to`, both included, by Bresenham's algorithm
+pub fn line(from: (isize, isize), to: (isize, isize)) -> Vec<(isize, isize)> {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let step_x = if x < to.0 { 1 } else { -1 };
+    let step_y = if y < to.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    let mut cells = Vec::with_capacity(dx.max(-dy) as usize + 1);
+    loop {
+        cells.push((x, y));
+        if (x, y) == to {
+            return cells;
+        }
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+/// The cells of the filled rectangle with corners `a` and `b`, both included
+pub fn rectangle(a: (isize, isize), b: (isize, isize)) -> impl Iterator<Item = (isize, isize)> {
+    let (min_x, max_x) = (a.0.min(b.0), a.0.max(b.0));
+    let (min_y, max_y) = (a.1.min(b.1), a.1.max(b.1));
+    (min_y..=max_y).flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+}
+
+/// The cells connected to `(x, y)`, horizontally or vertically, holding the same kind of
+/// particle, or nothing if `(x, y)` is empty. Empty if `(x, y)` is outside the grid.
+pub fn flood_fill(grid: &Grid<ParticleCell>, x: usize, y: usize) -> Vec<(usize, usize)> {
+    let Ok(start) = grid.get(x, y) else {
+        return Vec::new();
+    };
+    let kind = start.content.as_ref().map(|particle| particle.kind);
+    let Dimensions { width, height } = grid.dimensions();
+
+    let mut visited = vec![false; width * height];
+    let mut queue = VecDeque::from([(x, y)]);
+    visited[y * width + x] = true;
+    let mut cells = Vec::new();
+    while let Some((x, y)) = queue.pop_front() {
+        cells.push((x, y));
+        let neighbours = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for (nx, ny) in neighbours {
+            if nx >= width || ny >= height || visited[ny * width + nx] {
+                continue;
+            }
+            let cell = grid.get(nx, ny).unwrap();
+            if cell.content.as_ref().map(|particle| particle.kind) == kind {
+                visited[ny * width + nx] = true;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use cell_particle::particle::{Particle, ParticleKind};
+
+    use super::*;
+
+    #[test]
+    fn test_brush_footprint() {
+        assert_eq!(
+            Brush::default().footprint(3, 4).collect::<Vec<_>>(),
+            vec![(3, 4)]
+        );
+        assert_eq!(
+            Brush::new(BrushShape::Square, 2).footprint(0, 0).count(),
+            25
+        );
+        let circle: Vec<_> = Brush::new(BrushShape::Circle, 2).footprint(0, 0).collect();
+        assert_eq!(circle.len(), 21);
+        assert!(!circle.contains(&(2, 2)));
+        assert!(circle.contains(&(-2, 1)));
+
+        let mut rng = rand::rng();
+        let empty = Brush::new(BrushShape::Square, 3).with_density(Percentage::new(0.0));
+        assert!(empty.stamp(0, 0, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_line_has_no_gaps() {
+        for to in [(7, 2), (-3, 9), (0, -5), (4, 4), (0, 0)] {
+            let cells = line((0, 0), to);
+            assert_eq!(cells.first(), Some(&(0, 0)));
+            assert_eq!(cells.last(), Some(&to));
+            for pair in cells.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert!((a.0 - b.0).abs() <= 1 && (a.1 - b.1).abs() <= 1);
+            }
+        }
+        assert_eq!(line((0, 0), (3, 1)).len(), 4);
+    }
+
+    #[test]
+    fn test_rectangle_and_flood_fill() {
+        assert_eq!(rectangle((2, 3), (0, 2)).count(), 6);
+
+        // A wall of stone splits the empty space in two
+        let stone = ParticleCell {
+            content: Some(Particle::new(ParticleKind::STONE)),
+        };
+        let mut grid = Grid::filled(5, 3, ParticleCell::default()).unwrap();
+        for y in 0..3 {
+            *grid.get_mut(2, y).unwrap() = stone.clone();
+        }
+        assert_eq!(flood_fill(&grid, 0, 0).len(), 6);
+        assert_eq!(flood_fill(&grid, 2, 1).len(), 3);
+        assert!(flood_fill(&grid, 9, 9).is_empty());
+    }
+}
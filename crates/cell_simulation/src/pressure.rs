@@ -0,0 +1,432 @@
+//! The pressure on every particle, from the weight of what lies on top of it and from gas heated
+//! up in a sealed pocket. Cells are taken to be 10 cm tall, so every cell of water adds about a
+//! kilo Pascal to the pressure below it.
+
+use std::collections::{BTreeMap, HashSet};
+
+use cell_particle::{
+    grid::{Dimensions, Grid},
+    particle::{materials, MaterialRegistry},
+};
+use rayon::prelude::*;
+
+use crate::{Chunks, ParticleCell, ABSOLUTE_ZERO};
+
+/// The pressure on anything open to the air, in kilo Pascals
+pub const ATMOSPHERIC_PRESSURE: f32 = 101.325;
+
+/// The pressure in kilo Pascals a cell of material with a density of 1 g/cm³ adds to everything
+/// below it
+pub const PRESSURE_PER_CELL: f32 = 0.981;
+
+/// The temperature in degrees Celsius at which gas in a sealed pocket pushes back with just the
+/// pressure weighing down on it
+const REFERENCE_TEMPERATURE: f32 = 20.0;
+
+/// Changes in pressure smaller than this share of the pressure don't wake a particle, so gas in a
+/// sealed pocket slowly warming up or cooling down doesn't keep it awake
+const PRESSURE_EPSILON: f32 = 1e-3;
+
+/// How a material carries pressure, from its tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Holds up whatever lies on top of it, tagged `solid`
+    Solid,
+    /// Finds its level, tagged `liquid`
+    Liquid,
+    /// Fills the pocket it's in, tagged `gas`
+    Gas,
+    /// Anything else, like powders, passes the weight on to what's below
+    Loose,
+}
+
+/// What [`compute_pressure`] needs to know about a particle
+#[derive(Debug, Clone, Copy)]
+struct Matter {
+    phase: Phase,
+    density: f32,
+    temperature: f32,
+}
+
+impl Matter {
+    fn of(materials: &MaterialRegistry, cell: &ParticleCell) -> Option<Self> {
+        let particle = cell.content.as_ref()?;
+        let phase = match materials.get(particle.kind) {
+            Some(material) if material.has_tag("solid") => Phase::Solid,
+            Some(material) if material.has_tag("liquid") => Phase::Liquid,
+            Some(material) if material.has_tag("gas") => Phase::Gas,
+            _ => Phase::Loose,
+        };
+        Some(Matter {
+            phase,
+            density: particle.state.density,
+            temperature: particle.state.temperature,
+        })
+    }
+
+    fn is_liquid(matter: Option<Self>) -> bool {
+        matter.is_some_and(|matter| matter.phase == Phase::Liquid)
+    }
+}
+
+/// Whether the empty cell between `above` and `below` is a bubble in a body of liquid, which
+/// doesn't cut the column it's in or the body in two
+fn is_bubble(above: Option<Matter>, below: Option<Matter>) -> bool {
+    Matter::is_liquid(above) && Matter::is_liquid(below)
+}
+
+/// The pressure on the particle at `(x, y)` from the air and the column of particles resting on
+/// it, as [`compute_pressure`] works it out. What the particle is under beyond this is what pushes
+/// it around, like liquid up a U-tube or gas out of a sealed pocket.
+pub fn weight_on(grid: &Grid<ParticleCell>, x: usize, y: usize) -> f32 {
+    let materials = materials();
+    let matter = |y: usize| {
+        grid.get(x, y)
+            .ok()
+            .and_then(|cell| Matter::of(&materials, cell))
+    };
+    let mut weight = ATMOSPHERIC_PRESSURE;
+    for y in (0..y).rev() {
+        match matter(y) {
+            None if y > 0 && is_bubble(matter(y - 1), matter(y + 1)) => {}
+            None => break,
+            Some(Matter {
+                phase: Phase::Solid,
+                ..
+            }) => break,
+            Some(Matter { density, .. }) => weight += density * PRESSURE_PER_CELL,
+        }
+    }
+    weight
+}
+
+/// Works out the pressure on the particles in every column with dirty cells in `region`, and on
+/// every body of liquid and pocket of gas reaching into those columns, and stores it in their
+/// state:
+/// - Every particle carries the weight of the column of particles above it, by their density,
+///   up to the first empty cell or solid. Solids hold up what lies on them. A single empty cell
+///   with liquid above and below is a bubble, which the weight passes through.
+/// - Liquid finds its level, so every particle of a body of liquid is under the pressure of the
+///   body's highest surface, as in a U-tube. Liquid only a single empty cell apart is still one
+///   body.
+/// - Gas spreads its pressure over the pocket it's in. Gas in a pocket without any empty cell
+///   next to it is sealed in, and pushes back harder the hotter it is. The edges of the grid hold
+///   gas in as well.
+///
+/// Spreads the columns over all threads if `parallel`, which gives the same result. Returns the
+/// cells whose pressure changed by more than a tiny share, in row-major order.
+pub fn compute_pressure(
+    grid: &mut Grid<ParticleCell>,
+    region: &Chunks,
+    parallel: bool,
+) -> Vec<(usize, usize)> {
+    let Dimensions { width, height } = grid.dimensions();
+    let materials = materials();
+    let matter = |x: usize, y: usize| {
+        grid.get(x, y)
+            .ok()
+            .and_then(|cell| Matter::of(&materials, cell))
+    };
+    let bubble = |x: usize, y: usize| {
+        matter(x, y).is_none()
+            && y > 0
+            && y + 1 < height
+            && is_bubble(matter(x, y - 1), matter(x, y + 1))
+    };
+
+    // The weight of the column above each cell, worked out for a column once it's needed
+    let column = |x: usize| {
+        let mut weights = vec![ATMOSPHERIC_PRESSURE; height];
+        let mut weight = ATMOSPHERIC_PRESSURE;
+        for (y, cell_weight) in weights.iter_mut().enumerate() {
+            let Some(Matter { phase, density, .. }) = matter(x, y) else {
+                if !bubble(x, y) {
+                    weight = ATMOSPHERIC_PRESSURE;
+                }
+                continue;
+            };
+            *cell_weight = weight;
+            weight = match phase {
+                Phase::Solid => ATMOSPHERIC_PRESSURE,
+                _ => weight + density * PRESSURE_PER_CELL,
+            };
+        }
+        weights
+    };
+    let mut dirty_columns = vec![false; width];
+    for dirty in region.awake().filter_map(|(_, chunk)| chunk.dirty) {
+        dirty_columns[dirty.min.0..=dirty.max.0].fill(true);
+    }
+    let dirty_columns: Vec<usize> = (0..width).filter(|&x| dirty_columns[x]).collect();
+    let mut columns: Vec<Option<Vec<f32>>> = vec![None; width];
+    let worked_out: Vec<Vec<f32>> = if parallel {
+        dirty_columns.par_iter().map(|&x| column(x)).collect()
+    } else {
+        dirty_columns.iter().map(|&x| column(x)).collect()
+    };
+    for (&x, weights) in dirty_columns.iter().zip(worked_out) {
+        columns[x] = Some(weights);
+    }
+
+    // By row and then column, so the changed cells come out in row-major order
+    let mut pressure = BTreeMap::new();
+    for &x in &dirty_columns {
+        for y in 0..height {
+            if matter(x, y).is_some() {
+                pressure.insert((y, x), columns[x].as_ref().unwrap()[y]);
+            }
+        }
+    }
+
+    // Spread the pressure through connected bodies of liquid and pockets of gas
+    let neighbours = |(x, y): (usize, usize)| {
+        [
+            (x > 0).then(|| (x - 1, y)),
+            (x + 1 < width).then(|| (x + 1, y)),
+            (y > 0).then(|| (x, y - 1)),
+            (y + 1 < height).then(|| (x, y + 1)),
+        ]
+    };
+    // Liquid on either side of a single empty cell is still one body
+    let across_bubble = |cell: (usize, usize)| {
+        neighbours(cell)
+            .into_iter()
+            .flatten()
+            .filter(|&(x, y)| matter(x, y).is_none())
+            .flat_map(neighbours)
+            .flatten()
+    };
+    let mut visited = HashSet::new();
+    for &x in &dirty_columns {
+        for y in 0..height {
+            let phase = match matter(x, y) {
+                Some(Matter { phase, .. }) if !visited.contains(&(x, y)) => phase,
+                _ => continue,
+            };
+            if !matches!(phase, Phase::Liquid | Phase::Gas) {
+                continue;
+            }
+
+            let mut body = vec![(x, y)];
+            visited.insert((x, y));
+            let mut next = 0;
+            while let Some(&cell) = body.get(next) {
+                next += 1;
+                let bubbles = across_bubble(cell).filter(|_| phase == Phase::Liquid);
+                for neighbour in neighbours(cell).into_iter().flatten().chain(bubbles) {
+                    let (x, y) = neighbour;
+                    if !visited.contains(&neighbour)
+                        && matter(x, y).is_some_and(|m| m.phase == phase)
+                    {
+                        visited.insert(neighbour);
+                        body.push(neighbour);
+                    }
+                }
+            }
+
+            let mut weight =
+                |(x, y): (usize, usize)| columns[x].get_or_insert_with(|| column(x))[y];
+            let depth = |(x, y): (usize, usize)| {
+                matter(x, y).map_or(0.0, |m| m.density) * PRESSURE_PER_CELL * y as f32
+            };
+            if phase == Phase::Liquid {
+                // The pressure at the top of the body if it were all as high as its highest surface
+                let head = body
+                    .iter()
+                    .map(|&cell| weight(cell) - depth(cell))
+                    .fold(f32::MIN, f32::max);
+                for &(x, y) in &body {
+                    pressure.insert((y, x), head + depth((x, y)));
+                }
+            } else {
+                let base = body
+                    .iter()
+                    .map(|&cell| weight(cell))
+                    .fold(f32::MIN, f32::max);
+                let sealed = body.iter().all(|&cell| {
+                    neighbours(cell)
+                        .into_iter()
+                        .flatten()
+                        .all(|(x, y)| matter(x, y).is_some())
+                });
+                let pocket_pressure = if sealed {
+                    let temperature = body
+                        .iter()
+                        .filter_map(|&(x, y)| matter(x, y))
+                        .map(|m| m.temperature)
+                        .sum::<f32>()
+                        / body.len() as f32;
+                    base * (temperature - ABSOLUTE_ZERO).max(0.0)
+                        / (REFERENCE_TEMPERATURE - ABSOLUTE_ZERO)
+                } else {
+                    base
+                };
+                for &(x, y) in &body {
+                    pressure.insert((y, x), pocket_pressure);
+                }
+            }
+        }
+    }
+    drop(materials);
+
+    let mut changed = Vec::new();
+    for ((y, x), pressure) in pressure {
+        let Ok(ParticleCell {
+            content: Some(particle),
+        }) = grid.get_mut(x, y)
+        else {
+            continue;
+        };
+        let change = (particle.state.pressure - pressure).abs();
+        if change > PRESSURE_EPSILON * particle.state.pressure.abs().max(pressure.abs()) {
+            changed.push((x, y));
+        }
+        particle.state.pressure = pressure;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use cell_particle::particle::{Particle, ParticleKind};
+
+    use super::*;
+    use crate::DEFAULT_CHUNK_SIZE;
+
+    fn grid(rows: &[&str]) -> Grid<ParticleCell> {
+        let kind = |symbol| match symbol {
+            'w' => Some(ParticleKind::WATER),
+            '#' => Some(ParticleKind::STONE),
+            'v' => Some(ParticleKind::STEAM),
+            _ => None,
+        };
+        Grid::new(
+            rows.iter()
+                .map(|row| {
+                    row.chars()
+                        .map(|symbol| ParticleCell {
+                            content: kind(symbol).map(Particle::new),
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    fn pressure_at(grid: &Grid<ParticleCell>, x: usize, y: usize) -> f32 {
+        grid.get(x, y)
+            .unwrap()
+            .content
+            .as_ref()
+            .unwrap()
+            .state
+            .pressure
+    }
+
+    /// Every cell of a world of `width` by `height` cells, awake
+    fn everywhere(width: usize, height: usize) -> Chunks {
+        let mut chunks = Chunks::new(width, height, DEFAULT_CHUNK_SIZE);
+        chunks.wake_all();
+        chunks
+    }
+
+    #[test]
+    fn test_water_finds_its_level() {
+        let mut grid = grid(&[
+            "w#.", //
+            "w#.", //
+            "w#w", //
+            "www", //
+        ]);
+        compute_pressure(&mut grid, &everywhere(3, 4), false);
+
+        assert!((pressure_at(&grid, 0, 0) - ATMOSPHERIC_PRESSURE).abs() < 1e-3);
+        let below_surface = pressure_at(&grid, 0, 3) - ATMOSPHERIC_PRESSURE;
+        assert!((below_surface - 3.0 * PRESSURE_PER_CELL).abs() < 1e-3);
+        // The lower surface on the right is pushed on by the higher one on the left
+        let pushed = pressure_at(&grid, 2, 2) - ATMOSPHERIC_PRESSURE;
+        assert!((pushed - 2.0 * PRESSURE_PER_CELL).abs() < 1e-3);
+        // Stone holds up the stone on top of it
+        assert_eq!(pressure_at(&grid, 1, 2), ATMOSPHERIC_PRESSURE);
+    }
+
+    #[test]
+    fn test_bubbles_keep_a_body_together() {
+        let mut grid = grid(&[
+            "w#.", //
+            "w#.", //
+            "w#w", //
+            "w#.", //
+            "www", //
+        ]);
+        compute_pressure(&mut grid, &everywhere(3, 5), false);
+
+        // The weight of the water above the bubble still rests on the water below it
+        let resting = weight_on(&grid, 2, 4) - ATMOSPHERIC_PRESSURE;
+        assert!((resting - PRESSURE_PER_CELL).abs() < 1e-3);
+        // The water above the bubble is still pushed up by the surface on the left
+        let pushed = pressure_at(&grid, 2, 2) - weight_on(&grid, 2, 2);
+        assert!((pushed - 2.0 * PRESSURE_PER_CELL).abs() < 1e-3);
+        assert!((pressure_at(&grid, 2, 4) - pressure_at(&grid, 0, 4)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_sealed_gas_pushes_back_when_hot() {
+        let mut sealed = grid(&[
+            "###", //
+            "#v#", //
+            "###", //
+        ]);
+        let mut open = grid(&[
+            "#.#", //
+            "#v#", //
+            "###", //
+        ]);
+        for grid in [&mut sealed, &mut open] {
+            let steam = grid.get_mut(1, 1).unwrap().content.as_mut().unwrap();
+            steam.state.temperature = 313.0;
+            compute_pressure(grid, &everywhere(3, 3), false);
+        }
+
+        assert!(pressure_at(&sealed, 1, 1) > 2.0 * ATMOSPHERIC_PRESSURE - 1.0);
+        assert_eq!(pressure_at(&open, 1, 1), ATMOSPHERIC_PRESSURE);
+    }
+
+    #[test]
+    fn test_only_dirty_columns_and_the_bodies_in_them_are_worked_out() {
+        let mut grid = grid(&[
+            "w#w#w", //
+            "w#w#w", //
+            "www#w", //
+        ]);
+        let mut region = Chunks::new(5, 3, 1);
+        region.mark_active(0, 0);
+        let changed = compute_pressure(&mut grid, &region, true);
+
+        assert_eq!(changed, vec![(0, 1), (2, 1), (0, 2), (1, 2), (2, 2)]);
+        // The water on the right of the U-tube is part of the same body
+        let bottom = pressure_at(&grid, 2, 2) - ATMOSPHERIC_PRESSURE;
+        assert!((bottom - 2.0 * PRESSURE_PER_CELL).abs() < 1e-3);
+        // The column on the far right is left alone
+        assert_eq!(pressure_at(&grid, 4, 2), ATMOSPHERIC_PRESSURE);
+    }
+
+    #[test]
+    fn test_sealed_gas_drifting_a_little_stays_still() {
+        let mut grid = grid(&[
+            "###", //
+            "#v#", //
+            "###", //
+        ]);
+        let region = everywhere(3, 3);
+        compute_pressure(&mut grid, &region, false);
+
+        let steam = grid.get_mut(1, 1).unwrap().content.as_mut().unwrap();
+        steam.state.temperature += 0.1;
+        assert_eq!(compute_pressure(&mut grid, &region, false), vec![]);
+        let steam = grid.get_mut(1, 1).unwrap().content.as_mut().unwrap();
+        steam.state.temperature += 10.0;
+        assert_eq!(compute_pressure(&mut grid, &region, false), vec![(1, 1)]);
+    }
+}
@@ -0,0 +1,38 @@
+use cell_particle::{
+    particle::ParticleKind,
+    rule::{Occupancy, PressureCondition, Rule, RuleDefinition},
+};
+
+/// A cellular automaton rule, as applied by [`Simulation::step`](crate::Simulation::step)
+#[derive(Debug, Clone)]
+pub struct SimulationRule {
+    /// The rule to apply
+    pub rule: Rule<Occupancy<ParticleKind>>,
+    /// The priority of the rule, if not set, the rule doesn't care about the order of application, and will be randomly shuffled
+    pub priority: Option<usize>,
+    /// The pressure every particle the rule's input asks for by material must be under, if any.
+    /// See [`compute_pressure`](crate::compute_pressure) for where the pressure comes from.
+    pub pressure: Option<PressureCondition>,
+}
+
+impl From<RuleDefinition> for SimulationRule {
+    fn from(definition: RuleDefinition) -> Self {
+        SimulationRule {
+            rule: definition.rule,
+            priority: definition.priority,
+            pressure: definition.pressure,
+        }
+    }
+}
+
+impl SimulationRule {
+    /// Expands the rule's symmetry into one [`SimulationRule`] per variant, all with the same
+    /// priority and pressure condition
+    pub fn variants(&self) -> impl Iterator<Item = SimulationRule> + '_ {
+        self.rule.expand().into_iter().map(|rule| SimulationRule {
+            rule,
+            priority: self.priority,
+            pressure: self.pressure,
+        })
+    }
+}
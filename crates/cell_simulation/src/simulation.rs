@@ -0,0 +1,1164 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+
+use cell_particle::{
+    grid::{BoundaryMode, Dimensions, Grid, GridError, GridView, GridViewMut, Resolved},
+    particle::{materials, Particle, ParticleKind},
+    rule::{Occupancy, PressureCondition, Rule},
+};
+use rand::{
+    distr::{weighted::WeightedIndex, Distribution},
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+
+use crate::{
+    apply_phase_transitions, compute_pressure, diffuse_heat, weight_on, Chunks, DirtyRect,
+    ParticleCell, SimulationRule, DEFAULT_CHUNK_SIZE,
+};
+
+/// The new content of the cell at `x` and `y`, as written by a rule
+type CellWrite = (usize, usize, Option<Particle>);
+
+/// A rule a chunk applied during [`Simulation::step_parallel`], centred on `(x, y)`
+struct AppliedRule {
+    index: usize,
+    x: usize,
+    y: usize,
+    writes: Vec<CellWrite>,
+}
+
+/// What happened during a single [`Simulation::step`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepReport {
+    /// How many times each rule was applied, in the order the rules were given
+    pub rules_fired: Vec<usize>,
+}
+
+/// A world of particles stepped by cellular automaton rules, without any rendering attached
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    /// The data of the world itself, grid of cells
+    pub grid: Grid<ParticleCell>,
+    /// The world split into chunks, keeping track of the cells that may change
+    pub chunks: Chunks,
+    /// How rule windows crossing the edge of the world are treated
+    pub boundary: BoundaryMode<ParticleKind>,
+    /// The seed the world's random number generator was started from
+    pub(crate) seed: u64,
+    /// All randomness of the world is drawn from here, so the same seed, starting grid and rules
+    /// always give the same result
+    pub(crate) rng: ChaCha8Rng,
+}
+
+impl Simulation {
+    /// Creates an empty world with a random seed, see [`Simulation::seed`] to reproduce it
+    pub fn new(width: usize, height: usize) -> Self {
+        let grid = Grid::filled(width, height, ParticleCell::default()).unwrap();
+        let seed = rand::random();
+        Simulation {
+            grid,
+            chunks: Chunks::new(width, height, DEFAULT_CHUNK_SIZE),
+            boundary: BoundaryMode::default(),
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Creates a world from an existing grid with a random seed. Every cell starts out active,
+    /// so the whole grid is looked at in the first step.
+    pub fn from_grid(grid: Grid<ParticleCell>) -> Self {
+        let Dimensions { width, height } = grid.dimensions();
+        let seed = rand::random();
+        let mut chunks = Chunks::new(width, height, DEFAULT_CHUNK_SIZE);
+        chunks.wake_all();
+        Simulation {
+            grid,
+            chunks,
+            boundary: BoundaryMode::default(),
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Splits the world into chunks of `size` by `size` cells, the same cells stay active
+    pub fn with_chunk_size(mut self, size: usize) -> Self {
+        self.chunks = self.chunks.resized(size);
+        self
+    }
+
+    /// Restarts the world's random number generator from `seed`.
+    /// Call this before [`Simulation::with_random_particles`] to get the same particles every time.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self
+    }
+
+    /// The seed the world's random number generator was started from
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn with_boundary(mut self, boundary: BoundaryMode<ParticleKind>) -> Self {
+        self.boundary = boundary;
+        self
+    }
+
+    pub fn with_fill(mut self, particle_kind: ParticleKind) -> Self {
+        for cell in self.grid.iter_mut() {
+            cell.content = Some(Particle::new(particle_kind));
+        }
+        self
+    }
+
+    /// Replaces a cell and marks it and its neighbours active, so the next step picks it up
+    pub fn set_cell(&mut self, x: usize, y: usize, cell: ParticleCell) -> Result<(), GridError> {
+        *self.grid.get_mut(x, y)? = cell;
+        self.activate_around(x, y);
+        Ok(())
+    }
+
+    /// Marks a cell and its neighbours inside the grid as active
+    pub fn activate_around(&mut self, x: usize, y: usize) {
+        let Dimensions { width, height } = self.grid.dimensions();
+        self.chunks.mark_rect_active(DirtyRect {
+            min: (x.saturating_sub(1), y.saturating_sub(1)),
+            max: ((x + 1).min(width - 1), (y + 1).min(height - 1)),
+        });
+    }
+
+    /// The cells of a chunk, see [`Chunks::bounds`]
+    pub fn chunk(
+        &self,
+        chunk_x: usize,
+        chunk_y: usize,
+    ) -> Result<GridView<'_, ParticleCell>, GridError> {
+        let bounds = self
+            .chunks
+            .bounds(chunk_x, chunk_y)
+            .ok_or(GridError::OutOfBounds)?;
+        let (width, height) = (
+            bounds.max.0 - bounds.min.0 + 1,
+            bounds.max.1 - bounds.min.1 + 1,
+        );
+        self.grid.view(bounds.min.0, bounds.min.1, width, height)
+    }
+
+    /// The cells of a chunk to change as you like. The whole chunk and the cells around it are
+    /// marked active, as anything in it may have changed.
+    pub fn chunk_mut(
+        &mut self,
+        chunk_x: usize,
+        chunk_y: usize,
+    ) -> Result<GridViewMut<'_, ParticleCell>, GridError> {
+        let bounds = self
+            .chunks
+            .bounds(chunk_x, chunk_y)
+            .ok_or(GridError::OutOfBounds)?;
+        let Dimensions { width, height } = self.grid.dimensions();
+        self.chunks.mark_rect_active(DirtyRect {
+            min: (
+                bounds.min.0.saturating_sub(1),
+                bounds.min.1.saturating_sub(1),
+            ),
+            max: (
+                (bounds.max.0 + 1).min(width - 1),
+                (bounds.max.1 + 1).min(height - 1),
+            ),
+        });
+        let (width, height) = (
+            bounds.max.0 - bounds.min.0 + 1,
+            bounds.max.1 - bounds.min.1 + 1,
+        );
+        self.grid
+            .view_mut(bounds.min.0, bounds.min.1, width, height)
+    }
+
+    pub fn with_random_particles(mut self) -> Self {
+        let particle_kinds = materials().kinds().collect::<Vec<_>>();
+        for cell in self.grid.iter_mut() {
+            let random_index = self.rng.random_range(0..particle_kinds.len());
+            let particle_kind = particle_kinds[random_index];
+            cell.content = Some(Particle::new(particle_kind));
+        }
+        self
+    }
+
+    /// Advances the simulation by one tick, applying `rules` to the dirty cells of every awake
+    /// chunk. Rules are tried in order of priority, the first one matching a cell wins.
+    /// Afterwards heat flows through the awake chunks and the chunks around them, particles there
+    /// change phase and the pressure on them and on what lies below them is worked out again, see
+    /// [`diffuse_heat`], [`apply_phase_transitions`] and [`compute_pressure`]. The rules can be
+    /// borrowed, so `&[&SimulationRule]` works as well.
+    pub fn step<R: Borrow<SimulationRule>>(&mut self, rules: &[R]) -> StepReport {
+        let mut new_grid = self.grid.clone();
+        let cells_to_check: Vec<_> = self
+            .chunks
+            .awake()
+            .filter_map(|(_, chunk)| chunk.dirty)
+            .flat_map(|dirty| dirty.cells())
+            .collect();
+        let mut next_chunks = std::mem::take(&mut self.chunks);
+        let mut report = StepReport {
+            rules_fired: vec![0; rules.len()],
+        };
+        let ordered_rules = self.order_rules(rules);
+
+        // Process rules and track which cells were affected
+        'cell_loop: for &(x, y) in &cells_to_check {
+            // Skip if this cell has already been affected by a rule this frame
+            if next_chunks.is_affected(x, y) {
+                continue;
+            }
+
+            for &(index, rule) in &ordered_rules {
+                let Some(writes) = Self::try_rule(
+                    &self.grid,
+                    &self.boundary,
+                    rule,
+                    x,
+                    y,
+                    &mut self.rng,
+                    |x, y| next_chunks.is_affected(x, y),
+                ) else {
+                    continue;
+                };
+
+                Self::apply_writes(
+                    &self.grid,
+                    &self.boundary,
+                    &mut new_grid,
+                    &mut next_chunks,
+                    x,
+                    y,
+                    writes,
+                );
+                report.rules_fired[index] += 1;
+                continue 'cell_loop; // Skip remaining rules for this cell
+            }
+        }
+
+        self.grid = new_grid;
+        let region = next_chunks.grown(next_chunks.size());
+        self.chunks = next_chunks;
+        self.chunks.update();
+        self.update_fields(&region, false);
+        report
+    }
+
+    /// Advances the simulation by one tick like [`Simulation::step`], but spreads the awake chunks
+    /// over all threads. The chunks are updated in four passes, like the squares of a
+    /// checkerboard, so the chunks of a pass are never next to each other. Each chunk draws from
+    /// its own random number generator, seeded from the world's, and the results of a pass are
+    /// applied in chunk order, so the same seed always gives the same result no matter how many
+    /// threads there are. The result is not the same as that of [`Simulation::step`].
+    /// Heat and pressure are worked out on all threads as well.
+    pub fn step_parallel<R: Borrow<SimulationRule>>(&mut self, rules: &[R]) -> StepReport {
+        let mut new_grid = self.grid.clone();
+        let mut next_chunks = std::mem::take(&mut self.chunks);
+        let mut report = StepReport {
+            rules_fired: vec![0; rules.len()],
+        };
+        let ordered_rules = self.order_rules(rules);
+        let step_seed: u64 = self.rng.random();
+        let chunks_across = next_chunks.dimensions().width;
+
+        for pass in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let dirty_chunks: Vec<_> = next_chunks
+                .awake()
+                .filter(|((chunk_x, chunk_y), _)| (chunk_x % 2, chunk_y % 2) == pass)
+                .filter_map(|(position, chunk)| Some((position, chunk.dirty?)))
+                .collect();
+
+            // Chunks only read the grid and the cells earlier passes changed, what they'd write
+            // is gathered and applied afterwards
+            let (grid, boundary, chunks) = (&self.grid, &self.boundary, &next_chunks);
+            let applied: Vec<Vec<AppliedRule>> = dirty_chunks
+                .par_iter()
+                .map(|&((chunk_x, chunk_y), dirty)| {
+                    let mut rng = ChaCha8Rng::seed_from_u64(step_seed);
+                    rng.set_stream((chunk_y * chunks_across + chunk_x) as u64);
+                    let mut affected = HashSet::new();
+                    let mut applied = Vec::new();
+
+                    'cell_loop: for (x, y) in dirty.cells() {
+                        if chunks.is_affected(x, y) || affected.contains(&(x, y)) {
+                            continue;
+                        }
+                        for &(index, rule) in &ordered_rules {
+                            let Some(writes) =
+                                Self::try_rule(grid, boundary, rule, x, y, &mut rng, |x, y| {
+                                    chunks.is_affected(x, y) || affected.contains(&(x, y))
+                                })
+                            else {
+                                continue;
+                            };
+                            affected.extend(writes.iter().map(|&(x, y, _)| (x, y)));
+                            applied.push(AppliedRule {
+                                index,
+                                x,
+                                y,
+                                writes,
+                            });
+                            continue 'cell_loop;
+                        }
+                    }
+                    applied
+                })
+                .collect();
+
+            for AppliedRule {
+                index,
+                x,
+                y,
+                writes,
+            } in applied.into_iter().flatten()
+            {
+                // A window reaching into another chunk of the same pass, across a wrapping edge or
+                // because it's as large as a chunk, loses to the chunk that came first
+                if writes
+                    .iter()
+                    .any(|&(x, y, _)| next_chunks.is_affected(x, y))
+                {
+                    continue;
+                }
+                Self::apply_writes(
+                    &self.grid,
+                    &self.boundary,
+                    &mut new_grid,
+                    &mut next_chunks,
+                    x,
+                    y,
+                    writes,
+                );
+                report.rules_fired[index] += 1;
+            }
+        }
+
+        self.grid = new_grid;
+        let region = next_chunks.grown(next_chunks.size());
+        self.chunks = next_chunks;
+        self.chunks.update();
+        self.update_fields(&region, true);
+        report
+    }
+
+    /// Lets heat flow for a step over the dirty cells of `region`, the chunks that were awake this
+    /// step grown by a chunk, and works out the pressure again. Wakes the particles still warming
+    /// up or cooling down, so heat keeps flowing, and the cells around the particles that changed
+    /// phase or pressure, as rules may apply to them now. Does nothing while the whole world
+    /// sleeps.
+    fn update_fields(&mut self, region: &Chunks, parallel: bool) {
+        if region.awake().next().is_none() {
+            return;
+        }
+        for (x, y) in diffuse_heat(&mut self.grid, region, parallel) {
+            self.chunks.mark_active(x, y);
+        }
+        let mut changed = apply_phase_transitions(&mut self.grid, region);
+        changed.extend(compute_pressure(&mut self.grid, region, parallel));
+        for (x, y) in changed {
+            self.activate_around(x, y);
+        }
+    }
+
+    /// Whether every particle the rule's input asks for by material is under the pressure the
+    /// rule asks for, if it asks, given where each cell of the input lies in the grid
+    fn meets_pressure(
+        grid: &Grid<ParticleCell>,
+        rule: &SimulationRule,
+        cells: &[Option<(usize, usize)>],
+    ) -> bool {
+        let Some(condition) = rule.pressure else {
+            return true;
+        };
+        rule.rule
+            .input
+            .grid
+            .iter()
+            .zip(cells)
+            .filter(|(expected, _)| matches!(expected, Occupancy::OccupiedBy(_)))
+            .filter_map(|(_, &position)| position)
+            .all(|(x, y)| {
+                let Some(particle) = grid.get(x, y).ok().and_then(|cell| cell.content.as_ref())
+                else {
+                    return false;
+                };
+                match condition {
+                    PressureCondition::Pressure(threshold) => {
+                        threshold.is_met_by(particle.state.pressure)
+                    }
+                    PressureCondition::Overpressure(threshold) => {
+                        threshold.is_met_by(particle.state.pressure - weight_on(grid, x, y))
+                    }
+                }
+            })
+    }
+
+    /// Whether every particle where the rule's input asks for something lighter is lighter than
+    /// every particle the input asks for by material, given where each cell of the input lies in
+    /// the grid. Walls and anything else outside the grid never count as lighter.
+    /// [`cell_particle::rule::parse_rules`] turns down inputs without any material to compare to.
+    fn displaces_lighter(
+        grid: &Grid<ParticleCell>,
+        rule: &SimulationRule,
+        cells: &[Option<(usize, usize)>],
+    ) -> bool {
+        let input = &rule.rule.input.grid;
+        if !input
+            .iter()
+            .any(|expected| matches!(expected, Occupancy::OccupiedByLighter))
+        {
+            return true;
+        }
+        let density = |position: Option<(usize, usize)>| {
+            let (x, y) = position?;
+            let particle = grid.get(x, y).ok()?.content.as_ref()?;
+            Some(particle.state.density)
+        };
+        let lightest = input
+            .iter()
+            .zip(cells)
+            .filter(|(expected, _)| matches!(expected, Occupancy::OccupiedBy(_)))
+            .filter_map(|(_, &position)| density(position))
+            .fold(f32::INFINITY, f32::min);
+        input
+            .iter()
+            .zip(cells)
+            .filter(|(expected, _)| matches!(expected, Occupancy::OccupiedByLighter))
+            .all(|(_, &position)| density(position).is_some_and(|density| density < lightest))
+    }
+
+    /// The order rules are tried in this step, by priority, with rules of the same priority
+    /// shuffled and rules without a priority inserted at random
+    fn order_rules<'a, R: Borrow<SimulationRule>>(
+        &mut self,
+        rules: &'a [R],
+    ) -> Vec<(usize, &'a SimulationRule)> {
+        // Separate rules into prioritized and unprioritized
+        let (prioritized, unprioritized): (Vec<_>, Vec<_>) = rules
+            .iter()
+            .map(Borrow::borrow)
+            .enumerate()
+            .partition(|(_, rule)| rule.priority.is_some());
+
+        // Sort prioritized rules by priority
+        let mut ordered_rules: Vec<_> = prioritized.clone();
+        ordered_rules.sort_by_key(|(_, rule)| rule.priority.unwrap());
+
+        // Group and shuffle rules with same priority
+        let mut prioritised_rules = Vec::with_capacity(rules.len());
+        let mut current_group = Vec::new();
+        let mut current_priority = None;
+
+        for rule in ordered_rules {
+            match current_priority {
+                None => {
+                    current_priority = Some(rule.1.priority.unwrap());
+                    current_group.push(rule);
+                }
+                Some(prev) if prev != rule.1.priority.unwrap() => {
+                    if !current_group.is_empty() {
+                        current_group.shuffle(&mut self.rng);
+                        prioritised_rules.append(&mut current_group);
+                    }
+                    current_priority = Some(rule.1.priority.unwrap());
+                    current_group.push(rule);
+                }
+                _ => current_group.push(rule),
+            }
+        }
+        // Handle the last group
+        if !current_group.is_empty() {
+            current_group.shuffle(&mut self.rng);
+            prioritised_rules.extend(current_group);
+        }
+
+        // Randomly insert unprioritized rules
+        for rule in unprioritized {
+            let insert_pos = self.rng.random_range(0..=prioritised_rules.len());
+            prioritised_rules.insert(insert_pos, rule);
+        }
+        prioritised_rules
+    }
+
+    /// Tries to apply a rule with its window centred on the cell at `(x, y)`. Returns the new
+    /// content of each cell of the window inside the grid, or [`None`] if the rule doesn't match
+    /// or another rule has already changed one of those cells this step.
+    fn try_rule(
+        grid: &Grid<ParticleCell>,
+        boundary: &BoundaryMode<ParticleKind>,
+        rule: &SimulationRule,
+        x: usize,
+        y: usize,
+        rng: &mut ChaCha8Rng,
+        is_affected: impl Fn(usize, usize) -> bool,
+    ) -> Option<Vec<CellWrite>> {
+        let rule_dims = rule.rule.dimensions();
+
+        // Center the rule window on the particle
+        let mut rule_x = x as isize - (rule_dims.width / 2) as isize;
+        let mut rule_y = y as isize - (rule_dims.height / 2) as isize;
+        if matches!(boundary, BoundaryMode::Skip) {
+            // Windows are clamped against the top left edge, as they always have been
+            rule_x = rule_x.max(0);
+            rule_y = rule_y.max(0);
+        }
+
+        let fits = rule_x >= 0
+            && rule_y >= 0
+            && rule_x as usize + rule_dims.width <= grid.dimensions().width
+            && rule_y as usize + rule_dims.height <= grid.dimensions().height;
+
+        if !fits {
+            if matches!(boundary, BoundaryMode::Skip) {
+                return None;
+            }
+            return Self::try_rule_across_boundary(
+                grid,
+                boundary,
+                rule,
+                rule_x,
+                rule_y,
+                rng,
+                is_affected,
+            );
+        }
+
+        let (rule_x, rule_y) = (rule_x as usize, rule_y as usize);
+        let window = grid
+            .view(rule_x, rule_y, rule_dims.width, rule_dims.height)
+            .unwrap();
+        if !rule
+            .rule
+            .matches_with(window, |expected, cell| *expected == cell.occupancy())
+        {
+            return None;
+        }
+        let cells: Vec<_> = (0..rule_dims.height)
+            .flat_map(|dy| (0..rule_dims.width).map(move |dx| Some((rule_x + dx, rule_y + dy))))
+            .collect();
+        if !Self::meets_pressure(grid, rule, &cells) || !Self::displaces_lighter(grid, rule, &cells)
+        {
+            return None;
+        }
+        // Another rule already wrote part of the window, applying this one on top would
+        // duplicate or destroy particles
+        let overlaps = (0..rule_dims.height)
+            .any(|dy| (0..rule_dims.width).any(|dx| is_affected(rule_x + dx, rule_y + dy)));
+        if overlaps {
+            return None;
+        }
+
+        let output = Self::choose_rule_output(rng, &rule.rule);
+        let writes = Self::output_contents(
+            rule.rule
+                .input
+                .grid
+                .iter()
+                .zip(output.iter())
+                .zip(window.iter())
+                .map(|((input, output), current)| (input, output, current)),
+        )
+        .into_iter()
+        .enumerate()
+        .map(|(i, content)| {
+            (
+                rule_x + i % rule_dims.width,
+                rule_y + i / rule_dims.width,
+                content,
+            )
+        })
+        .collect();
+        Some(writes)
+    }
+
+    /// Tries to apply a rule whose window at `(rule_x, rule_y)` crosses the edge of the grid,
+    /// resolving every cell of the window through the world's [`BoundaryMode`].
+    /// Only cells inside the grid are written, walls stay put and the void swallows anything.
+    fn try_rule_across_boundary(
+        grid: &Grid<ParticleCell>,
+        boundary: &BoundaryMode<ParticleKind>,
+        rule: &SimulationRule,
+        rule_x: isize,
+        rule_y: isize,
+        rng: &mut ChaCha8Rng,
+        is_affected: impl Fn(usize, usize) -> bool,
+    ) -> Option<Vec<CellWrite>> {
+        let Dimensions { width, height } = rule.rule.dimensions();
+        let resolved: Vec<_> = (0..height as isize)
+            .flat_map(|dy| {
+                (0..width as isize).map(move |dx| grid.resolve(rule_x + dx, rule_y + dy, boundary))
+            })
+            .collect();
+
+        let window = resolved
+            .iter()
+            .map(|position| match position {
+                Resolved::Inside(x, y) => grid.get(*x, *y).unwrap().occupancy(),
+                Resolved::Solid(wall) => Occupancy::OccupiedBy(**wall),
+                Resolved::Void | Resolved::Outside => Occupancy::Vacant,
+            })
+            .collect();
+        if !rule
+            .rule
+            .matches(&Grid::from_flat(width, height, window).unwrap())
+        {
+            return None;
+        }
+        let cells: Vec<_> = resolved
+            .iter()
+            .map(|position| match *position {
+                Resolved::Inside(x, y) => Some((x, y)),
+                _ => None,
+            })
+            .collect();
+        if !Self::meets_pressure(grid, rule, &cells) || !Self::displaces_lighter(grid, rule, &cells)
+        {
+            return None;
+        }
+        let overlaps = resolved.iter().any(|position| match *position {
+            Resolved::Inside(x, y) => is_affected(x, y),
+            _ => false,
+        });
+        if overlaps {
+            return None;
+        }
+
+        let output = Self::choose_rule_output(rng, &rule.rule);
+        let inside: Vec<_> = resolved
+            .iter()
+            .zip(rule.rule.input.grid.iter().zip(output.iter()))
+            .filter_map(|(position, (input, output))| match *position {
+                Resolved::Inside(x, y) => Some((x, y, input, output)),
+                _ => None,
+            })
+            .collect();
+        let contents = Self::output_contents(
+            inside
+                .iter()
+                .map(|&(x, y, input, output)| (input, output, grid.get(x, y).unwrap())),
+        );
+        let writes = inside
+            .into_iter()
+            .zip(contents)
+            .map(|((x, y, _, _), content)| (x, y, content))
+            .collect();
+        Some(writes)
+    }
+
+    /// Writes what a rule centred on `(x, y)` changed into the new grid, marks those cells as
+    /// affected and wakes the cells around `(x, y)` for the next step
+    fn apply_writes(
+        grid: &Grid<ParticleCell>,
+        boundary: &BoundaryMode<ParticleKind>,
+        new_grid: &mut Grid<ParticleCell>,
+        next_chunks: &mut Chunks,
+        x: usize,
+        y: usize,
+        writes: Vec<CellWrite>,
+    ) {
+        for (write_x, write_y, content) in writes {
+            new_grid.get_mut(write_x, write_y).unwrap().content = content;
+            next_chunks.mark_affected(write_x, write_y);
+        }
+
+        // Mark cells for next frame's active set
+        for dy in -1..=2 {
+            for dx in -1..=1 {
+                if let Resolved::Inside(nx, ny) =
+                    grid.resolve(x as isize + dx, y as isize + dy, boundary)
+                {
+                    next_chunks.mark_for_next_frame(nx, ny);
+                }
+            }
+        }
+    }
+
+    /// Picks one of the rule's output grids by probability
+    fn choose_rule_output<'a>(
+        rng: &mut ChaCha8Rng,
+        rule: &'a Rule<Occupancy<ParticleKind>>,
+    ) -> &'a Grid<Occupancy<ParticleKind>> {
+        let weighted_index =
+            WeightedIndex::new(rule.output.iter().map(|o| o.probability.value())).unwrap();
+        &rule.output[weighted_index.sample(rng)].grid
+    }
+
+    /// The content of each cell of a window after a rule output has been applied to it, given
+    /// the input, output and current content of each cell. Particles keep their state when a rule
+    /// moves them, so heat travels with them: a cell that keeps its kind keeps its particle, and
+    /// the other particles of the window go to the other cells of their kind, in order. The
+    /// lighter particles of the input go to the cells the output marks as lighter, in order.
+    /// Particles the output makes out of nothing start out in the default state of their kind.
+    fn output_contents<'a>(
+        cells: impl Iterator<
+            Item = (
+                &'a Occupancy<ParticleKind>,
+                &'a Occupancy<ParticleKind>,
+                &'a ParticleCell,
+            ),
+        >,
+    ) -> Vec<Option<Particle>> {
+        let cells: Vec<_> = cells.collect();
+        let keeps = |input: &Occupancy<ParticleKind>,
+                     output: &Occupancy<ParticleKind>,
+                     current: &ParticleCell| match output {
+            Occupancy::Unknown | Occupancy::OccupiedByAny => true,
+            Occupancy::OccupiedBy(_) => current.occupancy() == *output,
+            Occupancy::OccupiedByLighter => matches!(input, Occupancy::OccupiedByLighter),
+            Occupancy::Vacant => false,
+        };
+
+        // Particles whose cell changes, free to move to another cell of the window, along with
+        // whether they were the lighter ones
+        let mut moving: Vec<(bool, Option<&Particle>)> = cells
+            .iter()
+            .filter(|(input, output, current)| !keeps(input, output, current))
+            .map(|(input, _, current)| {
+                (
+                    matches!(input, Occupancy::OccupiedByLighter),
+                    current.content.as_ref(),
+                )
+            })
+            .collect();
+
+        cells
+            .iter()
+            .map(|&(input, output, current)| match output {
+                _ if keeps(input, output, current) => current.content.clone(),
+                Occupancy::OccupiedBy(kind) => {
+                    let particle = moving
+                        .iter_mut()
+                        .find(|(_, particle)| {
+                            particle.is_some_and(|particle| particle.kind == *kind)
+                        })
+                        .and_then(|(_, particle)| particle.take());
+                    Some(particle.cloned().unwrap_or_else(|| Particle::new(*kind)))
+                }
+                Occupancy::OccupiedByLighter => moving
+                    .iter_mut()
+                    .find(|(lighter, particle)| *lighter && particle.is_some())
+                    .and_then(|(_, particle)| particle.take())
+                    .cloned(),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Simulation::new(100, 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cell_particle::rule::parse_rules;
+
+    use super::*;
+    use crate::thermal_energy;
+
+    const RULES: &str = "
+rule sand_fall
+in
+    s
+    .
+out
+    .
+    s
+
+rule sand_slide
+symmetry flip_x
+in
+    s.
+    *.
+out 50%
+    ..
+    *s
+out
+    s.
+    *.
+";
+
+    const WATER_RULES: &str = "
+rule water_fall
+priority 0
+overpressure below 1
+in
+    w
+    .
+out
+    .
+    w
+
+rule water_rise
+priority 0
+overpressure above 2
+in
+    .
+    w
+    *
+out
+    w
+    .
+    *
+
+rule water_spread
+priority 1
+symmetry flip_x
+in
+    w.
+    **
+out
+    .w
+    **
+";
+
+    fn rules() -> Vec<SimulationRule> {
+        parse_rules(RULES)
+            .unwrap()
+            .into_iter()
+            .map(SimulationRule::from)
+            .flat_map(|rule| rule.variants().collect::<Vec<_>>())
+            .collect()
+    }
+
+    fn kinds(simulation: &Simulation) -> Vec<Option<ParticleKind>> {
+        simulation
+            .grid
+            .iter()
+            .map(|cell| cell.content.as_ref().map(|particle| particle.kind))
+            .collect()
+    }
+
+    fn sand_pile(seed: u64) -> Simulation {
+        let mut simulation = Simulation::new(9, 9).with_seed(seed);
+        for y in 0..4 {
+            *simulation.grid.get_mut(4, y).unwrap() = ParticleCell {
+                content: Some(Particle::new(ParticleKind::SAND)),
+            };
+            simulation.chunks.mark_active(4, y);
+        }
+        simulation
+    }
+
+    #[test]
+    fn test_step_moves_sand_down() {
+        let rules = rules();
+        let mut simulation = sand_pile(0);
+        for _ in 0..20 {
+            simulation.step(&rules);
+        }
+
+        let sand = kinds(&simulation)
+            .into_iter()
+            .filter(|kind| *kind == Some(ParticleKind::SAND))
+            .count();
+        assert_eq!(sand, 4);
+        assert_eq!(
+            simulation.grid.get(4, 8).unwrap().occupancy(),
+            Occupancy::OccupiedBy(ParticleKind::SAND)
+        );
+        assert!(simulation.grid.get(4, 0).unwrap().content.is_none());
+    }
+
+    #[test]
+    fn test_same_seed_same_result() {
+        let rules = rules();
+        let run = |seed| {
+            let mut simulation = sand_pile(seed).with_random_particles();
+            for _ in 0..10 {
+                simulation.step(&rules);
+            }
+            kinds(&simulation)
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_overlapping_windows_dont_destroy_particles() {
+        let rules: Vec<SimulationRule> =
+            parse_rules("rule move\nsymmetry flip_x\nin\n    s.\nout\n    .s\n")
+                .unwrap()
+                .into_iter()
+                .map(SimulationRule::from)
+                .flat_map(|rule| rule.variants().collect::<Vec<_>>())
+                .collect();
+
+        for seed in 0..8 {
+            let mut simulation = Simulation::new(3, 1).with_seed(seed);
+            for x in [0, 2] {
+                *simulation.grid.get_mut(x, 0).unwrap() = ParticleCell {
+                    content: Some(Particle::new(ParticleKind::SAND)),
+                };
+            }
+            for x in 0..3 {
+                simulation.chunks.mark_active(x, 0);
+            }
+            simulation.step(&rules);
+
+            let sand = kinds(&simulation)
+                .into_iter()
+                .filter(|kind| *kind == Some(ParticleKind::SAND))
+                .count();
+            assert_eq!(sand, 2);
+        }
+    }
+
+    #[test]
+    fn test_closed_box_conserves_thermal_energy() {
+        let rules = rules();
+        let mut simulation = Simulation::new(10, 10).with_seed(5);
+        for y in 0..10 {
+            for x in 0..10 {
+                let (kind, temperature) = match (x, y) {
+                    (0 | 9, _) | (_, 0 | 9) => (ParticleKind::STONE, 20.0),
+                    (_, 1..=3) => (ParticleKind::SAND, 150.0 + 10.0 * x as f32),
+                    (_, 7..=8) => (ParticleKind::WATER, 5.0 * x as f32),
+                    _ => continue,
+                };
+                let mut particle = Particle::new(kind);
+                particle.state.temperature = temperature;
+                simulation
+                    .set_cell(
+                        x,
+                        y,
+                        ParticleCell {
+                            content: Some(particle),
+                        },
+                    )
+                    .unwrap();
+            }
+        }
+        let spread = |simulation: &Simulation| {
+            let temperatures: Vec<_> = simulation
+                .grid
+                .iter()
+                .filter_map(|cell| cell.content.as_ref())
+                .map(|particle| particle.state.temperature)
+                .collect();
+            let hottest = temperatures.iter().copied().fold(f32::MIN, f32::max);
+            hottest - temperatures.iter().copied().fold(f32::MAX, f32::min)
+        };
+        let energy = thermal_energy(&simulation.grid);
+        let initial_spread = spread(&simulation);
+
+        for _ in 0..50 {
+            simulation.step(&rules);
+            let drift = (thermal_energy(&simulation.grid) - energy).abs();
+            assert!(drift < energy * 1e-5, "thermal energy drifted by {}", drift);
+        }
+        assert!(spread(&simulation) < initial_spread / 2.0);
+        // The sand fell onto the water
+        assert!(simulation.grid.get(5, 1).unwrap().content.is_none());
+        assert_eq!(
+            simulation.grid.get(5, 6).unwrap().occupancy(),
+            Occupancy::OccupiedBy(ParticleKind::SAND)
+        );
+    }
+
+    #[test]
+    fn test_asleep_world_is_left_alone() {
+        let rules = rules();
+        let mut simulation = Simulation::new(100, 100).with_chunk_size(10).with_seed(0);
+        for (x, y, kind, temperature) in [
+            (50, 99, ParticleKind::STONE, 1000.0),
+            (51, 99, ParticleKind::STONE, 0.0),
+            (52, 99, ParticleKind::WATER, 150.0),
+            (20, 20, ParticleKind::SAND, 20.0),
+        ] {
+            let mut particle = Particle::new(kind);
+            particle.state.temperature = temperature;
+            simulation.grid.get_mut(x, y).unwrap().content = Some(particle);
+        }
+        assert_eq!(simulation.chunks.awake().count(), 0);
+        let before = simulation.grid.clone();
+
+        for _ in 0..10 {
+            simulation.step(&rules);
+            simulation.step_parallel(&rules);
+        }
+        assert_eq!(simulation.grid, before);
+        assert_eq!(simulation.chunks.awake().count(), 0);
+    }
+
+    #[test]
+    fn test_water_levels_out_in_a_u_tube() {
+        let rules: Vec<_> = parse_rules(WATER_RULES)
+            .unwrap()
+            .into_iter()
+            .map(SimulationRule::from)
+            .flat_map(|rule| rule.variants().collect::<Vec<_>>())
+            .collect();
+        let world = [
+            "#ww##......#",
+            "#ww##......#",
+            "#ww##......#",
+            "#ww##......#",
+            "#ww##......#",
+            "#ww##......#",
+            "#ww##......#",
+            "#ww........#",
+            "############",
+        ];
+        let mut simulation = Simulation::new(12, 9).with_seed(2);
+        for (y, row) in world.iter().enumerate() {
+            for (x, symbol) in row.chars().enumerate() {
+                let kind = match symbol {
+                    'w' => ParticleKind::WATER,
+                    '#' => ParticleKind::STONE,
+                    _ => continue,
+                };
+                simulation
+                    .set_cell(
+                        x,
+                        y,
+                        ParticleCell {
+                            content: Some(Particle::new(kind)),
+                        },
+                    )
+                    .unwrap();
+            }
+        }
+        for _ in 0..200 {
+            simulation.step(&rules);
+        }
+
+        let water_at = |x: usize, y: usize| {
+            simulation.grid.get(x, y).unwrap().occupancy()
+                == Occupancy::OccupiedBy(ParticleKind::WATER)
+        };
+        let water = (0..12)
+            .flat_map(|x| (0..9).map(move |y| (x, y)))
+            .filter(|&(x, y)| water_at(x, y))
+            .count();
+        assert_eq!(water, 16);
+        // The tube on the left drained into the basin on the right until both were about as high
+        assert!((0..5).all(|y| !water_at(1, y) && !water_at(2, y)));
+        assert!((5..11).filter(|&x| water_at(x, 6)).count() >= 2);
+    }
+
+    #[test]
+    fn test_heavier_particles_sink_through_lighter_ones() {
+        let rules: Vec<_> = parse_rules(
+            "
+rule sand_sink
+in
+    s
+    ~
+out
+    ~
+    s
+
+rule stone_sink
+in
+    #
+    ~
+out
+    ~
+    #
+
+rule water_sink
+in
+    w
+    ~
+out
+    ~
+    w
+",
+        )
+        .unwrap()
+        .into_iter()
+        .map(SimulationRule::from)
+        .collect();
+        let world = [
+            "s#wo", //
+            "wwow", //
+            "wwow", //
+            "wwow", //
+        ];
+        let mut simulation = Simulation::new(4, 4).with_seed(5);
+        for (y, row) in world.iter().enumerate() {
+            for (x, symbol) in row.chars().enumerate() {
+                let kind = match symbol {
+                    's' => ParticleKind::SAND,
+                    '#' => ParticleKind::STONE,
+                    'w' => ParticleKind::WATER,
+                    _ => ParticleKind::OIL,
+                };
+                simulation
+                    .set_cell(
+                        x,
+                        y,
+                        ParticleCell {
+                            content: Some(Particle::new(kind)),
+                        },
+                    )
+                    .unwrap();
+            }
+        }
+        for _ in 0..10 {
+            simulation.step(&rules);
+        }
+
+        let kind_at = |x: usize, y: usize| {
+            simulation
+                .grid
+                .get(x, y)
+                .unwrap()
+                .content
+                .as_ref()
+                .unwrap()
+                .kind
+        };
+        // Sand, stone and water sank to the bottom, the oil on top of the water stayed there
+        assert_eq!(kind_at(0, 3), ParticleKind::SAND);
+        assert_eq!(kind_at(1, 3), ParticleKind::STONE);
+        assert_eq!(kind_at(2, 3), ParticleKind::WATER);
+        assert_eq!(kind_at(3, 0), ParticleKind::OIL);
+        assert!((0..3).all(|y| kind_at(2, y) == ParticleKind::OIL));
+        assert!((1..4).all(|y| kind_at(3, y) == ParticleKind::WATER));
+    }
+
+    #[test]
+    fn test_parallel_step_is_the_same_on_any_number_of_threads() {
+        let rules = rules();
+        let run = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let mut simulation = Simulation::new(16, 16).with_seed(3).with_chunk_size(4);
+                for x in 0..16 {
+                    for y in 0..6 {
+                        *simulation.grid.get_mut(x, y).unwrap() = ParticleCell {
+                            content: Some(Particle::new(ParticleKind::SAND)),
+                        };
+                    }
+                }
+                simulation.chunks.wake_all();
+                for _ in 0..60 {
+                    simulation.step_parallel(&rules);
+                }
+                kinds(&simulation)
+            })
+        };
+
+        let single = run(1);
+        assert_eq!(single, run(4));
+        let sand = single
+            .iter()
+            .filter(|kind| **kind == Some(ParticleKind::SAND))
+            .count();
+        assert_eq!(sand, 16 * 6);
+        assert!(single[..16].iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,504 @@
+//! Versioned binary snapshots of a [`Simulation`].
+//!
+//! All numbers are little endian. A snapshot of version 2 is laid out as follows:
+//!
+//! | Field        | Type           | Description                                                |
+//! |--------------|----------------|------------------------------------------------------------|
+//! | magic        | `[u8; 8]`      | `CELLSNAP`                                                 |
+//! | version      | `u16`          | [`SNAPSHOT_VERSION`]                                       |
+//! | width        | `u32`          | Width of the grid in cells                                 |
+//! | height       | `u32`          | Height of the grid in cells                                |
+//! | resolution   | `u32`          | Pixels per cell the world is shown at                      |
+//! | seed         | `u64`          | Seed of the random number generator                        |
+//! | word pos     | `u128`         | How far the random number generator has come               |
+//! | boundary     | `u8` (+ `u8`)  | 0 skip, 1 wrap, 2 void, 3 solid followed by its kind       |
+//! | runs         | `u32` + runs   | Cells in row-major order, run-length encoded by kind       |
+//! | states       | `u32` + states | Particles whose state differs from the default of the kind |
+//! | chunk size   | `u32`          | Side of a chunk in cells, see [`Chunks`]                   |
+//! | chunks       | 2 x rects      | The dirty and next dirty rectangle of each chunk           |
+//!
+//! Kinds are stored as a `u8`: 0 for an empty cell, otherwise one more than the id of the
+//! material in the [`cell_particle::particle::materials`], 1 sand, 2 water, 3 stone.
+//! Materials registered at runtime have to be registered in the same order to be read back.
+//! A run is a kind followed by a `u32` length.
+//! A state is the `u32` row-major index of the cell followed by the temperature, pressure and
+//! density as `f32`s.
+//! The chunks are in row-major order. A rectangle is a `u8` 0 for none, or 1 followed by the
+//! `u32` x and y of its top left and bottom right cells.
+//!
+//! Version 1 has three sets of active cells instead of the chunks, the current, next and affected
+//! cells. Each set is a `u32` count followed by the `u32` x and y of each cell. Snapshots of
+//! version 1 are read into chunks of [`DEFAULT_CHUNK_SIZE`].
+
+use std::io::{Read, Write};
+
+use cell_particle::{
+    grid::{BoundaryMode, Grid},
+    particle::{materials, Particle, ParticleKind, ParticleState},
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{Chunks, DirtyRect, ParticleCell, Simulation, DEFAULT_CHUNK_SIZE};
+
+/// Magic bytes every snapshot starts with
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"CELLSNAP";
+
+/// The version of the snapshot format written by [`write_snapshot`]
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// The most cells a snapshot may hold, as many as a world of 8192 by 8192 cells. Snapshots
+/// claiming a larger grid are turned down before anything is allocated for them.
+pub const MAX_SNAPSHOT_CELLS: usize = 1 << 26;
+
+/// Error type for reading a snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading from the underlying reader failed, or the snapshot ended early
+    Io(std::io::Error),
+    /// The data doesn't start with [`SNAPSHOT_MAGIC`], so it isn't a snapshot
+    InvalidMagic,
+    /// The snapshot was written in a version of the format this version can't read
+    UnsupportedVersion(u16),
+    /// The grid has no cells
+    EmptyGrid,
+    /// The grid has more than [`MAX_SNAPSHOT_CELLS`] cells
+    GridTooLarge { width: usize, height: usize },
+    /// A kind of particle that doesn't exist
+    InvalidKind(u8),
+    /// A boundary mode that doesn't exist
+    InvalidBoundary(u8),
+    /// The runs of cells don't add up to the size of the grid
+    RunLengthMismatch { expected: usize, found: usize },
+    /// A state or an active cell points outside the grid, or at an empty cell
+    InvalidCell { x: usize, y: usize },
+    /// Chunks can't be empty
+    InvalidChunkSize(u32),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "Could not read snapshot: {}", error),
+            SnapshotError::InvalidMagic => write!(f, "Not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported snapshot version {}, only versions 1 to {} are supported",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::EmptyGrid => write!(f, "Snapshot has an empty grid"),
+            SnapshotError::GridTooLarge { width, height } => write!(
+                f,
+                "Snapshot grid of {}x{} cells is larger than the {} cells allowed",
+                width, height, MAX_SNAPSHOT_CELLS
+            ),
+            SnapshotError::InvalidKind(kind) => write!(f, "Invalid particle kind {}", kind),
+            SnapshotError::InvalidBoundary(boundary) => {
+                write!(f, "Invalid boundary mode {}", boundary)
+            }
+            SnapshotError::RunLengthMismatch { expected, found } => write!(
+                f,
+                "Runs cover {} cells, but the grid has {} cells",
+                found, expected
+            ),
+            SnapshotError::InvalidCell { x, y } => write!(f, "Invalid cell at ({}, {})", x, y),
+            SnapshotError::InvalidChunkSize(size) => write!(f, "Invalid chunk size {}", size),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(error: std::io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+fn kind_to_byte(kind: Option<ParticleKind>) -> u8 {
+    match kind {
+        None => 0,
+        Some(kind) => kind.0 + 1,
+    }
+}
+
+fn kind_from_byte(byte: u8) -> Result<Option<ParticleKind>, SnapshotError> {
+    match byte {
+        0 => Ok(None),
+        _ => {
+            let kind = ParticleKind(byte - 1);
+            match materials().get(kind) {
+                Some(_) => Ok(Some(kind)),
+                None => Err(SnapshotError::InvalidKind(byte)),
+            }
+        }
+    }
+}
+
+/// Writes a snapshot of `simulation`, shown at `resolution` pixels per cell, to `writer`.
+/// See the [module documentation](self) for the format.
+pub fn write_snapshot(
+    writer: &mut impl Write,
+    simulation: &Simulation,
+    resolution: u32,
+) -> std::io::Result<()> {
+    let dimensions = simulation.grid.dimensions();
+    writer.write_all(&SNAPSHOT_MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    writer.write_all(&(dimensions.width as u32).to_le_bytes())?;
+    writer.write_all(&(dimensions.height as u32).to_le_bytes())?;
+    writer.write_all(&resolution.to_le_bytes())?;
+    writer.write_all(&simulation.seed.to_le_bytes())?;
+    writer.write_all(&simulation.rng.get_word_pos().to_le_bytes())?;
+    match simulation.boundary {
+        BoundaryMode::Skip => writer.write_all(&[0])?,
+        BoundaryMode::Wrap => writer.write_all(&[1])?,
+        BoundaryMode::Void => writer.write_all(&[2])?,
+        BoundaryMode::Solid(kind) => writer.write_all(&[3, kind_to_byte(Some(kind))])?,
+    }
+
+    // Run-length encode the kinds
+    let kinds = simulation
+        .grid
+        .iter()
+        .map(|cell| cell.content.as_ref().map(|particle| particle.kind));
+    let mut runs: Vec<(Option<ParticleKind>, u32)> = Vec::new();
+    for kind in kinds {
+        match runs.last_mut() {
+            Some((run_kind, length)) if *run_kind == kind => *length += 1,
+            _ => runs.push((kind, 1)),
+        }
+    }
+    writer.write_all(&(runs.len() as u32).to_le_bytes())?;
+    for (kind, length) in runs {
+        writer.write_all(&[kind_to_byte(kind)])?;
+        writer.write_all(&length.to_le_bytes())?;
+    }
+
+    // Only states that differ from the default of their kind
+    let states: Vec<_> = simulation
+        .grid
+        .iter()
+        .enumerate()
+        .filter_map(|(index, cell)| {
+            let particle = cell.content.as_ref()?;
+            (particle.state != ParticleState::from_kind(particle.kind))
+                .then_some((index, &particle.state))
+        })
+        .collect();
+    writer.write_all(&(states.len() as u32).to_le_bytes())?;
+    for (index, state) in states {
+        writer.write_all(&(index as u32).to_le_bytes())?;
+        writer.write_all(&state.temperature.to_le_bytes())?;
+        writer.write_all(&state.pressure.to_le_bytes())?;
+        writer.write_all(&state.density.to_le_bytes())?;
+    }
+
+    writer.write_all(&(simulation.chunks.size() as u32).to_le_bytes())?;
+    for (_, chunk) in simulation.chunks.iter() {
+        for rect in [chunk.dirty, chunk.next_dirty] {
+            match rect {
+                None => writer.write_all(&[0])?,
+                Some(DirtyRect { min, max }) => {
+                    writer.write_all(&[1])?;
+                    for value in [min.0, min.1, max.0, max.1] {
+                        writer.write_all(&(value as u32).to_le_bytes())?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a [`Simulation`] and the resolution it was shown at from a snapshot written by
+/// [`write_snapshot`]. Fails on any snapshot of a newer version than [`SNAPSHOT_VERSION`].
+pub fn read_snapshot(reader: &mut impl Read) -> Result<(Simulation, u32), SnapshotError> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+    let version = u16::from_le_bytes(read_bytes(reader)?);
+    if !(1..=SNAPSHOT_VERSION).contains(&version) {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let width = read_u32(reader)? as usize;
+    let height = read_u32(reader)? as usize;
+    if width == 0 || height == 0 {
+        return Err(SnapshotError::EmptyGrid);
+    }
+    let resolution = read_u32(reader)?;
+    let seed = u64::from_le_bytes(read_bytes(reader)?);
+    let word_pos = u128::from_le_bytes(read_bytes(reader)?);
+    let boundary = match read_u8(reader)? {
+        0 => BoundaryMode::Skip,
+        1 => BoundaryMode::Wrap,
+        2 => BoundaryMode::Void,
+        3 => match kind_from_byte(read_u8(reader)?)? {
+            Some(kind) => BoundaryMode::Solid(kind),
+            None => return Err(SnapshotError::InvalidKind(0)),
+        },
+        byte => return Err(SnapshotError::InvalidBoundary(byte)),
+    };
+
+    let size = width
+        .checked_mul(height)
+        .filter(|&size| size <= MAX_SNAPSHOT_CELLS)
+        .ok_or(SnapshotError::GridTooLarge { width, height })?;
+
+    // The runs are all read and checked before the cells they stand for are allocated
+    let mut runs = Vec::new();
+    let mut covered: usize = 0;
+    for _ in 0..read_u32(reader)? {
+        let kind = kind_from_byte(read_u8(reader)?)?;
+        let length = read_u32(reader)? as usize;
+        covered = covered.saturating_add(length);
+        if covered > size {
+            return Err(SnapshotError::RunLengthMismatch {
+                expected: size,
+                found: covered,
+            });
+        }
+        if length > 0 {
+            runs.push((kind, length));
+        }
+    }
+    if covered != size {
+        return Err(SnapshotError::RunLengthMismatch {
+            expected: size,
+            found: covered,
+        });
+    }
+    let mut cells = Vec::with_capacity(size);
+    for (kind, length) in runs {
+        let cell = ParticleCell {
+            content: kind.map(Particle::new),
+        };
+        cells.extend(std::iter::repeat_n(cell, length));
+    }
+    let mut grid = Grid::from_flat(width, height, cells).unwrap();
+
+    for _ in 0..read_u32(reader)? {
+        let index = read_u32(reader)? as usize;
+        let state = ParticleState::new(read_f32(reader)?, read_f32(reader)?, read_f32(reader)?);
+        let (x, y) = (index % width, index / width);
+        match grid
+            .get_mut(x, y)
+            .ok()
+            .and_then(|cell| cell.content.as_mut())
+        {
+            Some(particle) => particle.state = state,
+            None => return Err(SnapshotError::InvalidCell { x, y }),
+        }
+    }
+
+    let chunks = if version == 1 {
+        read_active_cells(reader, width, height)?
+    } else {
+        read_chunks(reader, width, height)?
+    };
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_word_pos(word_pos);
+    let simulation = Simulation {
+        grid,
+        chunks,
+        boundary,
+        seed,
+        rng,
+    };
+    Ok((simulation, resolution))
+}
+
+/// Reads the chunks of a snapshot of version 2
+fn read_chunks(
+    reader: &mut impl Read,
+    width: usize,
+    height: usize,
+) -> Result<Chunks, SnapshotError> {
+    let size = read_u32(reader)?;
+    if size == 0 {
+        return Err(SnapshotError::InvalidChunkSize(size));
+    }
+    let mut chunks = Chunks::new(width, height, size as usize);
+    let positions: Vec<_> = chunks.iter().map(|(position, _)| position).collect();
+    for (chunk_x, chunk_y) in positions {
+        let bounds = chunks.bounds(chunk_x, chunk_y).unwrap();
+        for next in [false, true] {
+            if read_u8(reader)? == 0 {
+                continue;
+            }
+            let min = (read_u32(reader)? as usize, read_u32(reader)? as usize);
+            let max = (read_u32(reader)? as usize, read_u32(reader)? as usize);
+            for (x, y) in [min, max] {
+                if !bounds.contains(x, y) {
+                    return Err(SnapshotError::InvalidCell { x, y });
+                }
+            }
+            let rect = DirtyRect { min, max };
+            if next {
+                chunks.mark_rect_for_next_frame(rect);
+            } else {
+                chunks.mark_rect_active(rect);
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+/// Reads the three sets of active cells of a snapshot of version 1 into chunks. The cells
+/// affected by a rule are only ever set in the middle of a step, so that set is dropped.
+fn read_active_cells(
+    reader: &mut impl Read,
+    width: usize,
+    height: usize,
+) -> Result<Chunks, SnapshotError> {
+    let mut chunks = Chunks::new(width, height, DEFAULT_CHUNK_SIZE);
+    for set in 0..3 {
+        for _ in 0..read_u32(reader)? {
+            let (x, y) = (read_u32(reader)? as usize, read_u32(reader)? as usize);
+            if x >= width || y >= height {
+                return Err(SnapshotError::InvalidCell { x, y });
+            }
+            match set {
+                0 => chunks.mark_active(x, y),
+                1 => chunks.mark_for_next_frame(x, y),
+                _ => {}
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], SnapshotError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8, SnapshotError> {
+    Ok(read_bytes::<1>(reader)?[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, SnapshotError> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32, SnapshotError> {
+    Ok(f32::from_le_bytes(read_bytes(reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world() -> Simulation {
+        let mut simulation = Simulation::new(5, 4)
+            .with_seed(42)
+            .with_boundary(BoundaryMode::Solid(ParticleKind::STONE));
+        for (x, y, kind) in [
+            (0, 3, ParticleKind::STONE),
+            (1, 3, ParticleKind::STONE),
+            (2, 1, ParticleKind::SAND),
+            (3, 0, ParticleKind::WATER),
+        ] {
+            simulation.grid.get_mut(x, y).unwrap().content = Some(Particle::new(kind));
+            simulation.chunks.mark_active(x, y);
+        }
+        let water = simulation.grid.get_mut(3, 0).unwrap();
+        water.content.as_mut().unwrap().state.temperature = 80.0;
+        simulation.chunks.mark_for_next_frame(4, 3);
+        simulation
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let simulation = world();
+        let mut bytes = Vec::new();
+        write_snapshot(&mut bytes, &simulation, 12).unwrap();
+
+        let (loaded, resolution) = read_snapshot(&mut bytes.as_slice()).unwrap();
+        assert_eq!(resolution, 12);
+        assert_eq!(loaded.seed(), 42);
+        assert_eq!(loaded.boundary, simulation.boundary);
+        assert_eq!(loaded.grid, simulation.grid);
+        assert_eq!(
+            loaded
+                .grid
+                .iter()
+                .map(|cell| cell.content.as_ref().map(|particle| particle.state.clone()))
+                .collect::<Vec<_>>(),
+            simulation
+                .grid
+                .iter()
+                .map(|cell| cell.content.as_ref().map(|particle| particle.state.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.chunks, simulation.chunks);
+        // Writing it again gives the exact same bytes, random number generator included
+        let mut again = Vec::new();
+        write_snapshot(&mut again, &loaded, 12).unwrap();
+        assert_eq!(again, bytes);
+    }
+
+    #[test]
+    fn test_reads_version_1() {
+        let simulation = world();
+        let mut bytes = Vec::new();
+        write_snapshot(&mut bytes, &simulation, 12).unwrap();
+
+        // Swap the single chunk of version 2 for the sets of active cells of version 1
+        let mut old = bytes[..bytes.len() - 38].to_vec();
+        old[8..10].copy_from_slice(&1u16.to_le_bytes());
+        let sets: [&[(u32, u32)]; 3] = [&[(0, 3), (1, 3), (2, 1), (3, 0)], &[(4, 3)], &[]];
+        for set in sets {
+            old.extend((set.len() as u32).to_le_bytes());
+            for (x, y) in set {
+                old.extend(x.to_le_bytes());
+                old.extend(y.to_le_bytes());
+            }
+        }
+
+        let (loaded, _) = read_snapshot(&mut old.as_slice()).unwrap();
+        assert_eq!(loaded.grid, simulation.grid);
+        assert_eq!(loaded.chunks, simulation.chunks);
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let mut bytes = Vec::new();
+        write_snapshot(&mut bytes, &world(), 10).unwrap();
+
+        let mut future = bytes.clone();
+        future[8..10].copy_from_slice(&7u16.to_le_bytes());
+        assert!(matches!(
+            read_snapshot(&mut future.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(7))
+        ));
+
+        let mut garbage = bytes.clone();
+        garbage[0] = b'X';
+        assert!(matches!(
+            read_snapshot(&mut garbage.as_slice()),
+            Err(SnapshotError::InvalidMagic)
+        ));
+
+        let truncated = &bytes[..bytes.len() - 3];
+        assert!(matches!(
+            read_snapshot(&mut &truncated[..]),
+            Err(SnapshotError::Io(_))
+        ));
+
+        let mut huge = bytes.clone();
+        huge[10..18].copy_from_slice(&[0xff; 8]);
+        assert!(matches!(
+            read_snapshot(&mut huge.as_slice()),
+            Err(SnapshotError::GridTooLarge {
+                width: 0xffff_ffff,
+                height: 0xffff_ffff
+            })
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use cell_particle::{grid::BoundaryMode, particle::ParticleKind};
+use cell_simulation::UnknownColors;
+
+use crate::world::kind_of;
+
+pub const USAGE: &str = "\
+Usage: cells_cli --world <FILE> --rules <FILE> --ticks <N> --output <FILE> [OPTIONS]
+
+Steps a world with a rule set, without a window, and writes the final world.
+
+Options:
+    --world <FILE>           World to start from, one symbol per cell: . s w #
+                             or a .png image in the default palette
+    --rules <FILE>           Rule set to step the world with
+    --ticks <N>              Number of ticks to step
+    --output <FILE>          Where to write the final world, as an image if it ends in .png
+    --seed <N>               Seed of the simulation, random if not given
+    --boundary <MODE>        Edge of the world: skip, wrap, void or solid:<symbol> [default: skip]
+    --stats <FILE>           Where to write per tick stats as CSV [default: stdout]
+    --snapshot-every <N>     Write the world every N ticks
+    --snapshot-dir <DIR>     Where to write the snapshots, in the format of the output
+                             [default: snapshots]
+    --snap-colors            Use the nearest palette colour for unknown colours of a .png world
+    --parallel               Step the world on all threads, in chunks like a checkerboard
+    --help                   Print this message";
+
+/// The command line arguments of the runner
+#[derive(Debug)]
+pub struct Args {
+    pub world: PathBuf,
+    pub rules: PathBuf,
+    pub ticks: usize,
+    pub output: PathBuf,
+    pub seed: Option<u64>,
+    pub boundary: BoundaryMode<ParticleKind>,
+    pub stats: Option<PathBuf>,
+    pub snapshot_every: Option<usize>,
+    pub snapshot_dir: PathBuf,
+    pub unknown_colors: UnknownColors,
+    pub parallel: bool,
+}
+
+/// Error type for invalid command line arguments
+#[derive(Debug)]
+pub enum ArgsError {
+    /// `--help` was given
+    Help,
+    /// An option that isn't known
+    UnknownOption(String),
+    /// An option that needs a value came last
+    MissingValue(&'static str),
+    /// A required option wasn't given
+    MissingOption(&'static str),
+    /// The value of an option couldn't be parsed
+    InvalidValue { option: &'static str, value: String },
+}
+
+impl std::fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "{}", USAGE),
+            ArgsError::UnknownOption(option) => write!(f, "Unknown option '{}'", option),
+            ArgsError::MissingValue(option) => write!(f, "Missing value for {}", option),
+            ArgsError::MissingOption(option) => write!(f, "Missing required option {}", option),
+            ArgsError::InvalidValue { option, value } => {
+                write!(f, "Invalid value '{}' for {}", value, option)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+impl Args {
+    /// Parses the arguments, without the name of the program
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut world = None;
+        let mut rules = None;
+        let mut ticks = None;
+        let mut output = None;
+        let mut seed = None;
+        let mut boundary = BoundaryMode::Skip;
+        let mut stats = None;
+        let mut snapshot_every = None;
+        let mut snapshot_dir = PathBuf::from("snapshots");
+        let mut unknown_colors = UnknownColors::Error;
+        let mut parallel = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let option: &'static str = match arg.as_str() {
+                "--help" | "-h" => return Err(ArgsError::Help),
+                "--snap-colors" => {
+                    unknown_colors = UnknownColors::SnapToNearest;
+                    continue;
+                }
+                "--parallel" => {
+                    parallel = true;
+                    continue;
+                }
+                "--world" => "--world",
+                "--rules" => "--rules",
+                "--ticks" => "--ticks",
+                "--output" => "--output",
+                "--seed" => "--seed",
+                "--boundary" => "--boundary",
+                "--stats" => "--stats",
+                "--snapshot-every" => "--snapshot-every",
+                "--snapshot-dir" => "--snapshot-dir",
+                _ => return Err(ArgsError::UnknownOption(arg)),
+            };
+            let value = args.next().ok_or(ArgsError::MissingValue(option))?;
+            let invalid = |value: &str| ArgsError::InvalidValue {
+                option,
+                value: value.to_string(),
+            };
+
+            match option {
+                "--world" => world = Some(PathBuf::from(value)),
+                "--rules" => rules = Some(PathBuf::from(value)),
+                "--ticks" => ticks = Some(value.parse().map_err(|_| invalid(&value))?),
+                "--output" => output = Some(PathBuf::from(value)),
+                "--seed" => seed = Some(value.parse().map_err(|_| invalid(&value))?),
+                "--boundary" => boundary = parse_boundary(&value).ok_or_else(|| invalid(&value))?,
+                "--stats" => stats = Some(PathBuf::from(value)),
+                "--snapshot-every" => {
+                    let every: usize = value.parse().map_err(|_| invalid(&value))?;
+                    if every == 0 {
+                        return Err(invalid(&value));
+                    }
+                    snapshot_every = Some(every);
+                }
+                "--snapshot-dir" => snapshot_dir = PathBuf::from(value),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(Args {
+            world: world.ok_or(ArgsError::MissingOption("--world"))?,
+            rules: rules.ok_or(ArgsError::MissingOption("--rules"))?,
+            ticks: ticks.ok_or(ArgsError::MissingOption("--ticks"))?,
+            output: output.ok_or(ArgsError::MissingOption("--output"))?,
+            seed,
+            boundary,
+            stats,
+            snapshot_every,
+            snapshot_dir,
+            unknown_colors,
+            parallel,
+        })
+    }
+}
+
+/// Parses `skip`, `wrap`, `void` or `solid:<symbol>`
+fn parse_boundary(value: &str) -> Option<BoundaryMode<ParticleKind>> {
+    match value {
+        "skip" => Some(BoundaryMode::Skip),
+        "wrap" => Some(BoundaryMode::Wrap),
+        "void" => Some(BoundaryMode::Void),
+        _ => {
+            let mut symbols = value.strip_prefix("solid:")?.chars();
+            let kind = kind_of(symbols.next()?)?;
+            symbols
+                .next()
+                .is_none()
+                .then_some(BoundaryMode::Solid(kind))
+        }
+    }
+}
@@ -0,0 +1,214 @@
+mod args;
+mod world;
+
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use cell_particle::{grid::Grid, particle::materials, rule::parse_rules, rule::ParseError};
+use cell_simulation::{
+    export_png, import_png, ImageError, Palette, ParticleCell, Simulation, SimulationRule,
+    UnknownColors,
+};
+
+use args::{Args, ArgsError, USAGE};
+use world::{format_world, parse_world, WorldError};
+
+/// Error type for a run of the simulation
+#[derive(Debug)]
+enum RunError {
+    /// A file could not be read or written
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The rule set is invalid
+    Rules { path: PathBuf, error: ParseError },
+    /// The world is invalid
+    World { path: PathBuf, error: WorldError },
+    /// The world could not be imported from or exported to an image
+    Image { path: PathBuf, error: ImageError },
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            RunError::Rules { path, error } => write!(f, "{}:{}", path.display(), error),
+            RunError::World { path, error } => write!(f, "{}:{}", path.display(), error),
+            RunError::Image { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(ArgsError::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String, RunError> {
+    fs::read_to_string(path).map_err(|error| RunError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "png")
+}
+
+/// Reads a world from a world file, or from an image if the path ends in `.png`
+fn read_world(path: &Path, unknown_colors: UnknownColors) -> Result<Grid<ParticleCell>, RunError> {
+    if is_png(path) {
+        let file = fs::File::open(path).map_err(|error| RunError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        import_png(BufReader::new(file), &Palette::default(), unknown_colors).map_err(|error| {
+            RunError::Image {
+                path: path.to_path_buf(),
+                error,
+            }
+        })
+    } else {
+        parse_world(&read(path)?).map_err(|error| RunError::World {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+/// Writes a world to a world file, or to an image if the path ends in `.png`
+fn write_world(path: &Path, grid: &Grid<ParticleCell>) -> Result<(), RunError> {
+    if is_png(path) {
+        let file = fs::File::create(path).map_err(|error| RunError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        export_png(BufWriter::new(file), grid, &Palette::default()).map_err(|error| {
+            RunError::Image {
+                path: path.to_path_buf(),
+                error,
+            }
+        })
+    } else {
+        fs::write(path, format_world(grid)).map_err(|error| RunError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+/// Steps the world for the given number of ticks, writing stats and snapshots along the way
+fn run(args: &Args) -> Result<(), RunError> {
+    let grid = read_world(&args.world, args.unknown_colors)?;
+    let mut simulation = Simulation::from_grid(grid).with_boundary(args.boundary);
+    if let Some(seed) = args.seed {
+        simulation = simulation.with_seed(seed);
+    }
+    eprintln!("Seed: {}", simulation.seed());
+
+    // Expand the rules, remembering which definition each variant came from, so rules fired can
+    // be reported by name
+    let definitions = parse_rules(&read(&args.rules)?).map_err(|error| RunError::Rules {
+        path: args.rules.clone(),
+        error,
+    })?;
+    let mut rules = Vec::new();
+    let mut definition_of = Vec::new();
+    for (index, definition) in definitions.iter().enumerate() {
+        for variant in SimulationRule::from(definition.clone()).variants() {
+            rules.push(variant);
+            definition_of.push(index);
+        }
+    }
+
+    let stats_error = |error| RunError::Io {
+        path: args
+            .stats
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("stdout")),
+        error,
+    };
+    let mut stats: Box<dyn Write> = match &args.stats {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path).map_err(stats_error)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let kinds: Vec<_> = materials().kinds().collect();
+    let header: Vec<_> = ["tick".to_string(), "active_cells".to_string()]
+        .into_iter()
+        .chain(kinds.iter().map(|kind| kind.to_string()))
+        .chain(definitions.iter().map(|definition| definition.name.clone()))
+        .collect();
+    writeln!(stats, "{}", header.join(",")).map_err(stats_error)?;
+
+    if args.snapshot_every.is_some() {
+        fs::create_dir_all(&args.snapshot_dir).map_err(|error| RunError::Io {
+            path: args.snapshot_dir.clone(),
+            error,
+        })?;
+    }
+
+    for tick in 1..=args.ticks {
+        let report = if args.parallel {
+            simulation.step_parallel(&rules)
+        } else {
+            simulation.step(&rules)
+        };
+
+        let mut particle_counts = vec![0; kinds.len()];
+        for particle in simulation
+            .grid
+            .iter()
+            .filter_map(|cell| cell.content.as_ref())
+        {
+            let kind = kinds
+                .iter()
+                .position(|kind| *kind == particle.kind)
+                .unwrap();
+            particle_counts[kind] += 1;
+        }
+        let mut rules_fired = vec![0; definitions.len()];
+        for (variant, fired) in report.rules_fired.iter().enumerate() {
+            rules_fired[definition_of[variant]] += fired;
+        }
+        let row: Vec<_> = [tick, simulation.chunks.active_cell_count()]
+            .into_iter()
+            .chain(particle_counts)
+            .chain(rules_fired)
+            .map(|value| value.to_string())
+            .collect();
+        writeln!(stats, "{}", row.join(",")).map_err(stats_error)?;
+
+        if args.snapshot_every.is_some_and(|every| tick % every == 0) {
+            let extension = if is_png(&args.output) { "png" } else { "world" };
+            let path = args
+                .snapshot_dir
+                .join(format!("tick_{:06}.{}", tick, extension));
+            write_world(&path, &simulation.grid)?;
+        }
+    }
+    stats.flush().map_err(stats_error)?;
+
+    write_world(&args.output, &simulation.grid)
+}
@@ -0,0 +1,143 @@
+use cell_particle::{
+    grid::Grid,
+    particle::{materials, Particle, ParticleKind},
+};
+use cell_simulation::ParticleCell;
+
+/// The symbol of an empty cell in a world file, every other cell is the symbol of its material.
+/// These match the default legend of the rule format.
+const EMPTY: char = '.';
+
+/// Written for materials without a symbol, which can't be read back
+const NO_SYMBOL: char = '?';
+
+/// Error type for parsing a world file, pointing at the offending line and column
+#[derive(Debug)]
+pub enum WorldError {
+    /// The file doesn't contain any rows
+    Empty,
+    /// A symbol that is not in the legend
+    UnknownSymbol {
+        line: usize,
+        column: usize,
+        symbol: char,
+    },
+    /// A row of a different length than the first one
+    UnequalRowLengths { line: usize },
+}
+
+impl std::fmt::Display for WorldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorldError::Empty => write!(f, "The world is empty"),
+            WorldError::UnknownSymbol {
+                line,
+                column,
+                symbol,
+            } => write!(f, "{}:{}: Unknown symbol '{}'", line, column, symbol),
+            WorldError::UnequalRowLengths { line } => {
+                write!(f, "{}: Row length differs from the first row", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
+
+/// The kind of particle a symbol stands for, [`None`] if it isn't one
+pub fn kind_of(symbol: char) -> Option<ParticleKind> {
+    materials().by_symbol(symbol)
+}
+
+/// The symbol of a kind of particle, [`None`] if its material doesn't have one
+pub fn symbol_of(kind: ParticleKind) -> Option<char> {
+    materials().get(kind)?.symbol
+}
+
+/// Parses a world, one row of cells per line, one symbol per cell.
+/// Blank lines and lines starting with `//` are skipped.
+pub fn parse_world(source: &str) -> Result<Grid<ParticleCell>, WorldError> {
+    let mut width = None;
+    let mut cells = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        let row_length = line.chars().count();
+        if *width.get_or_insert(row_length) != row_length {
+            return Err(WorldError::UnequalRowLengths { line: line_number });
+        }
+        for (column, symbol) in line.chars().enumerate() {
+            let content = match symbol {
+                EMPTY => None,
+                symbol => match kind_of(symbol) {
+                    Some(kind) => Some(Particle::new(kind)),
+                    None => {
+                        return Err(WorldError::UnknownSymbol {
+                            line: line_number,
+                            column: column + 1,
+                            symbol,
+                        })
+                    }
+                },
+            };
+            cells.push(ParticleCell { content });
+        }
+    }
+
+    let width = width.ok_or(WorldError::Empty)?;
+    let height = cells.len() / width;
+    Ok(Grid::from_flat(width, height, cells).unwrap())
+}
+
+/// Formats a world the way [`parse_world`] reads it, as long as every material has a symbol
+pub fn format_world(grid: &Grid<ParticleCell>) -> String {
+    let mut world = String::with_capacity(grid.as_slice().len() + grid.dimensions().height);
+    for row in grid.rows() {
+        for cell in row {
+            world.push(match &cell.content {
+                Some(particle) => symbol_of(particle.kind).unwrap_or(NO_SYMBOL),
+                None => EMPTY,
+            });
+        }
+        world.push('\n');
+    }
+    world
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_world_round_trip() {
+        let source = "// A small world\n..s.\n\nw.#.\n";
+        let grid = parse_world(source).unwrap();
+        assert_eq!(grid.dimensions().width, 4);
+        assert_eq!(grid.dimensions().height, 2);
+        assert_eq!(format_world(&grid), "..s.\nw.#.\n");
+    }
+
+    #[test]
+    fn test_world_errors() {
+        assert!(matches!(
+            parse_world("// nothing\n"),
+            Err(WorldError::Empty)
+        ));
+        assert!(matches!(
+            parse_world("..\n.x\n"),
+            Err(WorldError::UnknownSymbol {
+                line: 2,
+                column: 2,
+                symbol: 'x'
+            })
+        ));
+        assert!(matches!(
+            parse_world("..\n...\n"),
+            Err(WorldError::UnequalRowLengths { line: 2 })
+        ));
+    }
+}
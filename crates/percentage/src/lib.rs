@@ -82,6 +82,56 @@ impl std::fmt::Display for Percentage {
     }
 }
 
+/// Error type for parsing a [`Percentage`] that isn't a number between 0% and 100%
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePercentageError(pub String);
+
+impl std::fmt::Display for ParsePercentageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` is not a percentage between 0% and 100%", self.0)
+    }
+}
+
+impl std::error::Error for ParsePercentageError {}
+
+impl std::str::FromStr for Percentage {
+    type Err = ParsePercentageError;
+
+    /// Parses a percentage written either as a percent, `25%`, or as a fraction, `0.25`.
+    /// Unlike [`Percentage::new`], values outside of the range are an error rather than clamped.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, scale) = match s.strip_suffix('%') {
+            Some(percent) => (percent, 100.0),
+            None => (s, 1.0),
+        };
+        let value = number
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| ParsePercentageError(s.to_string()))?
+            / scale;
+        if !(0.0..=1.0).contains(&value) {
+            return Err(ParsePercentageError(s.to_string()));
+        }
+        Ok(Percentage::new(value))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Percentage {
+    /// Serialised the same way it is displayed, e.g. `25%`
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Percentage {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let percentage = String::deserialize(deserializer)?;
+        percentage.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,6 +143,26 @@ mod tests {
         assert!((p.as_percent() - 50.0).abs() < EPSILON);
     }
 
+    #[test]
+    fn test_parse() {
+        assert_eq!("25%".parse(), Ok(Percentage::new(0.25)));
+        assert_eq!("0.25".parse(), Ok(Percentage::new(0.25)));
+        assert_eq!(
+            Percentage::new(0.125).to_string().parse(),
+            Ok(Percentage::new(0.125))
+        );
+        assert!("150%".parse::<Percentage>().is_err());
+        assert!("half".parse::<Percentage>().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let p = Percentage::new(0.75);
+        assert_eq!(serde_json::to_string(&p).unwrap(), "\"75%\"");
+        assert_eq!(serde_json::from_str::<Percentage>("\"75%\"").unwrap(), p);
+    }
+
     #[test]
     fn test_from_percent() {
         let p = Percentage::from_percent(75.0);
@@ -184,11 +254,11 @@ mod tests {
 
     #[test]
     fn test_percentage_multiplication() {
-        let p1 = Percentage::new(0.5);  // 50%
-        let p2 = Percentage::new(0.6);  // 60%
+        let p1 = Percentage::new(0.5); // 50%
+        let p2 = Percentage::new(0.6); // 60%
         let result = p1 * p2;
-        assert!((result.value() - 0.3).abs() < EPSILON);  // 50% of 60% = 30%
-        
+        assert!((result.value() - 0.3).abs() < EPSILON); // 50% of 60% = 30%
+
         // Test commutative property
         let result2 = p2 * p1;
         assert!((result2.value() - 0.3).abs() < EPSILON);